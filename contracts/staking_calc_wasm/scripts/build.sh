@@ -0,0 +1,5 @@
+#!/bin/bash
+# builds the npm package into ./pkg, client/ depends on it as a local package
+cd "`dirname $0`"
+cd ..
+wasm-pack build --target web --release --out-dir pkg
@@ -0,0 +1,406 @@
+/**
+ * @notice
+ * Reward calculator of the staking contract for the web client, built with wasm-pack.
+ * The math comes from staking_types, the same code the contract runs, so the client cannot drift from it.
+ * Amounts are passed as strings like the U128 of the contract, timestamps and durations in ms
+ */
+use near_sdk::serde::Deserialize;
+use near_sdk::serde_json;
+use near_sdk::Balance;
+use staking_types::*;
+use wasm_bindgen::prelude::*;
+
+fn parse_json<T: near_sdk::serde::de::DeserializeOwned>(
+    json: &str,
+    what: &str,
+) -> Result<T, String> {
+    serde_json::from_str(json).map_err(|err| format!("Invalid {}: {}", what, err))
+}
+
+fn parse_balance(amount: &str) -> Result<Balance, String> {
+    amount
+        .parse()
+        .map_err(|_| format!("Invalid amount {:?}", amount))
+}
+
+// JS numbers are f64, timestamps in ms fit them exactly
+fn to_ms(timestamp: f64) -> u64 {
+    timestamp.max(0.0) as u64
+}
+
+// the fields of get_solvency the rewards depend on
+#[derive(Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+struct Solvency {
+    paused_since: Option<u64>,
+    #[serde(default)]
+    pauses: Vec<(u64, u64)>,
+}
+
+// get_token_status
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TokenStatus {
+    status: String,
+    since: u64,
+}
+
+// JSON of an optional view, missing or null when the contract returned nothing
+fn parse_optional<T: near_sdk::serde::de::DeserializeOwned>(
+    json: Option<&str>,
+    what: &str,
+) -> Result<Option<T>, String> {
+    match json {
+        Some(json) => parse_json(json, what),
+        None => Ok(None),
+    }
+}
+
+pub fn claimable_of(
+    stake_json: &str,
+    config_json: &str,
+    now_ms: u64,
+    boost_bps: u16,
+    solvency_json: Option<&str>,
+    token_status_json: Option<&str>,
+) -> Result<Balance, String> {
+    let stake: Stake = parse_json(stake_json, "stake")?;
+    let config: Config = parse_json(config_json, "config")?;
+    let solvency: Solvency = parse_optional(solvency_json, "solvency")?.unwrap_or_default();
+    let retired_since = parse_optional::<TokenStatus>(token_status_json, "token status")?
+        .filter(|token| token.status == "retired")
+        .map(|token| token.since);
+    let end = accrual_end(
+        &solvency.pauses,
+        solvency.paused_since,
+        stake.since,
+        reward_end(now_ms, retired_since),
+    );
+    Ok(stake_reward(&stake, end, &config, boost_bps))
+}
+
+pub fn projected_reward_of(
+    amount: &str,
+    config_json: &str,
+    duration_ms: u64,
+    boost_bps: u16,
+) -> Result<Balance, String> {
+    let config: Config = parse_json(config_json, "config")?;
+    Ok(project_reward(
+        parse_balance(amount)?,
+        duration_ms,
+        &config,
+        boost_bps,
+    ))
+}
+
+pub fn apr_bps_of(config_json: &str, boost_bps: u16) -> Result<u32, String> {
+    let config: Config = parse_json(config_json, "config")?;
+    u32::try_from(apr_bps(&config, boost_bps)).map_err(|_| "APR is too large".to_string())
+}
+
+/**
+ * @notice
+ * claimableReward is what calculate_stake_reward of the contract returns for a stake of has_stake at nowMs.
+ * configJson is the output of get_config, solvencyJson and tokenStatusJson the outputs of get_solvency and
+ * get_token_status for the token of the stake, they hold the reward pauses and the retirement of the token
+ */
+#[wasm_bindgen(js_name = claimableReward)]
+pub fn claimable_reward(
+    stake_json: &str,
+    config_json: &str,
+    now_ms: f64,
+    boost_bps: u16,
+    solvency_json: Option<String>,
+    token_status_json: Option<String>,
+) -> Result<String, JsError> {
+    claimable_of(
+        stake_json,
+        config_json,
+        to_ms(now_ms),
+        boost_bps,
+        solvency_json.as_deref(),
+        token_status_json.as_deref(),
+    )
+    .map(|reward| reward.to_string())
+    .map_err(|err| JsError::new(&err))
+}
+
+/**
+ * @notice
+ * projectReward is the reward amount would earn when staked for durationMs
+ */
+#[wasm_bindgen(js_name = projectReward)]
+pub fn project_reward_js(
+    amount: &str,
+    config_json: &str,
+    duration_ms: f64,
+    boost_bps: u16,
+) -> Result<String, JsError> {
+    projected_reward_of(amount, config_json, to_ms(duration_ms), boost_bps)
+        .map(|reward| reward.to_string())
+        .map_err(|err| JsError::new(&err))
+}
+
+/**
+ * @notice
+ * aprBps is the yearly reward rate in basis points
+ */
+#[wasm_bindgen(js_name = aprBps)]
+pub fn apr_bps_js(config_json: &str, boost_bps: u16) -> Result<u32, JsError> {
+    apr_bps_of(config_json, boost_bps).map_err(|err| JsError::new(&err))
+}
+
+/**
+ * @notice
+ * toBaseUnits converts "1.5" into the smallest unit of a token, e.g. with 12 or 24 decimals
+ */
+#[wasm_bindgen(js_name = toBaseUnits)]
+pub fn to_base_units(amount: &str, decimals: u32) -> Result<String, JsError> {
+    parse_amount(amount, decimals)
+        .map(|amount| amount.to_string())
+        .map_err(|err| JsError::new(&err))
+}
+
+#[wasm_bindgen(js_name = fromBaseUnits)]
+pub fn from_base_units(amount: &str, decimals: u32) -> Result<String, JsError> {
+    parse_balance(amount)
+        .map(|amount| format_amount(amount, decimals))
+        .map_err(|err| JsError::new(&err))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, AccountId, PromiseResult, RuntimeFeesConfig, VMConfig};
+    use staking_contract::Stakeable;
+
+    use super::*;
+
+    const ONE_NTK: Balance = 1_000_000_000_000;
+
+    fn stake(amount: Balance, since: u64, claimable: Balance) -> Stake {
+        Stake {
+            address: accounts(2),
+            token_id: "token.testnet".parse::<AccountId>().unwrap(),
+            amount: U128(amount),
+            since,
+            claimable: U128(claimable),
+            locked_until: 0,
+        }
+    }
+
+    fn contract_at(timestamp: u64) -> Stakeable {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(timestamp * 1_000_000)
+            .build());
+        Stakeable::new(accounts(1))
+    }
+
+    #[test]
+    fn test_claimable_matches_contract() {
+        let nft: AccountId = "nft.testnet".parse().unwrap();
+        let fees = FeeConfig {
+            deposit_fee_bps: 100,
+            withdraw_fee_bps: 50,
+            reward_fee_bps: 1_000,
+            treasury_id: None,
+        };
+        // boost_bps of the stakeholder, whether fees are set
+        for (amount, since, now, claimable, boost_bps, with_fees) in [
+            (ONE_NTK, 0, 0, 0, 0, false),
+            (ONE_NTK, 0, ONE_HOUR, 0, 0, false),
+            (
+                3_000 * ONE_NTK,
+                1_656_996_479_907,
+                1_657_006_788_911,
+                0,
+                0,
+                false,
+            ),
+            (
+                7 * ONE_NTK + 13,
+                ONE_HOUR / 3,
+                40 * ONE_HOUR + 17,
+                12_345,
+                0,
+                false,
+            ),
+            (10u128.pow(24), 5, ONE_YEAR, 0, 0, false),
+            (
+                7 * ONE_NTK + 13,
+                ONE_HOUR / 3,
+                40 * ONE_HOUR + 17,
+                12_345,
+                1_000,
+                false,
+            ),
+            (ONE_NTK, 0, ONE_HOUR, 0, 0, true),
+            (3 * ONE_NTK, ONE_HOUR, 9 * ONE_HOUR, 5, 2_500, true),
+        ] {
+            // the config is set before the stake, rate changes after since are history claimable_of does not see
+            let mut contract = contract_at(0);
+            if with_fees {
+                contract.set_fee_config(fees.clone());
+            }
+            if boost_bps > 0 {
+                contract.set_boost_config(Some(nft.clone()), boost_bps, BoostMode::Escrow);
+                call_at(accounts(2), now, 10u128.pow(24), vec![]);
+                contract.storage_deposit(None, None);
+                call_at(nft.clone(), now, 0, vec![]);
+                contract.nft_on_transfer(accounts(2), accounts(2), "1".to_string(), "".to_string());
+            }
+            call_at(accounts(2), now, 0, vec![]);
+            let stake = stake(amount, since, claimable);
+            let expected = contract.calculate_stake_reward(stake.clone()).0;
+            let config_json = serde_json::to_string(contract.get_config()).unwrap();
+            let stake_json = serde_json::to_string(&stake).unwrap();
+            assert_eq!(
+                claimable_of(&stake_json, &config_json, now, boost_bps, None, None),
+                Ok(expected)
+            );
+        }
+    }
+
+    fn token() -> AccountId {
+        "token.testnet".parse().unwrap()
+    }
+
+    // a call of predecessor at timestamp in ms, results are the promise results of a callback
+    fn call_at(
+        predecessor: AccountId,
+        timestamp: u64,
+        deposit: Balance,
+        results: Vec<PromiseResult>,
+    ) {
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(predecessor)
+                .attached_deposit(deposit)
+                .block_timestamp(timestamp * 1_000_000)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results
+        );
+    }
+
+    // reconcile of the token with the contract holding balance
+    fn reconcile_at(contract: &mut Stakeable, timestamp: u64, balance: Balance) {
+        call_at(accounts(1), timestamp, 0, vec![]);
+        contract.reconcile(token());
+        let balance = format!("\"{}\"", balance).into_bytes();
+        call_at(
+            accounts(0),
+            timestamp,
+            0,
+            vec![PromiseResult::Successful(balance)],
+        );
+        contract.on_balance_reconciled(token());
+    }
+
+    fn assert_claimable_matches(contract: &Stakeable, stakes: &[Stake], now: u64) {
+        call_at(accounts(2), now, 0, vec![]);
+        let config_json = serde_json::to_string(contract.get_config()).unwrap();
+        let solvency_json = serde_json::to_string(&contract.get_solvency(token())).unwrap();
+        let status_json = serde_json::to_string(&contract.get_token_status(token())).unwrap();
+        for stake in stakes {
+            let expected = contract.calculate_stake_reward(stake.clone()).0;
+            let stake_json = serde_json::to_string(stake).unwrap();
+            assert_eq!(
+                claimable_of(
+                    &stake_json,
+                    &config_json,
+                    now,
+                    0,
+                    Some(&solvency_json),
+                    Some(&status_json)
+                ),
+                Ok(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_claimable_matches_contract_with_pauses_and_retirement() {
+        let mut contract = contract_at(0);
+        call_at(accounts(1), 0, 0, vec![]);
+        contract.allow_token(token());
+        let metadata = serde_json::json!({
+            "spec": "ft-1.0.0", "name": "Nolan Token", "symbol": "NTK",
+            "icon": null, "reference": null, "reference_hash": null, "decimals": 12,
+        });
+        call_at(
+            accounts(0),
+            0,
+            0,
+            vec![PromiseResult::Successful(metadata.to_string().into_bytes())],
+        );
+        assert!(contract.on_token_metadata(token()));
+        call_at(accounts(2), 0, 10u128.pow(24), vec![]);
+        contract.storage_deposit(None, None);
+        call_at(token(), 0, 0, vec![]);
+        contract.ft_on_transfer(accounts(2), U128(ONE_NTK), "staking".to_string());
+
+        let stakes = [
+            stake(ONE_NTK, 0, 0),
+            stake(ONE_NTK, 2 * ONE_HOUR, 17),
+            stake(5 * ONE_NTK, 5 * ONE_HOUR, 0),
+        ];
+        // paused from 1 to 3 hours and again from 4 hours
+        reconcile_at(&mut contract, ONE_HOUR, 0);
+        reconcile_at(&mut contract, 3 * ONE_HOUR, 100 * ONE_NTK);
+        reconcile_at(&mut contract, 4 * ONE_HOUR, 0);
+        for now in [2 * ONE_HOUR, 4 * ONE_HOUR, 6 * ONE_HOUR] {
+            assert_claimable_matches(&contract, &stakes, now);
+        }
+        reconcile_at(&mut contract, 6 * ONE_HOUR, 100 * ONE_NTK);
+        assert_claimable_matches(&contract, &stakes, 7 * ONE_HOUR);
+        // retired at 8 hours
+        call_at(accounts(1), 8 * ONE_HOUR, 0, vec![]);
+        contract.retire_token(token());
+        for now in [8 * ONE_HOUR, 10 * ONE_HOUR] {
+            assert_claimable_matches(&contract, &stakes, now);
+        }
+        // 4 active hours: 0 to 1, 3 to 4 and 6 to 8
+        assert_eq!(
+            contract.calculate_stake_reward(stakes[0].clone()).0,
+            stake_reward(&stakes[0], 4 * ONE_HOUR, contract.get_config(), 0)
+        );
+    }
+
+    #[test]
+    fn test_project_reward() {
+        let config_json = serde_json::to_string(&Config::default()).unwrap();
+        assert_eq!(
+            projected_reward_of("1000000", &config_json, ONE_HOUR, 0),
+            Ok(15_000)
+        );
+        assert_eq!(
+            projected_reward_of("1000000", &config_json, ONE_HOUR, 10_000),
+            Ok(30_000)
+        );
+        assert!(projected_reward_of("1.5", &config_json, ONE_HOUR, 0).is_err());
+    }
+
+    #[test]
+    fn test_apr_bps() {
+        let config_json = serde_json::to_string(&Config::default()).unwrap();
+        assert_eq!(apr_bps_of(&config_json, 0), Ok(1_314_000));
+        assert!(apr_bps_of("{}", 0).is_err());
+    }
+
+    #[test]
+    fn test_units_round_trip() {
+        for decimals in [12, 24] {
+            let amount = parse_amount("12.345", decimals).unwrap();
+            assert_eq!(format_amount(amount, decimals), "12.345");
+        }
+        assert_eq!(parse_amount("1", 12), Ok(ONE_NTK));
+    }
+}
@@ -0,0 +1,347 @@
+/**
+ * @notice
+ * Offline admin tool for the staking contract: builds ready-to-sign call payloads and simulates rewards.
+ * Nothing is sent to the network, the inputs are checked against the contract types before anything is printed
+ */
+use clap::{Parser, Subcommand};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::{json, Value};
+use near_sdk::{AccountId, Balance};
+use staking_types::*;
+
+mod payload;
+mod simulate;
+use crate::payload::*;
+use crate::simulate::*;
+
+#[derive(Parser)]
+#[command(
+    name = "staking-cli",
+    about = "Build staking contract calls and simulate rewards offline"
+)]
+struct Cli {
+    /// Print a near-cli command signed by this account instead of json
+    #[arg(long, global = true)]
+    near_cli: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Initialize the staking contract
+    New {
+        #[arg(long)]
+        contract_id: String,
+        #[arg(long)]
+        owner_id: String,
+    },
+    /// Allow a token to be staked
+    AllowToken {
+        #[arg(long)]
+        contract_id: String,
+        #[arg(long)]
+        token_id: String,
+    },
+    /// Stake through ft_transfer_call on the token contract
+    Stake {
+        #[arg(long)]
+        contract_id: String,
+        #[arg(long)]
+        token_id: String,
+        /// Human readable amount, e.g. 1.5
+        #[arg(long)]
+        amount: String,
+        /// Decimals of the token, from its ft_metadata
+        #[arg(long)]
+        decimals: u32,
+        #[arg(long)]
+        referrer_id: Option<String>,
+    },
+    /// Withdraw from one stake
+    WithdrawStake {
+        #[arg(long)]
+        contract_id: String,
+        /// Human readable amount, e.g. 1.5
+        #[arg(long)]
+        amount: String,
+        /// Decimals of the token, from its ft_metadata
+        #[arg(long)]
+        decimals: u32,
+        #[arg(long)]
+        stake_index: usize,
+        /// NEAR added to the storage balance of the signer, e.g. 0.01
+        #[arg(long, default_value = "0")]
+        storage_deposit: String,
+    },
+    /// Work out the reward of a stake offline
+    Simulate {
+        /// Human readable amount, e.g. 1.5
+        #[arg(long)]
+        amount: String,
+        /// Decimals of the token, from its ft_metadata
+        #[arg(long)]
+        decimals: u32,
+        /// How long the stake is kept, e.g. 90m, 36h, 30d, 1y
+        #[arg(long)]
+        duration: String,
+        /// File with the output of get_config, the default config when not given
+        #[arg(long)]
+        config: Option<String>,
+        #[arg(long, default_value_t = 0)]
+        boost_bps: u16,
+    },
+}
+
+/**
+ * @notice
+ * parse_duration reads durations like 90m, 36h or 30d into ms
+ */
+pub fn parse_duration(duration: &str) -> Result<u64, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("Invalid duration {:?}", duration))?;
+    let unit_ms = match unit {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" | "" => ONE_HOUR,
+        "d" => 24 * ONE_HOUR,
+        "y" => ONE_YEAR,
+        _ => {
+            return Err(format!(
+                "Unknown duration unit {:?}, use ms, s, m, h, d or y",
+                unit
+            ))
+        }
+    };
+    value
+        .checked_mul(unit_ms)
+        .ok_or_else(|| format!("Duration {} is too long", duration))
+}
+
+fn run(cli: Cli) -> Result<String, String> {
+    let payload = match cli.command {
+        Command::New {
+            contract_id,
+            owner_id,
+        } => new_payload(
+            parse_account_id(&contract_id)?,
+            parse_account_id(&owner_id)?,
+        ),
+        Command::AllowToken {
+            contract_id,
+            token_id,
+        } => allow_token_payload(
+            parse_account_id(&contract_id)?,
+            parse_account_id(&token_id)?,
+        ),
+        Command::Stake {
+            contract_id,
+            token_id,
+            amount,
+            decimals,
+            referrer_id,
+        } => stake_payload(
+            parse_account_id(&contract_id)?,
+            parse_account_id(&token_id)?,
+            parse_token_amount(&amount, decimals)?,
+            referrer_id.as_deref().map(parse_account_id).transpose()?,
+        ),
+        Command::WithdrawStake {
+            contract_id,
+            amount,
+            decimals,
+            stake_index,
+            storage_deposit,
+        } => withdraw_stake_payload(
+            parse_account_id(&contract_id)?,
+            parse_token_amount(&amount, decimals)?,
+            stake_index,
+            parse_amount(&storage_deposit, NEAR_DECIMALS)?,
+        ),
+        Command::Simulate {
+            amount,
+            decimals,
+            duration,
+            config,
+            boost_bps,
+        } => {
+            let config = match config {
+                Some(path) => std::fs::read_to_string(&path)
+                    .map_err(|err| format!("Cannot read {}: {}", path, err))
+                    .and_then(|json| parse_config(Some(&json)))?,
+                None => parse_config(None)?,
+            };
+            let simulation = simulate(
+                parse_token_amount(&amount, decimals)?,
+                decimals,
+                parse_duration(&duration)?,
+                &config,
+                boost_bps,
+            );
+            return Ok(near_sdk::serde_json::to_string_pretty(&simulation).unwrap());
+        }
+    };
+    Ok(match cli.near_cli {
+        Some(signer_id) => payload.near_cli(parse_account_id(&signer_id)?.as_str()),
+        None => near_sdk::serde_json::to_string_pretty(&payload).unwrap(),
+    })
+}
+
+fn main() {
+    match run(Cli::parse()) {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn run_args(args: &[&str]) -> Result<String, String> {
+        let cli = Cli::try_parse_from([&["staking-cli"], args].concat()).unwrap();
+        run(cli)
+    }
+
+    fn account(account_id: &str) -> AccountId {
+        account_id.parse().unwrap()
+    }
+
+    #[test]
+    fn test_stake_payload() {
+        let payload = stake_payload(
+            account("staking.testnet"),
+            account("token.testnet"),
+            parse_token_amount("1.5", 12).unwrap(),
+            None,
+        );
+        assert_eq!(payload.receiver_id, account("token.testnet"));
+        assert_eq!(payload.method_name, "ft_transfer_call");
+        assert_eq!(
+            payload.args,
+            json!({"receiver_id": "staking.testnet", "amount": "1500000000000", "msg": "staking"})
+        );
+        assert_eq!(payload.deposit.0, DEPOSIT_ONE_YOCTO);
+        assert_eq!(payload.gas.0, STAKE_TRANSFER_CALL_GAS.0);
+    }
+
+    #[test]
+    fn test_stake_payload_with_referrer() {
+        let payload = stake_payload(
+            account("staking.testnet"),
+            account("token.testnet"),
+            1,
+            Some(account("bob.testnet")),
+        );
+        let msg: Value =
+            near_sdk::serde_json::from_str(payload.args["msg"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            msg,
+            json!({"action": "staking", "referrer_id": "bob.testnet"})
+        );
+    }
+
+    #[test]
+    fn test_withdraw_stake_near_cli() {
+        let output = run_args(&[
+            "--near-cli",
+            "alice.testnet",
+            "withdraw-stake",
+            "--contract-id",
+            "staking.testnet",
+            "--amount",
+            "2",
+            "--decimals",
+            "12",
+            "--stake-index",
+            "1",
+            "--storage-deposit",
+            "0.01",
+        ])
+        .unwrap();
+        assert_eq!(
+            output,
+            "near call staking.testnet withdraw_stake '{\"amount\":\"2000000000000\",\"stake_index\":1}' \
+             --accountId alice.testnet --gas 150000000000000 --depositYocto 10000000000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        assert!(run_args(&[
+            "new",
+            "--contract-id",
+            "Staking!",
+            "--owner-id",
+            "alice.testnet"
+        ])
+        .is_err());
+        let stake = |amount: &str| {
+            run_args(&[
+                "stake",
+                "--contract-id",
+                "staking.testnet",
+                "--token-id",
+                "token.testnet",
+                "--amount",
+                amount,
+                "--decimals",
+                "12",
+            ])
+        };
+        assert!(stake("1.5").is_ok());
+        // the decimals of the token are never guessed
+        assert!(Cli::try_parse_from([
+            "staking-cli",
+            "stake",
+            "--contract-id",
+            "staking.testnet",
+            "--token-id",
+            "token.testnet",
+            "--amount",
+            "1.5",
+        ])
+        .is_err());
+        assert!(stake("0").is_err());
+        assert!(stake("1.0000000000001").is_err());
+        assert!(stake("1,5").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90m"), Ok(90 * 60_000));
+        assert_eq!(parse_duration("36h"), Ok(36 * ONE_HOUR));
+        assert_eq!(parse_duration("36"), Ok(36 * ONE_HOUR));
+        assert_eq!(parse_duration("1y"), Ok(ONE_YEAR));
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let mut config = Config::default();
+        config.fees.reward_fee_bps = 1_000;
+        let simulation = simulate(
+            parse_token_amount("1", 24).unwrap(),
+            24,
+            ONE_HOUR,
+            &config,
+            0,
+        );
+        assert_eq!(simulation.reward, "0.015");
+        assert_eq!(simulation.reward_fee, "0.0015");
+        assert_eq!(simulation.net_reward, "0.0135");
+        assert_eq!(simulation.apr_bps.0, 1_314_000);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::*;
+
+pub use staking_contract::{
+    ALLOW_TOKEN_GAS, DEPOSIT_ONE_YOCTO, STAKE_TRANSFER_CALL_GAS, TOKEN_REGISTRATION_DEPOSIT,
+    WITHDRAW_STAKE_GAS,
+};
+
+pub const TGAS: u64 = 1_000_000_000_000;
+pub const DEFAULT_CALL_GAS: u64 = 30 * TGAS;
+pub const NEAR_DECIMALS: u32 = 24;
+
+/**
+ * @notice
+ * CallPayload is everything needed to sign a function call, printed as json or as a near-cli command
+ */
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CallPayload {
+    pub receiver_id: AccountId,
+    pub method_name: String,
+    pub args: Value,
+    pub gas: U64,
+    pub deposit: U128,
+}
+
+impl CallPayload {
+    pub fn near_cli(&self, signer_id: &str) -> String {
+        let mut command = format!(
+            "near call {} {} '{}' --accountId {} --gas {}",
+            self.receiver_id, self.method_name, self.args, signer_id, self.gas.0
+        );
+        if self.deposit.0 > 0 {
+            command.push_str(&format!(" --depositYocto {}", self.deposit.0));
+        }
+        command
+    }
+}
+
+pub fn parse_account_id(account_id: &str) -> Result<AccountId, String> {
+    account_id
+        .parse()
+        .map_err(|_| format!("Invalid account id {:?}", account_id))
+}
+
+pub fn parse_token_amount(amount: &str, decimals: u32) -> Result<Balance, String> {
+    let amount = parse_amount(amount, decimals)?;
+    if amount == 0 {
+        return Err("Amount must be more than 0".to_string());
+    }
+    Ok(amount)
+}
+
+pub fn new_payload(contract_id: AccountId, owner_id: AccountId) -> CallPayload {
+    CallPayload {
+        receiver_id: contract_id,
+        method_name: "new".to_string(),
+        args: json!({ "owner_id": owner_id }),
+        gas: U64(DEFAULT_CALL_GAS),
+        deposit: U128(0),
+    }
+}
+
+pub fn allow_token_payload(contract_id: AccountId, token_id: AccountId) -> CallPayload {
+    CallPayload {
+        receiver_id: contract_id,
+        method_name: "allow_token".to_string(),
+        args: json!({ "token_id": token_id }),
+        gas: U64(ALLOW_TOKEN_GAS.0),
+        deposit: U128(TOKEN_REGISTRATION_DEPOSIT),
+    }
+}
+
+/**
+ * @notice
+ * stake_payload is the ft_transfer_call on the token contract that stakes amount in the staking contract
+ */
+pub fn stake_payload(
+    contract_id: AccountId,
+    token_id: AccountId,
+    amount: Balance,
+    referrer_id: Option<AccountId>,
+) -> CallPayload {
+    let msg = match referrer_id {
+        Some(referrer_id) => json!({ "action": "staking", "referrer_id": referrer_id }).to_string(),
+        None => "staking".to_string(),
+    };
+    CallPayload {
+        receiver_id: token_id,
+        method_name: "ft_transfer_call".to_string(),
+        args: json!({
+            "receiver_id": contract_id,
+            "amount": U128(amount),
+            "msg": msg,
+        }),
+        gas: U64(STAKE_TRANSFER_CALL_GAS.0),
+        deposit: U128(DEPOSIT_ONE_YOCTO),
+    }
+}
+
+/**
+ * @notice
+ * withdraw_stake_payload withdraws amount from the stake at stake_index,
+ * storage_deposit goes to the storage balance of the signer for the balance checkpoint
+ */
+pub fn withdraw_stake_payload(
+    contract_id: AccountId,
+    amount: Balance,
+    stake_index: usize,
+    storage_deposit: Balance,
+) -> CallPayload {
+    CallPayload {
+        receiver_id: contract_id,
+        method_name: "withdraw_stake".to_string(),
+        args: json!({ "amount": U128(amount), "stake_index": stake_index }),
+        gas: U64(WITHDRAW_STAKE_GAS.0),
+        deposit: U128(storage_deposit),
+    }
+}
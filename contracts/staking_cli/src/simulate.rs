@@ -0,0 +1,44 @@
+use crate::*;
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Simulation {
+    pub amount: String,
+    pub duration_ms: u64,
+    pub reward: String,
+    pub reward_fee: String,
+    // reward - reward_fee, what the staker receives
+    pub net_reward: String,
+    pub apr_bps: U128,
+}
+
+pub fn parse_config(json: Option<&str>) -> Result<Config, String> {
+    match json {
+        Some(json) => near_sdk::serde_json::from_str(json)
+            .map_err(|err| format!("Invalid config, pass the output of get_config: {}", err)),
+        None => Ok(Config::default()),
+    }
+}
+
+/**
+ * @notice
+ * simulate works out the reward of amount staked for duration_ms with the same formula as the contract
+ */
+pub fn simulate(
+    amount: Balance,
+    decimals: u32,
+    duration_ms: u64,
+    config: &Config,
+    boost_bps: u16,
+) -> Simulation {
+    let reward = project_reward(amount, duration_ms, config, boost_bps);
+    let reward_fee = fee_of(reward, config.fees.reward_fee_bps);
+    Simulation {
+        amount: format_amount(amount, decimals),
+        duration_ms,
+        reward: format_amount(reward, decimals),
+        reward_fee: format_amount(reward_fee, decimals),
+        net_reward: format_amount(reward - reward_fee, decimals),
+        apr_bps: U128(apr_bps(config, boost_bps)),
+    }
+}
@@ -0,0 +1,71 @@
+use crate::*;
+
+impl Stakeable {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Staking: Only the owner can call this method"
+        );
+    }
+
+    /**
+     * @notice
+     * Settings that change what stakers earn, pay or vote with are set by the owner until the first stake,
+     * after that they only change through proposals
+     */
+    pub(crate) fn assert_owner_before_staking(&self) {
+        self.assert_owner();
+        assert!(
+            self.total_staked == 0,
+            "Staking: Tokens are staked, change this setting with a proposal"
+        );
+    }
+
+    pub(crate) fn assert_owner_or_operator(&self) {
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner_id || self.operators.contains(&caller),
+            "Staking: Only the owner or an operator can call this method"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    // * readonly
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    /**
+     * @notice
+     * set_owner hands the contract over to a new owner, it requires one yocto so it can only be signed with a full access key
+     */
+    #[payable]
+    pub fn set_owner(&mut self, owner_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        log_str(format!("owner changed from {} to {}", self.owner_id, owner_id).as_str());
+        self.owner_id = owner_id;
+    }
+
+    /**
+     * @notice
+     * Operators can run day to day tasks like managing the staker allowlist, but cannot change the owner or upgrade the code
+     */
+    pub fn add_operator(&mut self, account_id: AccountId) -> bool {
+        self.assert_owner();
+        self.operators.insert(&account_id)
+    }
+
+    pub fn remove_operator(&mut self, account_id: AccountId) -> bool {
+        self.assert_owner();
+        self.operators.remove(&account_id)
+    }
+
+    // * readonly
+    pub fn get_operators(&self) -> Vec<AccountId> {
+        self.operators.to_vec()
+    }
+}
@@ -0,0 +1,504 @@
+use crate::*;
+
+pub const ON_AIRDROP_PAID_GAS: Gas = Gas(10_000_000_000_000);
+pub const ON_AIRDROP_SWEPT_GAS: Gas = Gas(10_000_000_000_000);
+// claims are tracked in words of 128 bits
+const CLAIM_WORD_BITS: u64 = 128;
+
+pub type Hash = [u8; 32];
+
+/**
+ * @notice
+ * AirdropCampaign is one distribution, the allocations are the leaves of the Merkle tree whose root is stored here.
+ * A leaf is sha256("{index}:{account_id}:{amount}") and every proof step hashes the sorted pair of nodes
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropCampaign {
+    pub token_id: AccountId,
+    pub merkle_root: String,
+    // sum of the allocations in the tree
+    pub total: U128,
+    // tokens sent for the campaign through ft_transfer_call
+    pub funded: U128,
+    pub claimed: U128,
+    pub expires_at: u64,
+    // claims become stakes locked for this many ms, None pays them out
+    pub lock_duration: Option<u64>,
+    pub swept: bool,
+}
+
+pub fn parse_hash(hex: &str) -> Result<Hash, String> {
+    let hex = hex.trim_start_matches("0x");
+    if hex.len() != 64 {
+        return Err("Staking: A hash is 32 bytes of hex".to_string());
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| "Staking: A hash is 32 bytes of hex".to_string())?;
+    }
+    Ok(hash)
+}
+
+pub fn airdrop_leaf(index: u64, account_id: &AccountId, amount: Balance) -> Hash {
+    env::sha256(format!("{}:{}:{}", index, account_id, amount).as_bytes())
+        .try_into()
+        .unwrap()
+}
+
+pub fn verify_proof(leaf: Hash, proof: &[Hash], root: &Hash) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        let (left, right) = if node <= *sibling {
+            (node, *sibling)
+        } else {
+            (*sibling, node)
+        };
+        env::sha256(&[left, right].concat()).try_into().unwrap()
+    });
+    &computed == root
+}
+
+impl Stakeable {
+    fn _get_airdrop(&self, campaign_id: u64) -> AirdropCampaign {
+        self.airdrops
+            .get(campaign_id)
+            .unwrap_or_else(|| env::panic_str("Staking: Airdrop does not exist"))
+    }
+
+    fn _is_airdrop_claimed(&self, campaign_id: u64, index: u64) -> bool {
+        let word = self
+            .airdrop_claims
+            .get(&(campaign_id, index / CLAIM_WORD_BITS))
+            .unwrap_or(0);
+        word & (1 << (index % CLAIM_WORD_BITS)) != 0
+    }
+
+    fn _set_airdrop_claimed(&mut self, campaign_id: u64, index: u64, claimed: bool) {
+        let key = (campaign_id, index / CLAIM_WORD_BITS);
+        let word = self.airdrop_claims.get(&key).unwrap_or(0);
+        let bit = 1 << (index % CLAIM_WORD_BITS);
+        let word = if claimed { word | bit } else { word & !bit };
+        self.airdrop_claims.insert(&key, &word);
+    }
+
+    /**
+     * @notice
+     * _fund_airdrop adds tokens sent with {"action": "fund_airdrop"} to a campaign, returns what is not needed.
+     * An expired or swept campaign takes nothing, the transfer is refunded
+     */
+    pub(crate) fn _fund_airdrop(
+        &mut self,
+        campaign_id: u64,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Result<Balance, String> {
+        let mut campaign = self
+            .airdrops
+            .get(campaign_id)
+            .ok_or_else(|| "Staking: Airdrop does not exist".to_string())?;
+        if &campaign.token_id != token_id {
+            return Err(format!("Staking: Airdrop is paid in {}", campaign.token_id));
+        }
+        if campaign.swept || env::block_timestamp_ms() >= campaign.expires_at {
+            return Err("Staking: Airdrop has expired, it cannot be funded".to_string());
+        }
+        let accepted = amount.min(campaign.total.0 - campaign.funded.0);
+        campaign.funded.0 += accepted;
+        self.airdrops.replace(campaign_id, &campaign);
+        Ok(amount - accepted)
+    }
+
+    /**
+     * @notice
+     * _check_airdrop_stake runs the checks of a deposit on the stake made by a claim of a locked campaign:
+     * the token takes deposits, the account is on the allowlist and the whole amount fits in the stake limits.
+     * The claim still pays no deposit fee
+     */
+    pub(crate) fn _check_airdrop_stake(
+        &self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Result<(), String> {
+        let StakePlan { refund, .. } = self._plan_stake(account_id, token_id, amount, None)?;
+        match refund.0 {
+            0 => Ok(()),
+            refund => Err(format!(
+                "Staking: Only {} of the airdrop fits in the stake limits",
+                amount - refund
+            )),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * create_airdrop stores a new campaign, it can be claimed once it is funded through ft_transfer_call with
+     * {"action": "fund_airdrop", "campaign_id": 0}
+     */
+    pub fn create_airdrop(
+        &mut self,
+        token_id: AccountId,
+        merkle_root: String,
+        total: U128,
+        expires_at: u64,
+        lock_duration: Option<u64>,
+    ) -> u64 {
+        self.assert_owner();
+        parse_hash(&merkle_root).unwrap_or_else(|reason| env::panic_str(reason.as_str()));
+        assert!(
+            expires_at > env::block_timestamp_ms(),
+            "Staking: Airdrop must expire in the future"
+        );
+        let campaign_id = self.airdrops.len();
+        self.airdrops.push(&AirdropCampaign {
+            token_id: token_id.clone(),
+            merkle_root,
+            total,
+            funded: U128(0),
+            claimed: U128(0),
+            expires_at,
+            lock_duration,
+            swept: false,
+        });
+        AirdropCreated {
+            campaign_id,
+            token_id,
+            total,
+            expires_at,
+        }
+        .emit();
+        campaign_id
+    }
+
+    /**
+     * @notice
+     * claim_airdrop verifies the allocation at index of the caller against the Merkle root.
+     * The amount is staked, locked for the lock duration of the campaign, or transferred when the campaign has none,
+     * cut pro rata when the token is underfunded. The stake must pass the checks of a deposit.
+     * The attached deposit goes to the storage balance of the caller like in withdraw_stake
+     */
+    #[payable]
+    pub fn claim_airdrop(
+        &mut self,
+        campaign_id: u64,
+        index: u64,
+        amount: U128,
+        proof: Vec<String>,
+    ) -> PromiseOrValue<U128> {
+        let account_id = env::predecessor_account_id();
+        self._deposit_storage(&account_id);
+        let mut campaign = self._get_airdrop(campaign_id);
+        let now = env::block_timestamp_ms();
+        assert!(now < campaign.expires_at, "Staking: Airdrop has expired");
+        assert!(
+            !self._is_airdrop_claimed(campaign_id, index),
+            "Staking: Airdrop is already claimed"
+        );
+        let proof = proof
+            .iter()
+            .map(|node| parse_hash(node))
+            .collect::<Result<Vec<Hash>, String>>()
+            .unwrap_or_else(|reason| env::panic_str(reason.as_str()));
+        let root = parse_hash(&campaign.merkle_root).unwrap();
+        assert!(
+            verify_proof(airdrop_leaf(index, &account_id, amount.0), &proof, &root),
+            "Staking: Invalid airdrop proof"
+        );
+        assert!(
+            campaign.claimed.0 + amount.0 <= campaign.funded.0,
+            "Staking: Airdrop is not funded yet"
+        );
+        if campaign.lock_duration.is_some() {
+            self._check_airdrop_stake(&account_id, &campaign.token_id, amount.0)
+                .unwrap_or_else(|reason| env::panic_str(reason.as_str()));
+        }
+        let initial_storage = env::storage_usage();
+        self._set_airdrop_claimed(campaign_id, index, true);
+        self._charge_storage(&account_id, initial_storage);
+        campaign.claimed.0 += amount.0;
+        self.airdrops.replace(campaign_id, &campaign);
+        AirdropClaimed {
+            campaign_id,
+            account_id: account_id.clone(),
+            index,
+            amount,
+            staked: campaign.lock_duration.is_some(),
+        }
+        .emit();
+        match campaign.lock_duration {
+            Some(lock_duration) => {
+                self._stake_locked(account_id, campaign.token_id, amount, now + lock_duration);
+                PromiseOrValue::Value(amount)
+            }
+            None => {
+                let paid = U128(self._pro_rata_payout(&account_id, &campaign.token_id, amount.0));
+                PromiseOrValue::Promise(
+                    self._register_on_token(&campaign.token_id, &account_id)
+                        .then(
+                            ext_ft::ext(campaign.token_id)
+                                .with_static_gas(FT_TRANSFER_GAS)
+                                .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                                .ft_transfer(
+                                    account_id.to_string(),
+                                    paid.0.to_string(),
+                                    Some("airdrop".to_string()),
+                                ),
+                        )
+                        .then(
+                            Self::ext(env::current_account_id())
+                                .with_static_gas(ON_AIRDROP_PAID_GAS)
+                                .on_airdrop_paid(campaign_id, index, account_id, amount, paid),
+                        ),
+                )
+            }
+        }
+    }
+
+    #[private]
+    pub fn on_airdrop_paid(
+        &mut self,
+        campaign_id: u64,
+        index: u64,
+        account_id: AccountId,
+        amount: U128,
+        paid: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => paid,
+            _ => {
+                log_str("Staking: Airdrop transfer failed, it can be claimed again");
+                let mut campaign = self._get_airdrop(campaign_id);
+                self._remove_shortfall(&account_id, &campaign.token_id, amount.0 - paid.0);
+                campaign.claimed.0 -= amount.0;
+                self.airdrops.replace(campaign_id, &campaign);
+                self._set_airdrop_claimed(campaign_id, index, false);
+                U128(0)
+            }
+        }
+    }
+
+    /**
+     * @notice
+     * sweep_airdrop sends what was funded and not claimed back to the treasury receiver once the campaign has expired,
+     * cut pro rata when the token is underfunded
+     */
+    pub fn sweep_airdrop(&mut self, campaign_id: u64) -> Promise {
+        self.assert_owner();
+        let mut campaign = self._get_airdrop(campaign_id);
+        assert!(
+            env::block_timestamp_ms() >= campaign.expires_at,
+            "Staking: Airdrop has not expired yet"
+        );
+        assert!(!campaign.swept, "Staking: Airdrop is already swept");
+        let amount = campaign.funded.0 - campaign.claimed.0;
+        assert!(amount > 0, "Staking: Nothing to sweep");
+        campaign.swept = true;
+        self.airdrops.replace(campaign_id, &campaign);
+        let receiver_id = self
+            .config
+            .fees
+            .treasury_id
+            .clone()
+            .unwrap_or_else(|| self.owner_id.clone());
+        let paid = self._pro_rata_payout(&receiver_id, &campaign.token_id, amount);
+        self._register_on_token(&campaign.token_id, &receiver_id)
+            .then(
+                ext_ft::ext(campaign.token_id)
+                    .with_static_gas(FT_TRANSFER_GAS)
+                    .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                    .ft_transfer(
+                        receiver_id.to_string(),
+                        paid.to_string(),
+                        Some("airdrop sweep".to_string()),
+                    ),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_AIRDROP_SWEPT_GAS)
+                    .on_airdrop_swept(campaign_id, receiver_id, U128(paid)),
+            )
+    }
+
+    #[private]
+    pub fn on_airdrop_swept(
+        &mut self,
+        campaign_id: u64,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                AirdropSwept {
+                    campaign_id,
+                    receiver_id,
+                    amount,
+                }
+                .emit();
+                amount
+            }
+            _ => {
+                log_str("Staking: Airdrop sweep failed, it can be swept again");
+                let mut campaign = self._get_airdrop(campaign_id);
+                let swept = campaign.funded.0 - campaign.claimed.0;
+                self._remove_shortfall(&receiver_id, &campaign.token_id, swept - amount.0);
+                campaign.swept = false;
+                self.airdrops.replace(campaign_id, &campaign);
+                U128(0)
+            }
+        }
+    }
+
+    // * readonly
+    pub fn get_airdrop(&self, campaign_id: u64) -> Option<AirdropCampaign> {
+        self.airdrops.get(campaign_id)
+    }
+
+    // * readonly
+    pub fn get_airdrops(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<AirdropCampaign> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        (from_index..self.airdrops.len())
+            .take(limit as usize)
+            .filter_map(|campaign_id| self.airdrops.get(campaign_id))
+            .collect()
+    }
+
+    // * readonly
+    pub fn is_airdrop_claimed(&self, campaign_id: u64, index: u64) -> bool {
+        self._is_airdrop_claimed(campaign_id, index)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_airdrop_claim_stakes_locked() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, Some(10 * ONE_HOUR));
+        call_at(accounts(2), ONE_HOUR);
+        let result = contract.claim_airdrop(0, 0, U128(AMOUNT), proof);
+        assert!(matches!(result, PromiseOrValue::Value(U128(AMOUNT))));
+        assert!(contract.is_airdrop_claimed(0, 0));
+        assert!(!contract.is_airdrop_claimed(0, 1));
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.total_amount.0, AMOUNT);
+        assert_eq!(summary.stakes[0].locked_until, 11 * ONE_HOUR);
+        assert_eq!(contract.get_airdrop(0).unwrap().claimed.0, AMOUNT);
+        // rewards of a locked stake can be claimed
+        call_at(accounts(2), 2 * ONE_HOUR);
+        contract.claim_all(None);
+    }
+
+    #[test]
+    fn test_airdrop_stake_is_locked() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, Some(10 * ONE_HOUR));
+        call_at(accounts(2), ONE_HOUR);
+        contract.claim_airdrop(0, 0, U128(AMOUNT), proof);
+        call_at(accounts(2), 2 * ONE_HOUR);
+        assert_eq!(
+            withdrawal_error(&contract, 0, AMOUNT),
+            "Staking: Stake 0 is locked until 39600000"
+        );
+        call_at(accounts(2), 11 * ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        assert!(contract.has_stake(accounts(2)).stakes.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Airdrop is already claimed")]
+    fn test_airdrop_double_claim() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, None);
+        call_at(accounts(2), ONE_HOUR);
+        contract.claim_airdrop(0, 0, U128(AMOUNT), proof.clone());
+        contract.claim_airdrop(0, 0, U128(AMOUNT), proof);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Invalid airdrop proof")]
+    fn test_airdrop_wrong_amount() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, None);
+        call_at(accounts(2), ONE_HOUR);
+        contract.claim_airdrop(0, 0, U128(2 * AMOUNT), proof);
+    }
+
+    #[test]
+    fn test_airdrop_payout_failure_can_be_claimed_again() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, None);
+        call_at(accounts(2), ONE_HOUR);
+        assert!(matches!(
+            contract.claim_airdrop(0, 0, U128(AMOUNT), proof),
+            PromiseOrValue::Promise(_)
+        ));
+        callback_at(ONE_HOUR, PromiseResult::Failed);
+        assert_eq!(
+            contract
+                .on_airdrop_paid(0, 0, accounts(2), U128(AMOUNT), U128(AMOUNT))
+                .0,
+            0
+        );
+        assert!(!contract.is_airdrop_claimed(0, 0));
+        assert_eq!(contract.get_airdrop(0).unwrap().claimed.0, 0);
+    }
+
+    #[test]
+    fn test_airdrop_stake_takes_the_deposit_checks() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, Some(10 * ONE_HOUR));
+        call_at(accounts(1), 0);
+        contract.set_allowlist_enabled(true);
+        assert_eq!(
+            contract._check_airdrop_stake(&accounts(2), &token(), AMOUNT),
+            Err(format!("Staking: {} is not on the allowlist", accounts(2)))
+        );
+        contract.add_to_allowlist(vec![accounts(2)]);
+        contract.set_stake_limits(U128(1), Some(U128(AMOUNT / 2)), None);
+        assert_eq!(
+            contract._check_airdrop_stake(&accounts(2), &token(), AMOUNT),
+            Err(format!(
+                "Staking: Only {} of the airdrop fits in the stake limits",
+                AMOUNT / 2
+            ))
+        );
+        assert_eq!(
+            contract._check_airdrop_stake(&accounts(2), &nft(), AMOUNT),
+            Err(format!("Staking: {} is not allowed", nft()))
+        );
+
+        contract.set_stake_limits(U128(1), None, None);
+        call_at(accounts(2), ONE_HOUR);
+        contract.claim_airdrop(0, 0, U128(AMOUNT), proof);
+        assert_eq!(amounts(&contract, accounts(2)), vec![AMOUNT]);
+    }
+
+    #[test]
+    fn test_expired_airdrop_funding_is_refunded() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.create_airdrop(token(), hex(&[1; 32]), U128(AMOUNT), ONE_HOUR, None);
+        testing_env!(get_context(token())
+            .block_timestamp(at_ms(ONE_HOUR))
+            .build());
+        let msg = "{\"action\": \"fund_airdrop\", \"campaign_id\": 0}".to_string();
+        let refund = contract.ft_on_transfer(accounts(1), U128(AMOUNT), msg);
+        assert!(matches!(refund, PromiseOrValue::Value(U128(AMOUNT))));
+        assert_eq!(contract.get_airdrop(0).unwrap().funded.0, 0);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::*;
+
+pub const DEFAULT_PAGE_LIMIT: u64 = 50;
+
+impl Stakeable {
+    pub(crate) fn _can_stake(&self, account_id: &AccountId) -> bool {
+        !self.config.allowlist_enabled || self.allowlist.contains(account_id)
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * set_allowlist_enabled turns the private pool mode on or off.
+     * It only gates new deposits, existing stakes can always be withdrawn
+     */
+    pub fn set_allowlist_enabled(&mut self, enabled: bool) {
+        self.assert_owner_or_operator();
+        self.config.allowlist_enabled = enabled;
+        log_str(format!("allowlist_enabled={}", enabled).as_str());
+    }
+
+    /**
+     * @notice
+     * Adds accounts to the allowlist in one call, returns how many were not on it yet
+     */
+    pub fn add_to_allowlist(&mut self, account_ids: Vec<AccountId>) -> u64 {
+        self.assert_owner_or_operator();
+        account_ids
+            .iter()
+            .filter(|account_id| self.allowlist.insert(account_id))
+            .count() as u64
+    }
+
+    /**
+     * @notice
+     * Removes accounts from the allowlist in one call, returns how many were on it
+     */
+    pub fn remove_from_allowlist(&mut self, account_ids: Vec<AccountId>) -> u64 {
+        self.assert_owner_or_operator();
+        account_ids
+            .iter()
+            .filter(|account_id| self.allowlist.remove(account_id))
+            .count() as u64
+    }
+
+    // * readonly
+    pub fn is_allowlist_enabled(&self) -> bool {
+        self.config.allowlist_enabled
+    }
+
+    /**
+     * @notice
+     * readonly
+     * can_stake tells whether deposits from the account are accepted with the current allowlist mode
+     */
+    pub fn can_stake(&self, account_id: AccountId) -> bool {
+        self._can_stake(&account_id)
+    }
+
+    // * readonly
+    pub fn get_allowlist(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        self.allowlist
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+            .collect()
+    }
+
+    // * readonly
+    pub fn get_allowlist_length(&self) -> u64 {
+        self.allowlist.len()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_allowlist() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.add_operator(accounts(4));
+        call_at(accounts(4), 0);
+        contract.set_allowlist_enabled(true);
+        assert!(!contract.can_stake(accounts(2)));
+        assert_eq!(
+            transfer(&mut contract, accounts(2), AMOUNT, "staking"),
+            AMOUNT
+        );
+
+        call_at(accounts(4), 0);
+        assert_eq!(contract.add_to_allowlist(vec![accounts(2), accounts(2)]), 1);
+        assert_eq!(contract.get_allowlist(None, None), vec![accounts(2)]);
+        assert_eq!(transfer(&mut contract, accounts(2), AMOUNT, "staking"), 0);
+
+        call_at(accounts(4), 0);
+        assert_eq!(contract.remove_from_allowlist(vec![accounts(2)]), 1);
+        assert_eq!(
+            transfer(&mut contract, accounts(2), AMOUNT, "staking"),
+            AMOUNT
+        );
+        // removed accounts keep their stakes
+        assert_eq!(contract.has_stake(accounts(2)).total_amount.0, AMOUNT);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Only the owner or an operator can call this method")]
+    fn test_allowlist_operator_only() {
+        let mut contract = setup();
+        call_at(accounts(2), 0);
+        contract.set_allowlist_enabled(true);
+    }
+}
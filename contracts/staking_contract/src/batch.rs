@@ -0,0 +1,431 @@
+use crate::*;
+
+pub const ON_WITHDRAW_RESOLVED_GAS: Gas = Gas(20_000_000_000_000);
+// static gas _pay_settlements attaches for one settlement: the registration of the receiver, ft_transfer and on_withdraw_resolved
+pub const SETTLEMENT_PAYOUT_GAS: Gas = Gas(STORAGE_BALANCE_OF_GAS.0
+    + ON_STORAGE_CHECKED_GAS.0
+    + FT_TRANSFER_GAS.0
+    + ON_WITHDRAW_RESOLVED_GAS.0);
+// gas clients attach to withdraw_stake, the payout of its settlement and the checkpoint
+pub const WITHDRAW_STAKE_GAS: Gas = Gas(150_000_000_000_000);
+
+/**
+ * @notice
+ * Settlement is every withdrawal of one token in a call added together,
+ * it is paid with a single ft_transfer and resolved by on_withdraw_resolved
+ */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Settlement {
+    pub token_id: AccountId,
+    pub principal: U128,
+    pub principal_fee: U128,
+    pub reward: U128,
+    pub reward_fee: U128,
+    pub referral_reward: U128,
+    pub payout: U128,
+    // latest lock of the withdrawn stakes, the principal keeps it when it is restored
+    pub locked_until: u64,
+    // part of the payout cut because the token is underfunded, owed to the account
+    pub shortfall: U128,
+}
+
+impl From<Withdrawal> for Settlement {
+    fn from(withdrawal: Withdrawal) -> Self {
+        Self {
+            token_id: withdrawal.token_id,
+            principal: withdrawal.principal,
+            principal_fee: withdrawal.principal_fee,
+            reward: withdrawal.reward,
+            reward_fee: withdrawal.reward_fee,
+            referral_reward: withdrawal.referral_reward,
+            payout: withdrawal.payout,
+            locked_until: withdrawal.locked_until,
+            shortfall: U128(0),
+        }
+    }
+}
+
+impl Settlement {
+    fn add(&mut self, withdrawal: &Withdrawal) {
+        self.principal.0 += withdrawal.principal.0;
+        self.principal_fee.0 += withdrawal.principal_fee.0;
+        self.reward.0 += withdrawal.reward.0;
+        self.reward_fee.0 += withdrawal.reward_fee.0;
+        self.referral_reward.0 += withdrawal.referral_reward.0;
+        self.payout.0 += withdrawal.payout.0;
+        self.locked_until = self.locked_until.max(withdrawal.locked_until);
+    }
+}
+
+pub const DUPLICATE_POSITION: &str = "Staking: A stake can only be withdrawn once per call";
+
+/**
+ * @notice
+ * sort_positions orders (index, amount) positions from the highest index down,
+ * so removing an emptied stake does not move the ones left to do. False when an index is there twice
+ */
+pub(crate) fn sort_positions(positions: &mut [(usize, Balance)]) -> bool {
+    positions.sort_unstable_by_key(|position| std::cmp::Reverse(position.0));
+    positions.windows(2).all(|pair| pair[0].0 != pair[1].0)
+}
+
+// adds a withdrawal to the settlement of its token
+pub(crate) fn add_to_settlements(settlements: &mut Vec<Settlement>, withdrawal: Withdrawal) {
+    match settlements
+        .iter_mut()
+        .find(|settlement| settlement.token_id == withdrawal.token_id)
+    {
+        Some(settlement) => settlement.add(&withdrawal),
+        None => settlements.push(withdrawal.into()),
+    }
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _settle_positions withdraws amount from every (index, amount) position of the account and saves it once
+     */
+    pub(crate) fn _settle_positions(
+        &mut self,
+        account_id: &AccountId,
+        mut positions: Vec<(usize, Balance)>,
+    ) -> Vec<Settlement> {
+        let mut stakeholder = self
+            ._get_stakeholder(account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: Account has no stakes"));
+        assert!(sort_positions(&mut positions), "{}", DUPLICATE_POSITION);
+        let mut settlements: Vec<Settlement> = Vec::new();
+        for (index, amount) in positions {
+            let withdrawal = self._withdraw_from(&mut stakeholder, index, amount);
+            add_to_settlements(&mut settlements, withdrawal);
+        }
+        self._save_stakeholder(account_id, stakeholder);
+        settlements
+    }
+
+    /**
+     * @notice
+     * _pay_settlements sends one ft_transfer per token, each preceded by the registration of the account on the token
+     * and followed by on_withdraw_resolved.
+     * Transfers are chained one after another because a joint promise cannot be returned,
+     * settlements with nothing to pay are skipped and payouts of an underfunded token are cut pro rata
+     */
+    pub(crate) fn _pay_settlements(
+        &mut self,
+        account_id: &AccountId,
+        mut settlements: Vec<Settlement>,
+    ) -> PromiseOrValue<U128> {
+        self._pro_rata_settlements(account_id, &mut settlements);
+        let mut payment: Option<Promise> = None;
+        for settlement in settlements {
+            if settlement.payout.0 == 0 {
+                continue;
+            }
+            // the gas of these calls adds up to SETTLEMENT_PAYOUT_GAS
+            let register = self._register_on_token(&settlement.token_id, account_id);
+            let transfer = ext_ft::ext(settlement.token_id.clone())
+                .with_static_gas(FT_TRANSFER_GAS)
+                .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                .ft_transfer(
+                    account_id.to_string(),
+                    settlement.payout.0.to_string(),
+                    Some("0".to_string()),
+                );
+            let resolve = Self::ext(env::current_account_id())
+                .with_static_gas(ON_WITHDRAW_RESOLVED_GAS)
+                .on_withdraw_resolved(account_id.clone(), settlement);
+            payment = Some(match payment {
+                Some(previous) => previous.then(register).then(transfer).then(resolve),
+                None => register.then(transfer).then(resolve),
+            });
+        }
+        match payment {
+            Some(promise) => PromiseOrValue::Promise(promise),
+            None => PromiseOrValue::Value(U128(0)),
+        }
+    }
+
+    /**
+     * @notice
+     * _rollback_settlement undoes a settlement whose transfer failed: the fees, the referral credit and the shortfall are taken back,
+     * the principal is staked again as a new stake with the lock it had and the reward becomes claimable on it
+     */
+    fn _rollback_settlement(&mut self, account_id: &AccountId, settlement: &Settlement) {
+        let token_id = &settlement.token_id;
+        self._remove_shortfall(account_id, token_id, settlement.shortfall.0);
+        self._refund_fee(
+            token_id,
+            settlement.principal_fee.0 + settlement.reward_fee.0,
+        );
+        let mut stakeholder = self
+            ._get_stakeholder(account_id)
+            .unwrap_or_else(|| self._add_stakeholder(account_id.clone()));
+        if let Some(referrer_id) = stakeholder.referrer.clone() {
+            self._remove_referral_reward(&referrer_id, token_id, settlement.referral_reward.0);
+        }
+        StakeRestored {
+            account_id: account_id.clone(),
+            stake_index: stakeholder.address_stakes.len() as u64,
+            token_id: token_id.clone(),
+            principal: settlement.principal,
+            reward: settlement.reward,
+            locked_until: settlement.locked_until,
+        }
+        .emit();
+        stakeholder.address_stakes.push(Stake {
+            address: account_id.clone(),
+            token_id: token_id.clone(),
+            amount: settlement.principal,
+            since: env::block_timestamp_ms(),
+            claimable: settlement.reward,
+            locked_until: settlement.locked_until,
+        });
+        self._save_stakeholder(account_id, stakeholder);
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * withdraw_all withdraws every stake of the caller with its reward, one transfer per token.
+     * The attached deposit goes to the storage balance of the caller like in withdraw_stake
+     */
+    #[payable]
+    pub fn withdraw_all(&mut self) -> PromiseOrValue<U128> {
+        let account_id = env::predecessor_account_id();
+        self._deposit_storage(&account_id);
+        let positions = self
+            ._get_stakeholder(&account_id)
+            .map(|stakeholder| {
+                stakeholder
+                    .address_stakes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, stake)| (index, stake.amount.0))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut settlements = self._settle_positions(&account_id, positions);
+        self._vest_rewards(&account_id, &mut settlements, false);
+        self._pay_settlements(&account_id, settlements)
+    }
+
+    /**
+     * @notice
+     * withdraw_many withdraws amounts[i] from the stake ids[i] of the caller, one transfer per token
+     */
+    #[payable]
+    pub fn withdraw_many(&mut self, ids: Vec<usize>, amounts: Vec<U128>) -> PromiseOrValue<U128> {
+        assert_eq!(
+            ids.len(),
+            amounts.len(),
+            "Staking: ids and amounts must have the same length"
+        );
+        assert!(!ids.is_empty(), "Staking: Nothing to withdraw");
+        let account_id = env::predecessor_account_id();
+        self._deposit_storage(&account_id);
+        let positions = ids
+            .into_iter()
+            .zip(amounts.into_iter().map(|amount| amount.0))
+            .collect();
+        let mut settlements = self._settle_positions(&account_id, positions);
+        self._vest_rewards(&account_id, &mut settlements, false);
+        self._pay_settlements(&account_id, settlements)
+    }
+
+    /**
+     * @notice
+     * claim_all pays the reward of every stake of the caller and restarts their timers, the principal stays staked.
+     * While vesting is enabled the reward vests, unless instant is set and the instant claim penalty is given up
+     */
+    #[payable]
+    pub fn claim_all(&mut self, instant: Option<bool>) -> PromiseOrValue<U128> {
+        let account_id = env::predecessor_account_id();
+        self._deposit_storage(&account_id);
+        let positions = self
+            ._get_stakeholder(&account_id)
+            .map(|stakeholder| {
+                (0..stakeholder.address_stakes.len())
+                    .map(|index| (index, 0))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut settlements = self._settle_positions(&account_id, positions);
+        self._vest_rewards(&account_id, &mut settlements, instant.unwrap_or(false));
+        self._pay_settlements(&account_id, settlements)
+    }
+
+    /**
+     * @notice
+     * on_withdraw_resolved is called after the transfer of a settlement,
+     * returns what was paid or rolls the settlement back and returns 0
+     */
+    #[private]
+    pub fn on_withdraw_resolved(&mut self, account_id: AccountId, settlement: Settlement) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                let payout = settlement.payout;
+                WithdrawSettled {
+                    account_id,
+                    token_id: settlement.token_id,
+                    principal: settlement.principal,
+                    reward: settlement.reward,
+                    fees: U128(settlement.principal_fee.0 + settlement.reward_fee.0),
+                    payout,
+                }
+                .emit();
+                payout
+            }
+            _ => {
+                log_str("Staking: Transfer failed, the withdrawal is rolled back");
+                self._rollback_settlement(&account_id, &settlement);
+                WithdrawRolledBack {
+                    account_id,
+                    token_id: settlement.token_id,
+                    principal: settlement.principal,
+                    reward: settlement.reward,
+                }
+                .emit();
+                U128(0)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_withdraw_resolved_success() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        callback_at(ONE_HOUR, PromiseResult::Successful(vec![]));
+        let paid = contract.on_withdraw_resolved(accounts(2), settlement(AMOUNT, HOURLY_REWARD));
+        assert_eq!(paid.0, AMOUNT + HOURLY_REWARD);
+        assert!(contract.has_stake(accounts(2)).stakes.is_empty());
+    }
+
+    #[test]
+    fn test_withdraw_resolved_failure_restores_stake() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        callback_at(ONE_HOUR, PromiseResult::Failed);
+        let paid = contract.on_withdraw_resolved(accounts(2), settlement(AMOUNT, HOURLY_REWARD));
+        assert_eq!(paid.0, 0);
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.total_amount.0, AMOUNT);
+        // the reward that was not paid stays claimable
+        assert_eq!(summary.stakes[0].claimable.0, HOURLY_REWARD);
+        assert_eq!(contract.total_staked, AMOUNT);
+    }
+
+    #[test]
+    fn test_withdraw_resolved_failure_restores_a_new_stake() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(400_000), 0);
+        callback_at(2 * ONE_HOUR, PromiseResult::Failed);
+        contract.on_withdraw_resolved(accounts(2), settlement(400_000, HOURLY_REWARD));
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.total_amount.0, AMOUNT);
+        assert_eq!(amounts(&contract, accounts(2)), vec![600_000, 400_000]);
+        assert_eq!(summary.stakes[1].claimable.0, HOURLY_REWARD);
+        assert_eq!(summary.stakes[1].locked_until, 0);
+    }
+
+    #[test]
+    fn test_restored_stake_keeps_its_own_lock() {
+        let mut contract = setup();
+        let proof = airdrop_campaign(&mut contract, Some(10 * ONE_HOUR));
+        call_at(accounts(2), ONE_HOUR);
+        contract.claim_airdrop(0, 0, U128(AMOUNT), proof);
+        stake(&mut contract, accounts(2), AMOUNT, ONE_HOUR);
+        call_at(accounts(2), 2 * ONE_HOUR);
+        assert_eq!(
+            contract
+                .quote_withdraw(accounts(2), 0, U128(0))
+                .locked_until,
+            11 * ONE_HOUR
+        );
+        contract.withdraw_stake(U128(AMOUNT), 1);
+        callback_at(2 * ONE_HOUR, PromiseResult::Failed);
+        contract.on_withdraw_resolved(accounts(2), settlement(AMOUNT, 0));
+        // the unlocked principal does not take the lock of the airdrop stake
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.stakes.len(), 2);
+        assert_eq!(summary.stakes[0].locked_until, 11 * ONE_HOUR);
+        assert_eq!(summary.stakes[1].locked_until, 0);
+        call_at(accounts(2), 3 * ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 1);
+        // and a locked principal stays locked
+        callback_at(3 * ONE_HOUR, PromiseResult::Failed);
+        let mut locked = settlement(AMOUNT, 0);
+        locked.locked_until = 11 * ONE_HOUR;
+        contract.on_withdraw_resolved(accounts(2), locked);
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.stakes[1].locked_until, 11 * ONE_HOUR);
+    }
+
+    #[test]
+    fn test_withdraw_all() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        assert!(matches!(
+            contract.withdraw_all(),
+            PromiseOrValue::Promise(_)
+        ));
+        assert!(contract.has_stake(accounts(2)).stakes.is_empty());
+        assert_eq!(contract.total_staked, 0);
+    }
+
+    #[test]
+    fn test_withdraw_many() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        stake(&mut contract, accounts(2), 3 * AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_many(vec![0, 2], vec![U128(AMOUNT), U128(AMOUNT)]);
+        assert_eq!(
+            amounts(&contract, accounts(2)),
+            vec![2 * AMOUNT, 2 * AMOUNT]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: A stake can only be withdrawn once per call")]
+    fn test_withdraw_many_duplicate_index() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_many(vec![0, 0], vec![U128(AMOUNT), U128(AMOUNT)]);
+    }
+
+    #[test]
+    fn test_claim_all_keeps_principal() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        assert!(matches!(
+            contract.claim_all(None),
+            PromiseOrValue::Promise(_)
+        ));
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.total_amount.0, 2 * AMOUNT);
+        assert!(summary.stakes.iter().all(|stake| stake.claimable.0 == 0));
+    }
+}
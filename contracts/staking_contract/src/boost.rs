@@ -0,0 +1,476 @@
+use crate::*;
+use near_sdk::PromiseError;
+
+pub const MAX_BOOST_BPS: u16 = 10_000;
+pub const NFT_TOKEN_GAS: Gas = Gas(10_000_000_000_000);
+pub const NFT_TRANSFER_GAS: Gas = Gas(15_000_000_000_000);
+pub const ON_BOOST_CALLBACK_GAS: Gas = Gas(15_000_000_000_000);
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Boost {
+    // collection the NFT belongs to, the boost only counts while it is the configured one
+    pub nft_contract_id: AccountId,
+    pub nft_token_id: String,
+    pub escrowed: bool,
+    pub since: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BoostConfig {
+    pub nft_contract_id: Option<AccountId>,
+    pub boost_bps: u16,
+    pub mode: BoostMode,
+}
+
+// Only the fields of the NEP-171 Token we need, the rest of the json is ignored
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftToken {
+    pub token_id: String,
+    pub owner_id: AccountId,
+}
+
+#[allow(dead_code)]
+#[ext_contract(ext_nft)]
+trait NonFungibleToken {
+    fn nft_token(&self, token_id: String) -> Option<NftToken>;
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: String,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    );
+}
+
+impl Stakeable {
+    pub(crate) fn _boost_bps(&self, stakeholder: &StakeHolder) -> u16 {
+        match (&stakeholder.boost, &self.config.boost_nft_contract_id) {
+            (Some(boost), Some(nft_contract_id)) if &boost.nft_contract_id == nft_contract_id => {
+                self.config.boost_bps
+            }
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn _boost_bps_of(&self, account_id: &AccountId) -> u16 {
+        self._get_stakeholder(account_id)
+            .map(|stakeholder| self._boost_bps(&stakeholder))
+            .unwrap_or(0)
+    }
+
+    fn _boost_nft_contract(&self) -> AccountId {
+        self.config
+            .boost_nft_contract_id
+            .clone()
+            .unwrap_or_else(|| env::panic_str("Staking: Boosts are not enabled"))
+    }
+
+    /**
+     * @notice
+     * _activate_boost settles the rewards accrued without the boost and records it on the stakeholder,
+     * an NFT that already boosts another account is moved to the new one
+     */
+    fn _activate_boost(
+        &mut self,
+        account_id: &AccountId,
+        nft_contract_id: AccountId,
+        nft_token_id: String,
+        escrowed: bool,
+    ) {
+        let nft = (nft_contract_id.clone(), nft_token_id.clone());
+        if let Some(previous_account_id) = self.boosted_nfts.get(&nft) {
+            if &previous_account_id != account_id {
+                self._lapse_boost(&previous_account_id);
+            }
+        }
+        let mut stakeholder = match self._get_stakeholder(account_id) {
+            Some(stakeholder) => stakeholder,
+            None => self._add_stakeholder(account_id.clone()),
+        };
+        if let Some(boost) = stakeholder.boost.as_ref() {
+            let previous = (boost.nft_contract_id.clone(), boost.nft_token_id.clone());
+            if previous != nft {
+                self.boosted_nfts.remove(&previous);
+            }
+        }
+        self._settle_rewards(&mut stakeholder);
+        stakeholder.boost = Some(Boost {
+            nft_contract_id,
+            nft_token_id: nft_token_id.clone(),
+            escrowed,
+            since: env::block_timestamp_ms(),
+        });
+        self._save_stakeholder(account_id, stakeholder);
+        self.boosted_nfts.insert(&nft, account_id);
+        BoostActivated {
+            account_id: account_id.clone(),
+            nft_token_id,
+            escrowed,
+        }
+        .emit();
+    }
+
+    /**
+     * @notice
+     * _lapse_boost settles the boosted rewards and removes the boost, returns the boost that was removed
+     */
+    fn _lapse_boost(&mut self, account_id: &AccountId) -> Option<Boost> {
+        let mut stakeholder = self._get_stakeholder(account_id)?;
+        self._settle_rewards(&mut stakeholder);
+        let boost = stakeholder.boost.take()?;
+        self._save_stakeholder(account_id, stakeholder);
+        self.boosted_nfts
+            .remove(&(boost.nft_contract_id.clone(), boost.nft_token_id.clone()));
+        BoostLapsed {
+            account_id: account_id.clone(),
+            nft_token_id: boost.nft_token_id.clone(),
+        }
+        .emit();
+        Some(boost)
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * set_boost_config sets the partner collection, the extra reward in bps and how ownership is proven.
+     * The owner can only call it until the first stake, then boost_bps changes with a proposal. Boosted stakers
+     * earn a new boost from then on, what they accrued before keeps the boost it accrued with
+     */
+    pub fn set_boost_config(
+        &mut self,
+        nft_contract_id: Option<AccountId>,
+        boost_bps: u16,
+        mode: BoostMode,
+    ) {
+        self.assert_owner_before_staking();
+        assert!(
+            boost_bps <= MAX_BOOST_BPS,
+            "Staking: Boost cannot be more than {} bps",
+            MAX_BOOST_BPS
+        );
+        self._set_rates(|config| {
+            config.boost_nft_contract_id = nft_contract_id;
+            config.boost_bps = boost_bps;
+        });
+        self.config.boost_mode = mode;
+    }
+
+    // * readonly
+    pub fn get_boost_config(&self) -> BoostConfig {
+        BoostConfig {
+            nft_contract_id: self.config.boost_nft_contract_id.clone(),
+            boost_bps: self.config.boost_bps,
+            mode: self.config.boost_mode.clone(),
+        }
+    }
+
+    // * readonly
+    pub fn get_boost(&self, account_id: AccountId) -> Option<Boost> {
+        self._get_stakeholder(&account_id)
+            .and_then(|stakeholder| stakeholder.boost)
+    }
+
+    /**
+     * @notice
+     * activate_boost checks with nft_token that the caller owns the NFT, the boost is recorded in on_boost_nft_checked
+     */
+    pub fn activate_boost(&mut self, nft_token_id: String) -> Promise {
+        assert!(
+            self.config.boost_mode == BoostMode::Ownership,
+            "Staking: Boost NFTs have to be escrowed with nft_transfer_call"
+        );
+        let account_id = env::predecessor_account_id();
+        let nft_contract_id = self._boost_nft_contract();
+        ext_nft::ext(nft_contract_id.clone())
+            .with_static_gas(NFT_TOKEN_GAS)
+            .nft_token(nft_token_id.clone())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_BOOST_CALLBACK_GAS)
+                    .on_boost_nft_checked(account_id, nft_contract_id, nft_token_id),
+            )
+    }
+
+    #[private]
+    pub fn on_boost_nft_checked(
+        &mut self,
+        account_id: AccountId,
+        nft_contract_id: AccountId,
+        nft_token_id: String,
+        #[callback_result] token: Result<Option<NftToken>, PromiseError>,
+    ) -> bool {
+        match token {
+            Ok(Some(token)) if token.owner_id == account_id => {
+                self._activate_boost(&account_id, nft_contract_id, nft_token_id, false);
+                true
+            }
+            _ => {
+                log_str(format!("Staking: {} does not own {}", account_id, nft_token_id).as_str());
+                false
+            }
+        }
+    }
+
+    /**
+     * @notice
+     * refresh_boost can be called by anyone, the boost of the account lapses if it no longer owns the NFT
+     */
+    pub fn refresh_boost(&mut self, account_id: AccountId) -> Promise {
+        let boost = self
+            .get_boost(account_id.clone())
+            .filter(|boost| !boost.escrowed)
+            .unwrap_or_else(|| env::panic_str("Staking: No boost to refresh"));
+        ext_nft::ext(boost.nft_contract_id.clone())
+            .with_static_gas(NFT_TOKEN_GAS)
+            .nft_token(boost.nft_token_id.clone())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_BOOST_CALLBACK_GAS)
+                    .on_boost_nft_refreshed(account_id, boost.nft_contract_id, boost.nft_token_id),
+            )
+    }
+
+    #[private]
+    pub fn on_boost_nft_refreshed(
+        &mut self,
+        account_id: AccountId,
+        nft_contract_id: AccountId,
+        nft_token_id: String,
+        #[callback_result] token: Result<Option<NftToken>, PromiseError>,
+    ) -> bool {
+        let still_owned = match token {
+            Ok(Some(token)) => token.owner_id == account_id,
+            Ok(None) => false,
+            // keep the boost when the collection cannot be reached
+            Err(_) => return true,
+        };
+        let same_boost = self
+            .get_boost(account_id.clone())
+            .map(|boost| {
+                boost.nft_contract_id == nft_contract_id && boost.nft_token_id == nft_token_id
+            })
+            .unwrap_or(false);
+        if !still_owned && same_boost {
+            self._lapse_boost(&account_id);
+        }
+        still_owned
+    }
+
+    /**
+     * @notice
+     * NEP-171 receiver, in escrow mode the NFT is kept and boosts previous_owner_id.
+     * Returns true to send the NFT back
+     */
+    pub fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: String,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        log_str(format!("nft_on_transfer sender={} msg={}", sender_id, msg).as_str());
+        let from_collection =
+            self.config.boost_nft_contract_id.as_ref() == Some(&env::predecessor_account_id());
+        if !from_collection || self.config.boost_mode != BoostMode::Escrow {
+            log_str("Staking: NFT is not accepted");
+            return PromiseOrValue::Value(true);
+        }
+        if self.get_boost(previous_owner_id.clone()).is_some() {
+            log_str("Staking: Account already has a boost");
+            return PromiseOrValue::Value(true);
+        }
+        self._activate_boost(
+            &previous_owner_id,
+            env::predecessor_account_id(),
+            token_id,
+            true,
+        );
+        PromiseOrValue::Value(false)
+    }
+
+    /**
+     * @notice
+     * withdraw_boost_nft ends the boost and transfers the escrowed NFT back from the collection it came from,
+     * the boost is restored in on_boost_nft_withdrawn if the transfer fails
+     */
+    pub fn withdraw_boost_nft(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            self.get_boost(account_id.clone())
+                .map(|boost| boost.escrowed)
+                .unwrap_or(false),
+            "Staking: No escrowed boost NFT"
+        );
+        let boost = self._lapse_boost(&account_id).unwrap();
+        ext_nft::ext(boost.nft_contract_id.clone())
+            .with_static_gas(NFT_TRANSFER_GAS)
+            .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+            .nft_transfer(
+                account_id.clone(),
+                boost.nft_token_id.clone(),
+                None,
+                Some("boost withdrawn".to_string()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_BOOST_CALLBACK_GAS)
+                    .on_boost_nft_withdrawn(account_id, boost.nft_contract_id, boost.nft_token_id),
+            )
+    }
+
+    #[private]
+    pub fn on_boost_nft_withdrawn(
+        &mut self,
+        account_id: AccountId,
+        nft_contract_id: AccountId,
+        nft_token_id: String,
+    ) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => true,
+            _ => {
+                log_str("Staking: NFT transfer failed, the boost is restored");
+                self._activate_boost(&account_id, nft_contract_id, nft_token_id, true);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    #[should_panic(expected = "Staking: Tokens are staked, change this setting with a proposal")]
+    fn test_boost_setter_needs_a_proposal_once_staked() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(1), ONE_HOUR);
+        contract.set_boost_config(Some(nft()), 5_000, BoostMode::Escrow);
+    }
+
+    #[test]
+    fn test_boost_change_keeps_the_accrued_boost() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        call_at(accounts(1), 0);
+        contract.set_boost_config(Some(nft()), 1_000, BoostMode::Escrow);
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(3), AMOUNT, 0);
+        testing_env!(get_context(nft()).build());
+        contract.nft_on_transfer(
+            accounts(2),
+            accounts(2),
+            "nft-1".to_string(),
+            "".to_string(),
+        );
+
+        // a proposal raises the boost from 10% to 50%, the hours before it keep 10%
+        call_at(accounts(2), 0);
+        let update = ConfigUpdate {
+            boost_bps: Some(5_000),
+            ..ConfigUpdate::default()
+        };
+        let proposal_id = contract.create_proposal(
+            "Raise the boost".to_string(),
+            ProposalKind::UpdateConfig { update },
+        );
+        contract.vote(proposal_id, Vote::Yes);
+        call_at(accounts(3), 0);
+        contract.vote(proposal_id, Vote::Yes);
+        let executable_at = contract
+            .get_proposal(proposal_id)
+            .unwrap()
+            .proposal
+            .executable_at;
+        call_at(accounts(4), executable_at);
+        contract.execute_proposal(proposal_id);
+        assert_eq!(contract.get_rate_history().len(), 1);
+        assert_eq!(contract.get_rate_history()[0].boost_bps, 1_000);
+        assert_eq!(contract.get_rate_history()[0].end, executable_at);
+
+        let hours = (executable_at / ONE_HOUR) as u128;
+        call_at(accounts(2), executable_at + ONE_HOUR);
+        let boosted = hours * HOURLY_REWARD * 11 / 10 + HOURLY_REWARD * 15 / 10;
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            boosted
+        );
+        assert_eq!(
+            contract.has_stake(accounts(3)).stakes[0].claimable.0,
+            (hours + 1) * HOURLY_REWARD
+        );
+        assert_eq!(
+            contract.get_liabilities(token()).accrued_rewards.0,
+            boosted + (hours + 1) * HOURLY_REWARD
+        );
+
+        // once claimed, only the other stake is owed
+        contract.withdraw_stake(U128(0), 0);
+        assert_eq!(ft_transfer_amounts(), vec![boosted]);
+        assert_eq!(
+            contract.get_liabilities(token()).accrued_rewards.0,
+            (hours + 1) * HOURLY_REWARD
+        );
+    }
+
+    #[test]
+    fn test_escrowed_boost_nft_returns_to_its_collection() {
+        let mut contract = setup();
+        let new_collection: AccountId = "new-nft.testnet".parse().unwrap();
+        call_at(accounts(1), 0);
+        contract.set_boost_config(Some(nft()), 1_000, BoostMode::Escrow);
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        testing_env!(get_context(nft()).build());
+        contract.nft_on_transfer(
+            accounts(2),
+            accounts(2),
+            "nft-1".to_string(),
+            "".to_string(),
+        );
+        assert_eq!(
+            contract.get_boost(accounts(2)).unwrap().nft_contract_id,
+            nft()
+        );
+
+        // once nothing is staked the collection changes, the old boost stops counting but the NFT goes back where it came from
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        call_at(accounts(1), ONE_HOUR);
+        contract.set_boost_config(Some(new_collection), 1_000, BoostMode::Escrow);
+        assert_eq!(contract._boost_bps_of(&accounts(2)), 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_boost_nft();
+        let receivers: Vec<AccountId> = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .filter(|receipt| {
+                receipt.actions.iter().any(|action| {
+                    matches!(
+                        action,
+                        near_sdk::mock::VmAction::FunctionCall { function_name, .. }
+                            if function_name == "nft_transfer"
+                    )
+                })
+            })
+            .map(|receipt| receipt.receiver_id)
+            .collect();
+        assert_eq!(receivers, vec![nft()]);
+
+        // a failed transfer restores the boost with its collection
+        callback_at(ONE_HOUR, PromiseResult::Failed);
+        assert!(!contract.on_boost_nft_withdrawn(accounts(2), nft(), "nft-1".to_string()));
+        assert_eq!(
+            contract.get_boost(accounts(2)).unwrap().nft_contract_id,
+            nft()
+        );
+    }
+}
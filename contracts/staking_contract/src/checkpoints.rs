@@ -0,0 +1,149 @@
+use crate::*;
+
+/**
+ * @notice
+ * Checkpoint is the staked balance of an account, or of the pool, from a block on
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Checkpoint {
+    pub block_height: u64,
+    pub timestamp: u64,
+    pub balance: U128,
+}
+
+fn push_checkpoint(checkpoints: &mut Vector<Checkpoint>, balance: Balance) {
+    let checkpoint = Checkpoint {
+        block_height: env::block_height(),
+        timestamp: env::block_timestamp_ms(),
+        balance: U128(balance),
+    };
+    // several changes in one block keep only the last balance
+    match checkpoints.len().checked_sub(1) {
+        Some(last) if checkpoints.get(last).unwrap().block_height == checkpoint.block_height => {
+            checkpoints.replace(last, &checkpoint);
+        }
+        _ => checkpoints.push(&checkpoint),
+    }
+}
+
+/**
+ * @notice
+ * Binary search for the last checkpoint at or before the block, None if the first one is after it
+ */
+fn checkpoint_at(checkpoints: &Vector<Checkpoint>, block_height: u64) -> Option<Checkpoint> {
+    let (mut low, mut high) = (0, checkpoints.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        if checkpoints.get(middle).unwrap().block_height <= block_height {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low.checked_sub(1).and_then(|index| checkpoints.get(index))
+}
+
+impl Stakeable {
+    fn _account_checkpoints(&self, account_id: &AccountId) -> Option<Vector<Checkpoint>> {
+        self.account_checkpoints.get(account_id)
+    }
+
+    /**
+     * @notice
+     * _write_checkpoints records the new balance of the account and of the pool,
+     * the storage they take is charged to the account
+     */
+    pub(crate) fn _write_checkpoints(&mut self, account_id: &AccountId, balance: Balance) {
+        let initial_storage = env::storage_usage();
+        let mut checkpoints = self._account_checkpoints(account_id).unwrap_or_else(|| {
+            let mut prefix = b"ac".to_vec();
+            prefix.extend(env::sha256(account_id.as_bytes()));
+            Vector::new(prefix)
+        });
+        push_checkpoint(&mut checkpoints, balance);
+        self.account_checkpoints.insert(account_id, &checkpoints);
+        push_checkpoint(&mut self.total_checkpoints, self.total_staked);
+        self._charge_storage(account_id, initial_storage);
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * readonly
+     * staked_balance_at returns the staked balance of the account at the end of the block,
+     * 0 for an account without checkpoints like total_staked_at before the first one.
+     * Records older than the checkpoints get theirs from backfill_stakeholders
+     */
+    pub fn staked_balance_at(&self, account_id: AccountId, block_height: u64) -> U128 {
+        U128(
+            self._account_checkpoints(&account_id)
+                .and_then(|checkpoints| checkpoint_at(&checkpoints, block_height))
+                .map(|checkpoint| checkpoint.balance.0)
+                .unwrap_or(0),
+        )
+    }
+
+    // * readonly
+    pub fn total_staked_at(&self, block_height: u64) -> U128 {
+        U128(
+            checkpoint_at(&self.total_checkpoints, block_height)
+                .map(|checkpoint| checkpoint.balance.0)
+                .unwrap_or(0),
+        )
+    }
+
+    // * readonly
+    pub fn get_account_checkpoints(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Checkpoint> {
+        let checkpoints = match self._account_checkpoints(&account_id) {
+            Some(checkpoints) => checkpoints,
+            None => return vec![],
+        };
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        (from_index..checkpoints.len().min(from_index.saturating_add(limit)))
+            .filter_map(|index| checkpoints.get(index))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_staked_balance_checkpoints() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        stake_at_block(&mut contract, accounts(2), AMOUNT, 10);
+        stake_at_block(&mut contract, accounts(3), AMOUNT, 10);
+        stake_at_block(&mut contract, accounts(2), 2 * AMOUNT, 20);
+        at_block(accounts(2), 30);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+
+        assert_eq!(contract.staked_balance_at(accounts(2), 9).0, 0);
+        assert_eq!(contract.staked_balance_at(accounts(2), 10).0, AMOUNT);
+        assert_eq!(contract.staked_balance_at(accounts(2), 25).0, 3 * AMOUNT);
+        assert_eq!(contract.staked_balance_at(accounts(2), 30).0, 2 * AMOUNT);
+        assert_eq!(contract.total_staked_at(9).0, 0);
+        assert_eq!(contract.total_staked_at(10).0, 2 * AMOUNT);
+        assert_eq!(contract.total_staked_at(20).0, 4 * AMOUNT);
+        assert_eq!(contract.total_staked_at(u64::MAX).0, 3 * AMOUNT);
+        assert_eq!(
+            contract
+                .get_account_checkpoints(accounts(2), None, None)
+                .len(),
+            3
+        );
+    }
+}
@@ -1,22 +1,62 @@
 use crate::*;
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
-#[serde(crate = "near_sdk::serde")]
-pub struct Config {
-    pub reward_numerator: u32,
-    pub reward_denumerator: u32,
-    pub decimals: u32,
+impl Stakeable {
+    /**
+     * @notice
+     * _set_rates changes the reward rate or the boost with update. The rates it replaces are kept in rate_history
+     * until now, so what every stake accrued so far keeps its rate without settling each stakeholder
+     */
+    pub(crate) fn _set_rates(&mut self, update: impl FnOnce(&mut Config)) {
+        let previous = RatePeriod::of(&self.config, env::block_timestamp_ms());
+        update(&mut self.config);
+        let start = self.rate_history.last().map_or(0, |period| period.end);
+        if previous.end > start && !previous.same_rates(&RatePeriod::of(&self.config, previous.end))
+        {
+            self.rate_history.push(previous);
+        }
+    }
+
+    /**
+     * @notice
+     * _accrued_reward is what stake accrued from its since to end at the rates of the time, claimable not included.
+     * Paused time of its token earns nothing, boost_bps is the boost of the staker in a rate period
+     */
+    pub(crate) fn _accrued_reward(
+        &self,
+        stake: &Stake,
+        end: u64,
+        boost_bps: impl Fn(&RatePeriod) -> u16,
+    ) -> Balance {
+        let solvency = self.token_solvency.get(&stake.token_id);
+        history_reward(
+            stake.amount.0,
+            stake.since,
+            end,
+            &self.rate_history,
+            &self.config,
+            |t| {
+                solvency
+                    .as_ref()
+                    .map_or(t, |solvency| solvency.active_time(t))
+            },
+            boost_bps,
+        )
+    }
 }
 
-// APR 15% = (token_stacking * 15/100) * total_block
-// Moi block se tra thuong 715 / 1_000_000_000 --> tinh ra duoc thanh APR 15%
+#[near_bindgen]
+impl Stakeable {
+    // * readonly
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            reward_numerator: 15,
-            reward_denumerator: 1000,
-            decimals: 24,
-        }
+    /**
+     * @notice
+     * readonly
+     * get_rate_history is every reward rate and boost replaced so far with the time it ended, oldest first
+     */
+    pub fn get_rate_history(&self) -> Vec<RatePeriod> {
+        self.rate_history.clone()
     }
 }
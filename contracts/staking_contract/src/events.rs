@@ -0,0 +1,488 @@
+use crate::*;
+use near_sdk::serde_json::json;
+
+/**
+ * @notice
+ * Events are logged in the NEP-297 format, the same `EVENT_JSON:` logs nolan_token emits for ft_mint and ft_transfer
+ */
+pub const EVENT_STANDARD: &str = "nolan_staking";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+pub(crate) fn emit_event<T: Serialize>(event: &str, data: T) {
+    let log = json!({
+        "standard": EVENT_STANDARD,
+        "version": EVENT_STANDARD_VERSION,
+        "event": event,
+        "data": [data],
+    });
+    log_str(&format!("EVENT_JSON:{}", log));
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractUpgraded {
+    pub code_hash: String,
+}
+
+impl ContractUpgraded {
+    pub fn emit(self) {
+        emit_event("contract_upgraded", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractUpgradeFailed {
+    pub code_hash: String,
+}
+
+impl ContractUpgradeFailed {
+    pub fn emit(self) {
+        emit_event("contract_upgrade_failed", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralBound {
+    pub account_id: AccountId,
+    pub referrer_id: AccountId,
+}
+
+impl ReferralBound {
+    pub fn emit(self) {
+        emit_event("referral_bound", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralRewardCredited {
+    pub referrer_id: AccountId,
+    pub referee_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+impl ReferralRewardCredited {
+    pub fn emit(self) {
+        emit_event("referral_reward_credited", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralRewardPaid {
+    pub referrer_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+impl ReferralRewardPaid {
+    pub fn emit(self) {
+        emit_event("referral_reward_paid", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BoostActivated {
+    pub account_id: AccountId,
+    pub nft_token_id: String,
+    pub escrowed: bool,
+}
+
+impl BoostActivated {
+    pub fn emit(self) {
+        emit_event("boost_activated", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BoostLapsed {
+    pub account_id: AccountId,
+    pub nft_token_id: String,
+}
+
+impl BoostLapsed {
+    pub fn emit(self) {
+        emit_event("boost_lapsed", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VeLocked {
+    pub account_id: AccountId,
+    pub amount: U128,
+    pub end: u64,
+}
+
+impl VeLocked {
+    pub fn emit(self) {
+        emit_event("ve_locked", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VeDelegated {
+    pub account_id: AccountId,
+    pub delegate_id: Option<AccountId>,
+}
+
+impl VeDelegated {
+    pub fn emit(self) {
+        emit_event("ve_delegated", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VeWithdrawn {
+    pub account_id: AccountId,
+    pub amount: U128,
+}
+
+impl VeWithdrawn {
+    pub fn emit(self) {
+        emit_event("ve_withdrawn", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalCreated {
+    pub proposal_id: u64,
+    pub proposer: AccountId,
+}
+
+impl ProposalCreated {
+    pub fn emit(self) {
+        emit_event("proposal_created", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoteCast {
+    pub proposal_id: u64,
+    pub account_id: AccountId,
+    pub vote: Vote,
+    pub weight: U128,
+}
+
+impl VoteCast {
+    pub fn emit(self) {
+        emit_event("vote_cast", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalExecuted {
+    pub proposal_id: u64,
+}
+
+impl ProposalExecuted {
+    pub fn emit(self) {
+        emit_event("proposal_executed", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeCollected {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    // deposit, withdraw or reward
+    pub kind: String,
+    pub amount: U128,
+}
+
+impl FeeCollected {
+    pub fn emit(self) {
+        emit_event("fee_collected", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryWithdrawn {
+    pub receiver_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+impl TreasuryWithdrawn {
+    pub fn emit(self) {
+        emit_event("treasury_withdrawn", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawSettled {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub principal: U128,
+    pub reward: U128,
+    pub fees: U128,
+    pub payout: U128,
+}
+
+impl WithdrawSettled {
+    pub fn emit(self) {
+        emit_event("withdraw_settled", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawRolledBack {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub principal: U128,
+    pub reward: U128,
+}
+
+impl WithdrawRolledBack {
+    pub fn emit(self) {
+        emit_event("withdraw_rolled_back", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardVested {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub end: u64,
+}
+
+impl RewardVested {
+    pub fn emit(self) {
+        emit_event("reward_vested", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardForfeited {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+impl RewardForfeited {
+    pub fn emit(self) {
+        emit_event("reward_forfeited", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingReleased {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+impl VestingReleased {
+    pub fn emit(self) {
+        emit_event("vesting_released", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropCreated {
+    pub campaign_id: u64,
+    pub token_id: AccountId,
+    pub total: U128,
+    pub expires_at: u64,
+}
+
+impl AirdropCreated {
+    pub fn emit(self) {
+        emit_event("airdrop_created", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropClaimed {
+    pub campaign_id: u64,
+    pub account_id: AccountId,
+    pub index: u64,
+    pub amount: U128,
+    pub staked: bool,
+}
+
+impl AirdropClaimed {
+    pub fn emit(self) {
+        emit_event("airdrop_claimed", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropSwept {
+    pub campaign_id: u64,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+}
+
+impl AirdropSwept {
+    pub fn emit(self) {
+        emit_event("airdrop_swept", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeCreated {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub since: u64,
+    pub locked_until: u64,
+}
+
+impl StakeCreated {
+    pub fn emit(self) {
+        emit_event("stake_created", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeIncreased {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+    // reward settled on the stake before amount was added
+    pub claimable: U128,
+}
+
+impl StakeIncreased {
+    pub fn emit(self) {
+        emit_event("stake_increased", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeWithdrawn {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub reward: U128,
+    // the stake is removed when nothing remains, the following stakes move down one index
+    pub remaining: U128,
+}
+
+impl StakeWithdrawn {
+    pub fn emit(self) {
+        emit_event("stake_withdrawn", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeRestored {
+    pub account_id: AccountId,
+    // the principal is pushed as a new stake, so this is the number of stakes before
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub principal: U128,
+    pub reward: U128,
+    pub locked_until: u64,
+}
+
+impl StakeRestored {
+    pub fn emit(self) {
+        emit_event("stake_restored", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenStatusChanged {
+    pub token_id: AccountId,
+    pub status: TokenStatus,
+}
+
+impl TokenStatusChanged {
+    pub fn emit(self) {
+        emit_event("token_status_changed", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenAccountRegistered {
+    pub token_id: AccountId,
+    pub account_id: AccountId,
+    pub deposit: U128,
+    pub payer: RegistrationPayer,
+}
+
+impl TokenAccountRegistered {
+    pub fn emit(self) {
+        emit_event("token_account_registered", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SolvencyChecked {
+    pub token_id: AccountId,
+    pub balance: U128,
+    pub principal: U128,
+    pub accrued_rewards: U128,
+    pub reserved: U128,
+    pub liabilities: U128,
+    pub underfunded: bool,
+}
+
+impl SolvencyChecked {
+    pub fn emit(self) {
+        emit_event("solvency_checked", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProRataPayout {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub paid: U128,
+    pub shortfall: U128,
+}
+
+impl ProRataPayout {
+    pub fn emit(self) {
+        emit_event("pro_rata_payout", self);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ShortfallPaid {
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+impl ShortfallPaid {
+    pub fn emit(self) {
+        emit_event("shortfall_paid", self);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::*;
+
+pub const ON_TREASURY_WITHDRAW_GAS: Gas = Gas(10_000_000_000_000);
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositQuote {
+    pub amount: U128,
+    pub fee: U128,
+    pub staked: U128,
+}
+
+/**
+ * @notice
+ * Withdrawal is everything a withdrawal of a stake pays out, computed by _compute_withdrawal
+ */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Withdrawal {
+    pub token_id: AccountId,
+    pub principal: U128,
+    pub principal_fee: U128,
+    pub reward: U128,
+    pub reward_fee: U128,
+    // paid to the referrer on top of the reward
+    pub referral_reward: U128,
+    // principal + reward - fees, what the staker receives
+    pub payout: U128,
+    // principal left in the stake afterwards
+    pub remaining: U128,
+    // lock of the stake, kept if the principal is restored
+    pub locked_until: u64,
+}
+
+impl Stakeable {
+    pub(crate) fn _set_fee_config(&mut self, fees: FeeConfig) {
+        fees.assert_valid();
+        self.config.fees = fees;
+    }
+
+    pub(crate) fn _collect_fee(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        kind: &str,
+        amount: Balance,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let balance = self.treasury.get(token_id).unwrap_or(0);
+        self.treasury.insert(token_id, &(balance + amount));
+        FeeCollected {
+            account_id: account_id.clone(),
+            token_id: token_id.clone(),
+            kind: kind.to_string(),
+            amount: U128(amount),
+        }
+        .emit();
+    }
+
+    /**
+     * @notice
+     * _refund_fee takes back fees collected by a withdrawal that was rolled back
+     */
+    pub(crate) fn _refund_fee(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        let balance = self.treasury.get(token_id).unwrap_or(0);
+        self.treasury
+            .insert(token_id, &balance.saturating_sub(amount));
+    }
+
+    pub(crate) fn _quote_deposit(&self, amount: Balance) -> DepositQuote {
+        let fee = fee_of(amount, self.config.fees.deposit_fee_bps);
+        DepositQuote {
+            amount: U128(amount),
+            fee: U128(fee),
+            staked: U128(amount - fee),
+        }
+    }
+
+    /**
+     * @notice
+     * _compute_withdrawal works out a withdrawal of amount from a stake without changing anything
+     */
+    pub(crate) fn _compute_withdrawal(
+        &self,
+        stakeholder: &StakeHolder,
+        index: usize,
+        amount: Balance,
+    ) -> Result<Withdrawal, String> {
+        let stake = stakeholder
+            .address_stakes
+            .get(index)
+            .ok_or_else(|| format!("Staking: Stake {} does not exist", index))?;
+        if stake.amount.0 < amount {
+            return Err("Staking: Cannot withdraw more than you have staked".to_string());
+        }
+        if amount > 0 && env::block_timestamp_ms() < stake.locked_until {
+            return Err(format!(
+                "Staking: Stake {} is locked until {}",
+                index, stake.locked_until
+            ));
+        }
+        let reward = self
+            ._calculate_reward(stake, stakeholder.boost_collection())
+            .0;
+        let principal_fee = fee_of(amount, self.config.fees.withdraw_fee_bps);
+        let reward_fee = fee_of(reward, self.config.fees.reward_fee_bps);
+        let referral_reward = self._referral_share(stakeholder, reward);
+        Ok(Withdrawal {
+            token_id: stake.token_id.clone(),
+            principal: U128(amount),
+            principal_fee: U128(principal_fee),
+            reward: U128(reward),
+            reward_fee: U128(reward_fee),
+            referral_reward: U128(referral_reward),
+            payout: U128(amount - principal_fee + reward - reward_fee),
+            remaining: U128(stake.amount.0 - amount),
+            locked_until: stake.locked_until,
+        })
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    pub fn set_fee_config(&mut self, fees: FeeConfig) {
+        self.assert_owner_before_staking();
+        self._set_fee_config(fees);
+    }
+
+    // * readonly
+    pub fn get_fee_config(&self) -> FeeConfig {
+        self.config.fees.clone()
+    }
+
+    // * readonly
+    pub fn get_treasury_balances(&self) -> Vec<(AccountId, U128)> {
+        self.treasury
+            .iter()
+            .map(|(token_id, balance)| (token_id, U128(balance)))
+            .collect()
+    }
+
+    /**
+     * @notice
+     * readonly
+     * quote_deposit shows the deposit fee and the amount that would be staked
+     */
+    pub fn quote_deposit(&self, amount: U128) -> DepositQuote {
+        self._quote_deposit(amount.0)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * quote_withdraw shows the fees and the payout of withdraw_stake with the same arguments
+     */
+    pub fn quote_withdraw(
+        &self,
+        account_id: AccountId,
+        stake_index: usize,
+        amount: U128,
+    ) -> Withdrawal {
+        let stakeholder = self
+            ._get_stakeholder(&account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: Account has no stakes"));
+        self._compute_withdrawal(&stakeholder, stake_index, amount.0)
+            .unwrap_or_else(|reason| env::panic_str(reason.as_str()))
+    }
+
+    /**
+     * @notice
+     * withdraw_treasury transfers collected fees of a token to the treasury account, cut pro rata when the token is underfunded,
+     * the balance is restored in on_treasury_withdraw if the transfer fails
+     */
+    pub fn withdraw_treasury(&mut self, token_id: AccountId, amount: Option<U128>) -> Promise {
+        self.assert_owner();
+        let balance = self.treasury.get(&token_id).unwrap_or(0);
+        let amount = amount.map(|amount| amount.0).unwrap_or(balance);
+        assert!(
+            amount > 0 && amount <= balance,
+            "Staking: Not enough fees in the treasury"
+        );
+        self.treasury.insert(&token_id, &(balance - amount));
+        let receiver_id = self
+            .config
+            .fees
+            .treasury_id
+            .clone()
+            .unwrap_or_else(|| self.owner_id.clone());
+        let paid = self._pro_rata_payout(&receiver_id, &token_id, amount);
+        self._register_on_token(&token_id, &receiver_id)
+            .then(
+                ext_ft::ext(token_id.clone())
+                    .with_static_gas(FT_TRANSFER_GAS)
+                    .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                    .ft_transfer(
+                        receiver_id.to_string(),
+                        paid.to_string(),
+                        Some("treasury".to_string()),
+                    ),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_TREASURY_WITHDRAW_GAS)
+                    .on_treasury_withdraw(receiver_id, token_id, U128(amount), U128(paid)),
+            )
+    }
+
+    #[private]
+    pub fn on_treasury_withdraw(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        paid: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                TreasuryWithdrawn {
+                    receiver_id,
+                    token_id,
+                    amount: paid,
+                }
+                .emit();
+                paid
+            }
+            _ => {
+                log_str("Staking: Treasury transfer failed, the fees are restored");
+                self._remove_shortfall(&receiver_id, &token_id, amount.0 - paid.0);
+                let balance = self.treasury.get(&token_id).unwrap_or(0);
+                self.treasury.insert(&token_id, &(balance + amount.0));
+                U128(0)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    #[should_panic(expected = "Staking: Tokens are staked, change this setting with a proposal")]
+    fn test_owner_setters_need_a_proposal_once_staked() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_fee_config(FeeConfig {
+            reward_fee_bps: 1_000,
+            ..FeeConfig::default()
+        });
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(1), 0);
+        contract.set_fee_config(FeeConfig::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Withdraw fee cannot be more than 300 bps")]
+    fn test_fee_cap() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_fee_config(FeeConfig {
+            withdraw_fee_bps: MAX_WITHDRAW_FEE_BPS + 1,
+            ..FeeConfig::default()
+        });
+    }
+
+    #[test]
+    fn test_fees_and_withdraw_treasury() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_fee_config(FeeConfig {
+            deposit_fee_bps: 100,
+            treasury_id: Some(accounts(4)),
+            ..FeeConfig::default()
+        });
+        stake(&mut contract, accounts(2), 100 * AMOUNT, 0);
+        assert_eq!(contract.has_stake(accounts(2)).total_amount.0, 99 * AMOUNT);
+        assert_eq!(
+            contract.get_treasury_balances(),
+            vec![(token(), U128(AMOUNT))]
+        );
+
+        call_at(accounts(1), 0);
+        contract.withdraw_treasury(token(), None);
+        assert_eq!(contract.get_treasury_balances(), vec![(token(), U128(0))]);
+        // a failed transfer puts the fees back
+        callback_at(0, PromiseResult::Failed);
+        assert_eq!(
+            contract
+                .on_treasury_withdraw(accounts(4), token(), U128(AMOUNT), U128(AMOUNT))
+                .0,
+            0
+        );
+        assert_eq!(
+            contract.get_treasury_balances(),
+            vec![(token(), U128(AMOUNT))]
+        );
+        callback_at(0, PromiseResult::Successful(vec![]));
+        assert_eq!(
+            contract
+                .on_treasury_withdraw(accounts(4), token(), U128(AMOUNT), U128(AMOUNT))
+                .0,
+            AMOUNT
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use crate::*;
+use near_sdk::collections::Vector;
+
+/**
+ * @notice
+ * Config fields a proposal can change, fields left out keep their value.
+ * A cap of 0 removes the cap
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConfigUpdate {
+    pub reward_numerator: Option<u32>,
+    pub reward_denumerator: Option<u32>,
+    pub min_stake: Option<U128>,
+    pub max_stake_per_account: Option<U128>,
+    pub pool_capacity: Option<U128>,
+    pub referral_bps: Option<u16>,
+    pub boost_bps: Option<u16>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProposalKind {
+    UpdateConfig {
+        update: ConfigUpdate,
+    },
+    AllowToken {
+        token_id: AccountId,
+    },
+    RemoveToken {
+        token_id: AccountId,
+    },
+    UpdateGovernance {
+        governance: GovernanceConfig,
+    },
+    UpdateFees {
+        fees: FeeConfig,
+    },
+    UpdateVe {
+        ve_token_id: Option<AccountId>,
+        max_lock_duration: u64,
+    },
+    UpdateVesting {
+        vesting: VestingConfig,
+    },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ProposalStatus {
+    Active,
+    Succeeded,
+    Defeated,
+    Executed,
+    Cancelled,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Vote {
+    Yes,
+    No,
+    Abstain,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: AccountId,
+    pub description: String,
+    pub kind: ProposalKind,
+    // votes are weighted by the stake each account had at the end of this block
+    pub snapshot_block: u64,
+    pub total_staked_at_snapshot: U128,
+    // thresholds of the governance config when the proposal was created, later changes do not apply to it
+    pub quorum_bps: u16,
+    pub pass_threshold_bps: u16,
+    pub voting_ends_at: u64,
+    pub executable_at: u64,
+    pub yes: U128,
+    pub no: U128,
+    pub abstain: U128,
+    pub executed: bool,
+    pub cancelled: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoteRecord {
+    pub account_id: AccountId,
+    pub vote: Vote,
+    pub weight: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalView {
+    #[serde(flatten)]
+    pub proposal: Proposal,
+    pub status: ProposalStatus,
+}
+
+impl Proposal {
+    pub fn status(&self, now: u64) -> ProposalStatus {
+        if self.cancelled {
+            return ProposalStatus::Cancelled;
+        }
+        if self.executed {
+            return ProposalStatus::Executed;
+        }
+        if now < self.voting_ends_at {
+            return ProposalStatus::Active;
+        }
+        let turnout = self.yes.0 + self.no.0 + self.abstain.0;
+        let quorum = self.total_staked_at_snapshot.0 * self.quorum_bps as u128 / BPS_DENOMINATOR;
+        let passed = turnout > 0
+            && turnout >= quorum
+            && self.yes.0 * BPS_DENOMINATOR
+                > (self.yes.0 + self.no.0) * self.pass_threshold_bps as u128;
+        if passed {
+            ProposalStatus::Succeeded
+        } else {
+            ProposalStatus::Defeated
+        }
+    }
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _voting_weight is the stake of the account at the snapshot block, from its balance checkpoints
+     */
+    pub(crate) fn _voting_weight(&self, account_id: &AccountId, snapshot_block: u64) -> Balance {
+        self.staked_balance_at(account_id.clone(), snapshot_block).0
+    }
+
+    fn _get_proposal(&self, proposal_id: u64) -> Proposal {
+        self.proposals
+            .get(proposal_id)
+            .unwrap_or_else(|| env::panic_str("Staking: Proposal does not exist"))
+    }
+
+    fn _voters(&self, proposal_id: u64) -> Vector<AccountId> {
+        self.proposal_voters.get(&proposal_id).unwrap_or_else(|| {
+            let mut prefix = b"pv".to_vec();
+            prefix.extend(proposal_id.to_le_bytes());
+            Vector::new(prefix)
+        })
+    }
+
+    fn _set_governance_config(&mut self, governance: GovernanceConfig) {
+        assert!(
+            governance.quorum_bps as u128 <= BPS_DENOMINATOR
+                && governance.pass_threshold_bps as u128 <= BPS_DENOMINATOR,
+            "Staking: Basis points cannot be more than 10000"
+        );
+        self.config.governance = governance;
+    }
+
+    /**
+     * @notice
+     * _apply_config_update checks the new values with the same limits as the owner setters.
+     * A new reward rate or boost goes through _set_rates so what stakes accrued before keeps its rate
+     */
+    fn _apply_config_update(&mut self, update: ConfigUpdate) {
+        if let Some(reward_denumerator) = update.reward_denumerator {
+            assert!(reward_denumerator > 0, "Staking: Denominator cannot be 0");
+        }
+        if let Some(boost_bps) = update.boost_bps {
+            assert!(boost_bps <= MAX_BOOST_BPS, "Staking: Boost is too high");
+        }
+        self._set_rates(|config| {
+            if let Some(reward_numerator) = update.reward_numerator {
+                config.reward_numerator = reward_numerator;
+            }
+            if let Some(reward_denumerator) = update.reward_denumerator {
+                config.reward_denumerator = reward_denumerator;
+            }
+            if let Some(boost_bps) = update.boost_bps {
+                config.boost_bps = boost_bps;
+            }
+        });
+        if let Some(min_stake) = update.min_stake {
+            assert!(min_stake.0 > 0, "Staking: Minimum stake must be positive");
+            self.config.min_stake = min_stake;
+        }
+        if let Some(max_stake_per_account) = update.max_stake_per_account {
+            self.config.max_stake_per_account = Some(max_stake_per_account).filter(|max| max.0 > 0);
+        }
+        if let Some(pool_capacity) = update.pool_capacity {
+            self.config.pool_capacity = Some(pool_capacity).filter(|capacity| capacity.0 > 0);
+        }
+        if let Some(referral_bps) = update.referral_bps {
+            assert!(
+                referral_bps <= MAX_REFERRAL_BPS,
+                "Staking: Referral share is too high"
+            );
+            self.config.referral_bps = referral_bps;
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * Like the fee, ve and vesting setters, the owner can only call it until the first stake, then it takes a proposal
+     */
+    pub fn set_governance_config(&mut self, governance: GovernanceConfig) {
+        self.assert_owner_before_staking();
+        self._set_governance_config(governance);
+    }
+
+    // * readonly
+    pub fn get_governance_config(&self) -> GovernanceConfig {
+        self.config.governance.clone()
+    }
+
+    /**
+     * @notice
+     * create_proposal can be called by accounts with at least proposal_threshold staked, returns the proposal id
+     */
+    pub fn create_proposal(&mut self, description: String, kind: ProposalKind) -> u64 {
+        let proposer = env::predecessor_account_id();
+        let now = env::block_timestamp_ms();
+        let staked = self
+            ._get_stakeholder(&proposer)
+            .map(|stakeholder| self._normalized_total(&stakeholder))
+            .unwrap_or(0);
+        assert!(
+            staked > 0 && staked >= self.config.governance.proposal_threshold.0,
+            "Staking: Not enough staked to create a proposal"
+        );
+        let id = self.proposals.len();
+        let voting_ends_at = now + self.config.governance.voting_period;
+        let proposal = Proposal {
+            id,
+            proposer: proposer.clone(),
+            description,
+            kind,
+            snapshot_block: env::block_height(),
+            total_staked_at_snapshot: U128(self.total_staked),
+            quorum_bps: self.config.governance.quorum_bps,
+            pass_threshold_bps: self.config.governance.pass_threshold_bps,
+            voting_ends_at,
+            executable_at: voting_ends_at + self.config.governance.execution_delay,
+            yes: U128(0),
+            no: U128(0),
+            abstain: U128(0),
+            executed: false,
+            cancelled: false,
+        };
+        self.proposals.push(&proposal);
+        ProposalCreated {
+            proposal_id: id,
+            proposer,
+        }
+        .emit();
+        id
+    }
+
+    /**
+     * @notice
+     * vote records the vote of the caller with its stake at the snapshot, an account votes once
+     */
+    pub fn vote(&mut self, proposal_id: u64, vote: Vote) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let mut proposal = self._get_proposal(proposal_id);
+        assert_eq!(
+            proposal.status(env::block_timestamp_ms()),
+            ProposalStatus::Active,
+            "Staking: Proposal is not open for voting"
+        );
+        let key = (proposal_id, account_id.clone());
+        assert!(
+            self.proposal_votes.get(&key).is_none(),
+            "Staking: Already voted"
+        );
+        let weight = self._voting_weight(&account_id, proposal.snapshot_block);
+        assert!(weight > 0, "Staking: No stake at the proposal snapshot");
+        match vote {
+            Vote::Yes => proposal.yes.0 += weight,
+            Vote::No => proposal.no.0 += weight,
+            Vote::Abstain => proposal.abstain.0 += weight,
+        }
+        self.proposals.replace(proposal_id, &proposal);
+        self.proposal_votes.insert(
+            &key,
+            &VoteRecord {
+                account_id: account_id.clone(),
+                vote,
+                weight: U128(weight),
+            },
+        );
+        let mut voters = self._voters(proposal_id);
+        voters.push(&account_id);
+        self.proposal_voters.insert(&proposal_id, &voters);
+        VoteCast {
+            proposal_id,
+            account_id,
+            vote,
+            weight: U128(weight),
+        }
+        .emit();
+        U128(weight)
+    }
+
+    /**
+     * @notice
+     * execute_proposal applies a proposal that passed once its execution delay is over, anyone can call it.
+     * Token proposals go through the same steps as allow_token and disallow_token, an AllowToken proposal
+     * needs the gas of allow_token and its registration is paid by the storage reserve
+     */
+    pub fn execute_proposal(&mut self, proposal_id: u64) {
+        let mut proposal = self._get_proposal(proposal_id);
+        let now = env::block_timestamp_ms();
+        assert_eq!(
+            proposal.status(now),
+            ProposalStatus::Succeeded,
+            "Staking: Proposal has not passed"
+        );
+        assert!(
+            now >= proposal.executable_at,
+            "Staking: Proposal cannot be executed before {}",
+            proposal.executable_at
+        );
+        match proposal.kind.clone() {
+            ProposalKind::UpdateConfig { update } => self._apply_config_update(update),
+            ProposalKind::AllowToken { token_id } => {
+                self._allow_token(token_id);
+            }
+            ProposalKind::RemoveToken { token_id } => self._disallow_token(&token_id),
+            ProposalKind::UpdateGovernance { governance } => {
+                self._set_governance_config(governance)
+            }
+            ProposalKind::UpdateFees { fees } => self._set_fee_config(fees),
+            ProposalKind::UpdateVe {
+                ve_token_id,
+                max_lock_duration,
+            } => self._set_ve_config(ve_token_id, max_lock_duration),
+            ProposalKind::UpdateVesting { vesting } => self._set_vesting_config(vesting),
+        }
+        proposal.executed = true;
+        self.proposals.replace(proposal_id, &proposal);
+        ProposalExecuted { proposal_id }.emit();
+    }
+
+    /**
+     * @notice
+     * cancel_proposal stops an active proposal, it can be done by the proposer or the owner
+     */
+    pub fn cancel_proposal(&mut self, proposal_id: u64) {
+        let mut proposal = self._get_proposal(proposal_id);
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == proposal.proposer || caller == self.owner_id,
+            "Staking: Only the proposer or the owner can cancel"
+        );
+        assert_eq!(
+            proposal.status(env::block_timestamp_ms()),
+            ProposalStatus::Active,
+            "Staking: Only active proposals can be cancelled"
+        );
+        proposal.cancelled = true;
+        self.proposals.replace(proposal_id, &proposal);
+    }
+
+    // * readonly
+    pub fn get_proposal(&self, proposal_id: u64) -> Option<ProposalView> {
+        let now = env::block_timestamp_ms();
+        self.proposals
+            .get(proposal_id)
+            .map(|proposal| ProposalView {
+                status: proposal.status(now),
+                proposal,
+            })
+    }
+
+    // * readonly
+    pub fn get_proposals(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<ProposalView> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        (from_index..self.proposals.len().min(from_index.saturating_add(limit)))
+            .filter_map(|proposal_id| self.get_proposal(proposal_id))
+            .collect()
+    }
+
+    // * readonly
+    pub fn get_proposal_count(&self) -> u64 {
+        self.proposals.len()
+    }
+
+    // * readonly
+    pub fn get_vote(&self, proposal_id: u64, account_id: AccountId) -> Option<VoteRecord> {
+        self.proposal_votes.get(&(proposal_id, account_id))
+    }
+
+    // * readonly
+    pub fn get_proposal_votes(
+        &self,
+        proposal_id: u64,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<VoteRecord> {
+        let voters = self._voters(proposal_id);
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        (from_index..voters.len().min(from_index.saturating_add(limit)))
+            .filter_map(|index| voters.get(index))
+            .filter_map(|account_id| self.proposal_votes.get(&(proposal_id, account_id)))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_proposal_keeps_its_thresholds() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(3), 3 * AMOUNT, 0);
+        call_at(accounts(2), 0);
+        let governance = GovernanceConfig {
+            quorum_bps: 10_000,
+            ..contract.get_governance_config()
+        };
+        let raise = contract.create_proposal(
+            "Every stake has to vote".to_string(),
+            ProposalKind::UpdateGovernance { governance },
+        );
+        let fees = FeeConfig {
+            reward_fee_bps: 1_000,
+            ..FeeConfig::default()
+        };
+        let charge = contract.create_proposal(
+            "Take a reward fee".to_string(),
+            ProposalKind::UpdateFees { fees },
+        );
+        contract.vote(charge, Vote::Yes);
+        call_at(accounts(3), 0);
+        contract.vote(raise, Vote::Yes);
+
+        let executable_at = contract.get_proposal(raise).unwrap().proposal.executable_at;
+        call_at(accounts(1), executable_at);
+        contract.execute_proposal(raise);
+        assert_eq!(contract.get_governance_config().quorum_bps, 10_000);
+        // a quarter of the stake voted on the fee, enough for the quorum it was created with
+        let view = contract.get_proposal(charge).unwrap();
+        assert_eq!(view.proposal.quorum_bps, 1_000);
+        assert_eq!(view.status, ProposalStatus::Succeeded);
+        contract.execute_proposal(charge);
+        assert_eq!(contract.get_fee_config().reward_fee_bps, 1_000);
+    }
+
+    #[test]
+    fn test_token_proposals_follow_the_token_lifecycle() {
+        let mut contract = setup();
+        let other: AccountId = "other.testnet".parse().unwrap();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), 0);
+        let allow = contract.create_proposal(
+            "Allow other".to_string(),
+            ProposalKind::AllowToken {
+                token_id: other.clone(),
+            },
+        );
+        let remove = contract.create_proposal(
+            "Close token".to_string(),
+            ProposalKind::RemoveToken { token_id: token() },
+        );
+        contract.vote(allow, Vote::Yes);
+        contract.vote(remove, Vote::Yes);
+
+        let executable_at = contract.get_proposal(allow).unwrap().proposal.executable_at;
+        call_at(accounts(3), executable_at);
+        contract.execute_proposal(allow);
+        // the token is active once its metadata is read, like with allow_token
+        assert!(contract.get_token_status(other.clone()).is_none());
+        let metadata = near_sdk::serde_json::json!({
+            "spec": "ft-1.0.0",
+            "name": "Other Token",
+            "symbol": "OTH",
+            "decimals": 18,
+        });
+        callback_at(
+            executable_at,
+            PromiseResult::Successful(metadata.to_string().into_bytes()),
+        );
+        assert!(contract.on_token_metadata(other.clone()));
+        assert_eq!(contract.get_token_decimals(other.clone()), 18);
+        assert!(contract.get_token_status(other).unwrap().status == TokenStatus::Active);
+
+        call_at(accounts(3), executable_at);
+        contract.execute_proposal(remove);
+        // the stakes of a closed token can still be withdrawn
+        assert!(contract.get_token_status(token()).unwrap().status == TokenStatus::DepositsClosed);
+        assert_eq!(contract.has_stake(accounts(2)).total_amount.0, AMOUNT);
+    }
+
+    #[test]
+    fn test_governance_vote_and_execute() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(3), 2 * AMOUNT, 0);
+        call_at(accounts(2), 0);
+        let update = ConfigUpdate {
+            reward_numerator: Some(30),
+            ..ConfigUpdate::default()
+        };
+        let proposal_id = contract.create_proposal(
+            "Double the rewards".to_string(),
+            ProposalKind::UpdateConfig { update },
+        );
+        let view = contract.get_proposal(proposal_id).unwrap();
+        assert_eq!(view.status, ProposalStatus::Active);
+        assert_eq!(view.proposal.total_staked_at_snapshot.0, 3 * AMOUNT);
+        assert_eq!(contract.vote(proposal_id, Vote::Yes).0, AMOUNT);
+        call_at(accounts(3), 0);
+        assert_eq!(contract.vote(proposal_id, Vote::No).0, 2 * AMOUNT);
+        let votes = contract.get_proposal_votes(proposal_id, None, None);
+        assert_eq!(votes.len(), 2);
+        assert!(contract.get_vote(proposal_id, accounts(3)).unwrap().vote == Vote::No);
+
+        // the majority voted no
+        let voting_ends_at = contract
+            .get_proposal(proposal_id)
+            .unwrap()
+            .proposal
+            .voting_ends_at;
+        call_at(accounts(2), voting_ends_at);
+        assert_eq!(
+            contract.get_proposal(proposal_id).unwrap().status,
+            ProposalStatus::Defeated
+        );
+
+        call_at(accounts(3), voting_ends_at);
+        let update = ConfigUpdate {
+            reward_numerator: Some(30),
+            ..ConfigUpdate::default()
+        };
+        let proposal_id = contract.create_proposal(
+            "Double the rewards".to_string(),
+            ProposalKind::UpdateConfig { update },
+        );
+        contract.vote(proposal_id, Vote::Yes);
+        let executable_at = contract
+            .get_proposal(proposal_id)
+            .unwrap()
+            .proposal
+            .executable_at;
+        call_at(accounts(4), executable_at);
+        contract.execute_proposal(proposal_id);
+        assert_eq!(contract.config.reward_numerator, 30);
+        assert_eq!(
+            contract.get_proposal(proposal_id).unwrap().status,
+            ProposalStatus::Executed
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Already voted")]
+    fn test_governance_double_vote() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), 0);
+        let proposal_id = contract.create_proposal(
+            "Close".to_string(),
+            ProposalKind::RemoveToken { token_id: token() },
+        );
+        contract.vote(proposal_id, Vote::Yes);
+        contract.vote(proposal_id, Vote::No);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: No stake at the proposal snapshot")]
+    fn test_governance_vote_weight_is_the_snapshot() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        at_block(accounts(2), 1);
+        let proposal_id = contract.create_proposal(
+            "Close".to_string(),
+            ProposalKind::RemoveToken { token_id: token() },
+        );
+        // stakes made after the snapshot block do not vote
+        stake_at_block(&mut contract, accounts(3), AMOUNT, 2);
+        at_block(accounts(3), 2);
+        contract.vote(proposal_id, Vote::Yes);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Proposal cannot be executed before")]
+    fn test_governance_execution_delay() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), 0);
+        let proposal_id = contract.create_proposal(
+            "Close".to_string(),
+            ProposalKind::RemoveToken { token_id: token() },
+        );
+        contract.vote(proposal_id, Vote::Yes);
+        let voting_ends_at = contract
+            .get_proposal(proposal_id)
+            .unwrap()
+            .proposal
+            .voting_ends_at;
+        call_at(accounts(2), voting_ends_at);
+        contract.execute_proposal(proposal_id);
+    }
+}
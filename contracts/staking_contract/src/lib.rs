@@ -1,25 +1,76 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
 use near_sdk::collections::LookupSet;
+use near_sdk::collections::UnorderedMap;
+use near_sdk::collections::UnorderedSet;
+use near_sdk::collections::Vector;
 use near_sdk::env::log_str;
-use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, ext_contract, near_bindgen, AccountId, Balance, PromiseOrValue, PromiseResult,
+    assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Promise, PromiseOrValue,
+    PromiseResult,
 };
 use near_sdk::{Gas, PanicOnDefault};
+use staking_types::*;
 
+mod access;
+mod airdrop;
+mod allowlist;
+mod batch;
+mod boost;
+mod checkpoints;
 mod config;
-use crate::config::*;
+mod events;
+mod fees;
+mod governance;
+mod limits;
+mod message;
+mod migrate;
+mod preview;
+mod projection;
+mod referral;
+mod registration;
+mod solvency;
+mod storage;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test_utils;
+mod tokens;
+mod upgrade;
+mod ve;
+mod vesting;
+use crate::airdrop::*;
+use crate::allowlist::*;
+use crate::batch::*;
+pub use crate::batch::{SETTLEMENT_PAYOUT_GAS, WITHDRAW_STAKE_GAS};
+use crate::boost::*;
+use crate::checkpoints::*;
+use crate::events::*;
+use crate::fees::*;
+use crate::governance::*;
+use crate::message::*;
+use crate::migrate::*;
+use crate::preview::*;
+use crate::referral::*;
+pub use crate::registration::TOKEN_REGISTRATION_DEPOSIT;
+use crate::registration::*;
+use crate::solvency::*;
+pub use crate::tokens::ALLOW_TOKEN_GAS;
+use crate::tokens::*;
+use crate::ve::*;
+use crate::vesting::*;
+use std::collections::HashMap;
 
 pub const REWARD_PER_HOUR: usize = 1_000;
-pub const ONE_HOUR: u64 = 3600_000;
 pub const FT_TRANSFER_GAS: Gas = Gas(10_000_000_000_000);
+// gas clients attach to ft_transfer_call on the token to stake, for ft_on_transfer here and ft_resolve_transfer
+pub const STAKE_TRANSFER_CALL_GAS: Gas = Gas(100_000_000_000_000);
 
 pub const DEPOSIT_ONE_YOCTO: Balance = 1;
 pub const NO_DEPOSIT: Balance = 0;
 pub const FT_HARVEST_CALLBACK_GAS: Gas = Gas(10_000_000_000_000);
 
+#[allow(dead_code)]
 #[ext_contract(ext_ft)]
 trait FungibleToken {
     // change methods
@@ -42,22 +93,13 @@ trait FungibleToken {
     // view methods
     fn ft_total_supply(&self) -> String;
     fn ft_balance_of(&self, account_id: String) -> String;
-}
-
-/**
- * @notice
- * A stake struct is used to represent the way we store stakes,
- * A Stake will contain the users address, the amount staked and a timestamp,
- * Since which is when the stake was made
- */
-
-#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Clone)]
-#[serde(crate = "near_sdk::serde")]
-pub struct Stake {
-    address: AccountId, // address
-    amount: U128,       // amount of staked
-    since: u64,         // start
-    claimable: U128,
+    fn ft_metadata(&self) -> TokenMetadata;
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance;
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance>;
 }
 
 /**
@@ -67,17 +109,31 @@ pub struct Stake {
 pub struct StakeHolder {
     address: AccountId,
     address_stakes: Vec<Stake>,
+    // account credited with a share of the rewards this stakeholder claims, can only be set once
+    referrer: Option<AccountId>,
+    // NFT of the partner collection that boosts the rewards of this stakeholder
+    boost: Option<Boost>,
 }
 
-/**
- * @notice
- * StakingSummary is a struct that is used to contain all stakes performed by a certain account
- */
-#[derive(Clone, Serialize, Deserialize)]
-#[serde(crate = "near_sdk::serde")]
-pub struct StakingSummary {
-    total_amount: U128,
-    stakes: Vec<Stake>,
+impl StakeHolder {
+    // collection of the boost NFT, rewards are boosted in the rate periods of that collection
+    pub(crate) fn boost_collection(&self) -> Option<&AccountId> {
+        self.boost.as_ref().map(|boost| &boost.nft_contract_id)
+    }
+
+    /**
+     * @notice
+     * apply_withdrawal leaves the remaining principal in the stake and restarts its timer, an emptied stake is removed
+     */
+    pub(crate) fn apply_withdrawal(&mut self, index: usize, withdrawal: &Withdrawal) {
+        let stake = &mut self.address_stakes[index];
+        stake.amount = withdrawal.remaining;
+        stake.since = env::block_timestamp_ms();
+        stake.claimable = U128(0);
+        if stake.amount.0 == 0 {
+            self.address_stakes.remove(index);
+        }
+    }
 }
 
 #[near_bindgen]
@@ -88,7 +144,7 @@ pub struct Stakeable {
      *   This is a array where we store all Stakes that are performed on the Contract
      *   The stakes for each address are stored at a certain index, the index can be found using the stakes mapping
      */
-    stakeholders: LookupMap<AccountId, StakeHolder>,
+    stakeholders: LookupMap<AccountId, VersionedStakeHolder>,
     /**
     * @notice
      rewardPerHour is 1000 because it is used to represent 0.001, since we only use integer numbers
@@ -97,7 +153,53 @@ pub struct Stakeable {
     // reward_per_hour: usize,
     owner_id: AccountId,
     allowed_token: LookupSet<AccountId>,
+    // lifecycle of the allowed tokens, missing for tokens allowed before it existed
+    token_states: LookupMap<AccountId, TokenState>,
+    // symbol, icon and decimals read with ft_metadata when a token is allowed
+    token_metadata: LookupMap<AccountId, TokenMetadata>,
+    // what the contract owes per token and the outcome of its last reconcile
+    token_liabilities: LookupMap<AccountId, TokenLiabilities>,
+    token_solvency: LookupMap<AccountId, TokenSolvency>,
     config: Config,
+    // sum of all staked principal, used to enforce pool_capacity
+    total_staked: Balance,
+    operators: UnorderedSet<AccountId>,
+    // accounts allowed to stake while config.allowlist_enabled is on
+    allowlist: UnorderedSet<AccountId>,
+    // referral rewards accrued by each referrer, per token
+    referral_rewards: LookupMap<AccountId, HashMap<AccountId, U128>>,
+    // (collection, NFT token id) -> stakeholder using it, so one NFT boosts one account
+    boosted_nfts: LookupMap<(AccountId, String), AccountId>,
+    ve_locks: LookupMap<AccountId, VeLock>,
+    // voting power of each account from its own and delegated locks, and of all locks
+    ve_points: LookupMap<AccountId, VePoint>,
+    ve_total: VePoint,
+    ve_slope_changes: LookupMap<(VeHolder, u64), u128>,
+    proposals: Vector<Proposal>,
+    proposal_votes: LookupMap<(u64, AccountId), VoteRecord>,
+    // voters of each proposal in voting order, for paginated views
+    proposal_voters: LookupMap<u64, Vector<AccountId>>,
+    // staked balance history of each account and of the pool, for snapshots
+    account_checkpoints: LookupMap<AccountId, Vector<Checkpoint>>,
+    total_checkpoints: Vector<Checkpoint>,
+    // NEAR prepaid by each account for the storage it uses
+    storage_balances: LookupMap<AccountId, Balance>,
+    // NEAR that pays token registrations of accounts without storage balance
+    storage_reserve: Balance,
+    // fees collected per token, waiting for withdraw_treasury
+    treasury: UnorderedMap<AccountId, Balance>,
+    // claimed rewards that are still vesting, per account
+    vestings: LookupMap<AccountId, Vec<VestingEntry>>,
+    // contract stakers, like the vault, whose rewards are paid right away while vesting is on
+    vesting_exempt: UnorderedSet<AccountId>,
+    airdrops: Vector<AirdropCampaign>,
+    // claimed bits of every campaign, 128 allocation indexes per word
+    airdrop_claims: LookupMap<(u64, u64), u128>,
+    // reward rates and boosts replaced so far, oldest first, the config holds the current ones
+    rate_history: Vec<RatePeriod>,
+    // what pro rata payouts of an underfunded token did not pay, per account and token
+    shortfalls: LookupMap<AccountId, HashMap<AccountId, U128>>,
+    state_version: u16,
 }
 
 #[near_bindgen]
@@ -105,11 +207,39 @@ impl Stakeable {
     #[init]
     pub fn new(owner_id: AccountId) -> Self {
         Self {
-            stakeholders: LookupMap::new(b"stakeholders".to_vec()),
+            stakeholders: LookupMap::new(b"sh".to_vec()),
             // reward_per_hour: REWARD_PER_HOUR,
             owner_id,
             allowed_token: LookupSet::new(b"allowedToken".to_vec()),
+            token_states: LookupMap::new(b"tokenStates".to_vec()),
+            token_metadata: LookupMap::new(b"tokenMetadata".to_vec()),
+            token_liabilities: LookupMap::new(b"tokenLiabilities".to_vec()),
+            token_solvency: LookupMap::new(b"tokenSolvency".to_vec()),
             config: Config::default(),
+            total_staked: 0,
+            operators: UnorderedSet::new(b"operators".to_vec()),
+            allowlist: UnorderedSet::new(b"allowlist".to_vec()),
+            referral_rewards: LookupMap::new(b"referralRewards".to_vec()),
+            boosted_nfts: LookupMap::new(b"boostedNfts".to_vec()),
+            ve_locks: LookupMap::new(b"veLocks".to_vec()),
+            ve_points: LookupMap::new(b"vePoints".to_vec()),
+            ve_total: VePoint::default(),
+            ve_slope_changes: LookupMap::new(b"veSlopeChanges".to_vec()),
+            proposals: Vector::new(b"proposals".to_vec()),
+            proposal_votes: LookupMap::new(b"proposalVotes".to_vec()),
+            proposal_voters: LookupMap::new(b"proposalVoters".to_vec()),
+            account_checkpoints: LookupMap::new(b"accountCheckpoints".to_vec()),
+            total_checkpoints: Vector::new(b"totalCheckpoints".to_vec()),
+            storage_balances: LookupMap::new(b"storageBalances".to_vec()),
+            storage_reserve: 0,
+            treasury: UnorderedMap::new(b"treasury".to_vec()),
+            vestings: LookupMap::new(b"vestings".to_vec()),
+            vesting_exempt: UnorderedSet::new(b"vestingExempt".to_vec()),
+            rate_history: Vec::new(),
+            shortfalls: LookupMap::new(b"shortfalls".to_vec()),
+            airdrops: Vector::new(b"airdrops".to_vec()),
+            airdrop_claims: LookupMap::new(b"airdropClaims".to_vec()),
+            state_version: STATE_VERSION,
         }
     }
 }
@@ -123,9 +253,11 @@ impl Stakeable {
         let stakeholder: StakeHolder = StakeHolder {
             address: stake_id.to_owned(),
             address_stakes: Vec::new(),
+            referrer: None,
+            boost: None,
         };
-        self.stakeholders.insert(&stake_id, &stakeholder);
-        return stakeholder;
+        self._save_stakeholder(&stake_id, stakeholder.clone());
+        stakeholder
     }
 
     /**
@@ -133,109 +265,270 @@ impl Stakeable {
      * _Stake is used to make a stake for an sender. It will remove the amount staked from the stakers account and place those tokens inside a stake container
      * StakeID
      */
-    fn _stake(&mut self, sender: AccountId, amount: U128) {
-        assert!(amount.0 > 0, "Cannot stake nothing");
-        // Mappings in solidity creates all values, but empty, so we can just check the address
-        match self.stakeholders.get(&sender) {
-            None => {
-                self._add_stakeholder(sender.clone());
-            }
-            Some(_) => (),
+    fn _stake(&mut self, sender: AccountId, token_id: AccountId, amount: U128) {
+        self._stake_locked(sender, token_id, amount, 0);
+    }
+
+    pub(crate) fn _new_stake(
+        &self,
+        sender: &AccountId,
+        token_id: &AccountId,
+        amount: U128,
+        locked_until: u64,
+    ) -> Stake {
+        Stake {
+            address: sender.clone(),
+            token_id: token_id.clone(),
+            amount,
+            since: env::block_timestamp_ms(),
+            claimable: U128(0),
+            locked_until,
         }
+    }
 
-        env::log_str(&format!("stakeholder={}", sender.clone().to_string()));
-
-        match self.stakeholders.get(&sender) {
-            Some(mut stakeholder) => {
-                let stake = Stake {
-                    address: sender.clone(),
-                    amount: amount,
-                    since: env::block_timestamp_ms(),
-                    claimable: U128(0),
-                };
-                stakeholder.address_stakes.push(stake);
-                // overwrite new data
-                self.stakeholders.insert(&sender, &stakeholder);
+    /**
+     * @notice
+     * _plan_stake checks a deposit of amount for sender and splits it into the deposit fee,
+     * the stake and what ft_on_transfer refunds, without changing anything.
+     * With stake_index the deposit goes to that stake, which has to be a stake of token_id
+     */
+    pub(crate) fn _plan_stake(
+        &self,
+        sender: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+        stake_index: Option<usize>,
+    ) -> Result<StakePlan, String> {
+        self._check_token_deposits(token_id)?;
+        if let Some(index) = stake_index {
+            let exists = self._get_stakeholder(sender).is_some_and(|stakeholder| {
+                stakeholder
+                    .address_stakes
+                    .get(index)
+                    .is_some_and(|stake| &stake.token_id == token_id)
+            });
+            if !exists {
+                return Err(format!(
+                    "Staking: Stake {} of {} does not exist",
+                    index, token_id
+                ));
             }
-            None => {}
         }
+        let accepted = self._accepted_stake_amount(sender, token_id, amount)?;
+        let quote = self._quote_deposit(accepted);
+        if quote.staked.0 == 0 {
+            return Err("Cannot stake nothing".to_string());
+        }
+        Ok(StakePlan {
+            quote,
+            refund: U128(amount - accepted),
+        })
+    }
+
+    /**
+     * @notice
+     * _grow_stake settles the reward of a stake into claimable and adds amount to its principal
+     */
+    pub(crate) fn _grow_stake(
+        &self,
+        stake: &mut Stake,
+        amount: Balance,
+        boost_collection: Option<&AccountId>,
+    ) {
+        stake.claimable = self._calculate_reward(stake, boost_collection);
+        stake.since = env::block_timestamp_ms();
+        stake.amount.0 += amount;
+    }
+
+    /**
+     * @notice
+     * _add_to_stake adds amount to the stake of sender at index, checked by _plan_stake
+     */
+    fn _add_to_stake(&mut self, sender: &AccountId, index: usize, amount: U128) {
+        let mut stakeholder = self
+            ._get_stakeholder(sender)
+            .unwrap_or_else(|| env::panic_str("Staking: Account has no stakes"));
+        let mut stake = stakeholder.address_stakes[index].clone();
+        self._grow_stake(&mut stake, amount.0, stakeholder.boost_collection());
+        StakeIncreased {
+            account_id: sender.clone(),
+            stake_index: index as u64,
+            token_id: stake.token_id.clone(),
+            amount,
+            claimable: stake.claimable,
+        }
+        .emit();
+        stakeholder.address_stakes[index] = stake;
+        self._save_stakeholder(sender, stakeholder);
+    }
+
+    /**
+     * @notice
+     * _stake_locked makes a stake whose principal cannot be withdrawn before locked_until, like the airdrop stakes
+     */
+    pub(crate) fn _stake_locked(
+        &mut self,
+        sender: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        locked_until: u64,
+    ) {
+        assert!(amount.0 > 0, "Cannot stake nothing");
+        // Mappings in solidity creates all values, but empty, so we can just check the address
+        let mut stakeholder = match self._get_stakeholder(&sender) {
+            Some(stakeholder) => stakeholder,
+            None => self._add_stakeholder(sender.clone()),
+        };
+
+        env::log_str(&format!("stakeholder={}", sender));
+
+        let stake = self._new_stake(&sender, &token_id, amount, locked_until);
+        StakeCreated {
+            account_id: sender.clone(),
+            stake_index: stakeholder.address_stakes.len() as u64,
+            token_id,
+            amount,
+            since: stake.since,
+            locked_until,
+        }
+        .emit();
+        stakeholder.address_stakes.push(stake);
+        // overwrite new data
+        self._save_stakeholder(&sender, stakeholder);
     }
 
     /**
      * @notice
      * withdrawStake takes in an amount and a index of the stake and will remove tokens from that stake
      * Notice index of the stake is the users stake counter, starting at 0 for the first stake
-     * Will return the amount to MINT onto the acount
-     * Will also calculateStakeReward and reset timer
+     * Will return the withdrawal with the amount to transfer onto the acount
+     * Will also calculateStakeReward, take the fees, credit the referrer and reset timer
      */
-    fn _with_draw_stake(&mut self, amount: U128, index: usize) -> U128 {
-        let account_id = env::signer_account_id();
-        match self.stakeholders.get(&account_id) {
-            Some(mut stakeholder) => {
-                let current_stake = stakeholder.address_stakes.get_mut(index).unwrap();
-                assert!(
-                    current_stake.amount.0 >= amount.0,
-                    "Staking: Cannot withdraw more than you have staked"
-                );
-                let reward = self.calculate_stake_reward(current_stake.clone());
-                env::log_str(
-                    format!("current_stake={}, {}", current_stake.amount.0, amount.0).as_str(),
-                );
-                current_stake.amount = U128(current_stake.amount.0 - amount.0);
-                current_stake.since = env::block_timestamp_ms();
-                if (current_stake.amount.0 == 0) {
-                    stakeholder.address_stakes.remove(index);
-                }
-                self.stakeholders.insert(&account_id, &stakeholder);
-                return U128(amount.0 + reward.0);
-            }
-            None => todo!(),
+    fn _with_draw_stake(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        index: usize,
+    ) -> Withdrawal {
+        let mut stakeholder = self
+            ._get_stakeholder(&account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: Account has no stakes"));
+        let withdrawal = self._withdraw_from(&mut stakeholder, index, amount.0);
+        self._save_stakeholder(&account_id, stakeholder);
+        withdrawal
+    }
+
+    /**
+     * @notice
+     * _withdraw_from does the work of _with_draw_stake on a stakeholder that is already loaded,
+     * the caller saves it, so a batch reads and writes the stakeholder once
+     */
+    pub(crate) fn _withdraw_from(
+        &mut self,
+        stakeholder: &mut StakeHolder,
+        index: usize,
+        amount: Balance,
+    ) -> Withdrawal {
+        let withdrawal = self
+            ._compute_withdrawal(stakeholder, index, amount)
+            .unwrap_or_else(|reason| env::panic_str(reason.as_str()));
+        env::log_str(
+            format!(
+                "current_stake={}, {}",
+                stakeholder.address_stakes[index].amount.0, amount
+            )
+            .as_str(),
+        );
+        stakeholder.apply_withdrawal(index, &withdrawal);
+        let account_id = stakeholder.address.clone();
+        let token_id = &withdrawal.token_id;
+        StakeWithdrawn {
+            account_id: account_id.clone(),
+            stake_index: index as u64,
+            token_id: token_id.clone(),
+            amount: withdrawal.principal,
+            reward: withdrawal.reward,
+            remaining: withdrawal.remaining,
+        }
+        .emit();
+        self._credit_referral(stakeholder, token_id, withdrawal.reward.0);
+        self._collect_fee(
+            &account_id,
+            token_id,
+            "withdraw",
+            withdrawal.principal_fee.0,
+        );
+        self._collect_fee(&account_id, token_id, "reward", withdrawal.reward_fee.0);
+        withdrawal
+    }
+
+    /**
+     * @notice
+     * _calculate_reward returns the reward settled on the stake (claimable) plus what accrued since `since`
+     * at the rates of the time, boosted in the periods of boost_collection, the collection of the staker's NFT
+     */
+    pub(crate) fn _calculate_reward(
+        &self,
+        current_stake: &Stake,
+        boost_collection: Option<&AccountId>,
+    ) -> U128 {
+        let now = env::block_timestamp_ms();
+        let end = self._reward_end(&current_stake.token_id, now);
+        let accrued = self._accrued_reward(current_stake, end, |period| {
+            period.boost_bps_of(boost_collection)
+        });
+        U128(current_stake.claimable.0.saturating_add(accrued))
+    }
+
+    /**
+     * @notice
+     * _settle_rewards moves the reward accrued so far into claimable and restarts the timer of every stake,
+     * it has to run before anything that changes how rewards accrue, like the boost of the stakeholder
+     */
+    pub(crate) fn _settle_rewards(&self, stakeholder: &mut StakeHolder) {
+        let boost_collection = stakeholder.boost_collection().cloned();
+        let now = env::block_timestamp_ms();
+        for stake in stakeholder.address_stakes.iter_mut() {
+            stake.claimable = self._calculate_reward(stake, boost_collection.as_ref());
+            stake.since = now;
         }
     }
 }
 
 #[near_bindgen]
 impl Stakeable {
-    pub fn allow_token(&mut self, token_id: AccountId) -> String {
-        self.allowed_token.insert(&token_id);
-        token_id.to_string()
-    }
     /**
      * @notice
      * readonly
      * calculateStakeReward is used to calculate how much a user should be rewarded for their stakes
-     * and the duration the stake has been active
+     * and the duration the stake has been active, including the NFT boost of the staker
      */
     pub fn calculate_stake_reward(&self, current_stake: Stake) -> U128 {
-        // First calculate how long the stake has been active
-        // Use current seconds since epoch - the seconds since epoch the stake was made
-        // The output will be duration in SECONDS ,
-        // We will reward the user 0.1% per Hour So thats 0.1% per 3600 seconds
-        // the alghoritm is  seconds = block.timestamp - stake seconds (block.timestap - _stake.since)
-        // hours = Seconds / 3600 (seconds /3600) 3600 is an variable in Solidity names hours
-        // we then multiply each token by the hours staked , then divide by the rewardPerHour rate
-        // return (((block.timestamp - _current_stake.since) / 1 hours) * _current_stake.amount) / rewardPerHour;
-        let timestamp = env::block_timestamp_ms();
-        let duration = (timestamp - current_stake.since) as u128;
-        env::log_str(format!("timestamp={}", timestamp.to_string(),).as_str());
-        return U128(
-            ((duration
-                * current_stake.amount.0
-                // * u128::pow(10, self.config.decimals)
-                * self.config.reward_numerator as u128)
-                / ONE_HOUR as u128)
-                / self.config.reward_denumerator as u128,
-        );
+        let stakeholder = self._get_stakeholder(&current_stake.address);
+        let boost_collection = stakeholder
+            .as_ref()
+            .and_then(|stakeholder| stakeholder.boost_collection());
+        self._calculate_reward(&current_stake, boost_collection)
     }
     /**
      * @notice
      * readonly
      * hasStake is used to check if a account has stakes and the total amount along with all the seperate stakes
+     * An account that never staked gets an empty summary
      */
     pub fn has_stake(&self, staker: AccountId) -> StakingSummary {
         // totalStakeAmount is used to count total staked amount of the address
         let mut total_stake_amount: U128 = U128(0);
-        let stakeholder = self.stakeholders.get(&staker).unwrap().clone();
+        let stakeholder = match self._get_stakeholder(&staker) {
+            Some(stakeholder) => stakeholder,
+            None => {
+                return StakingSummary {
+                    total_amount: U128(0),
+                    stakes: Vec::new(),
+                }
+            }
+        };
+        let boost_collection = stakeholder.boost_collection().cloned();
 
         // Keep a summary in memory since we need to calculate this
         let mut summary = StakingSummary {
@@ -245,43 +538,42 @@ impl Stakeable {
 
         // Itterate all stakes and grab amount of stakes
         for stake in summary.stakes.iter_mut() {
-            let available_reward = self.calculate_stake_reward(stake.clone());
-            env::log_str(format!("claimable_amount={}", available_reward.0.to_string(),).as_str());
+            let available_reward = self._calculate_reward(stake, boost_collection.as_ref());
+            env::log_str(format!("claimable_amount={}", available_reward.0).as_str());
             stake.claimable = available_reward;
             total_stake_amount = U128(total_stake_amount.0 + stake.amount.0);
         }
 
         // // Assign calculate amount to summary
         summary.total_amount = total_stake_amount;
-        return summary;
+        summary
     }
 
     /**
      * @notice withdrawStake is used to withdraw stakes from the account holder
+     * The attached deposit goes to the storage balance of the caller, it pays for the balance checkpoint.
+     * Returns the transfer and its resolve callback so contracts staking here, like the vault, can wait for the tokens
      */
-    pub fn withdraw_stake(&mut self, amount: U128, stake_index: usize) {
-        let account_id = env::signer_account_id();
-        let claimable_amount = self._with_draw_stake(amount, stake_index);
-        let token: AccountId = "dev-1653846714290-58446128043200".parse().unwrap();
-        log_str(format!("claimable_amount={}", claimable_amount.0.to_string(),).as_str());
-        ext_ft::ext(token)
-            .with_static_gas(FT_TRANSFER_GAS)
-            .with_attached_deposit(DEPOSIT_ONE_YOCTO)
-            .ft_transfer(
-                account_id.to_string(),
-                claimable_amount.0.to_string(),
-                Some("0".to_string()),
-            );
-        // TODO: transfer token to receiver
+    #[payable]
+    pub fn withdraw_stake(&mut self, amount: U128, stake_index: usize) -> PromiseOrValue<U128> {
+        let account_id = env::predecessor_account_id();
+        self._deposit_storage(&account_id);
+        let withdrawal = self._with_draw_stake(account_id.clone(), amount, stake_index);
+        log_str(format!("claimable_amount={}", withdrawal.payout.0).as_str());
+        let mut settlements = vec![withdrawal.into()];
+        self._vest_rewards(&account_id, &mut settlements, false);
+        self._pay_settlements(&account_id, settlements)
     }
 
     // * readonly
+    // unit of the stake limits and totals, get_token_decimals gives the decimals of a token
     pub fn decimals(&self) -> u32 {
-        return self.config.decimals;
+        self.config.decimals
     }
 
+    // rounded ratio, get_apr_bps gives the exact rate
     pub fn reward_per_hour(&self) -> f32 {
-        return self.config.reward_numerator as f32 / self.config.reward_denumerator as f32;
+        self.config.reward_numerator as f32 / self.config.reward_denumerator as f32
     }
 }
 
@@ -290,51 +582,234 @@ impl Stakeable {
 impl Stakeable {
     pub fn ft_on_transfer(
         &mut self,
-        sender_id: ValidAccountId,
+        sender_id: AccountId,
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
         let processor = env::predecessor_account_id();
         let account_id = env::signer_account_id();
-        log_str(
-            format!(
-                "processor={}, account_id={}",
-                processor.to_string(),
-                account_id.to_string()
-            )
-            .as_str(),
-        );
-        // comment out for testing
-        // let is_contained = self.allowed_token.contains(&processor);
-        // assert!(is_contained, "token is not allow");
+        log_str(format!("processor={}, account_id={}", processor, account_id).as_str());
         // assert_ne!(processor, account_id.clone(), "Oops1");
         assert!(amount.0 > 0, "Oops2");
 
-        let mut result = 0;
-
-        match msg.as_str() {
-            "staking" => {
-                self._stake(sender_id.clone(), amount);
-
-                // let token: AccountId = "dev-1653846714290-58446128043200".parse().unwrap();
-                // let receiver = env::current_account_id();
-                // ext_ft::ext(token)
-                //     .with_static_gas(FT_TRANSFER_GAS)
-                //     .with_attached_deposit(DEPOSIT_ONE_YOCTO)
-                //     .ft_resolve_transfer(sender_id.clone(), receiver, amount);
-
-                format!(
-                    "Account={} stake {} is successfully",
-                    sender_id.to_string(),
-                    amount.0.to_string()
-                );
-                result = 1;
-                // PromiseOrValue::Value(U128(1))
+        // the returned value is the amount the token contract refunds to the sender
+        let mut unused_amount = amount.0;
+
+        match TransferMessage::parse(&msg) {
+            Some(TransferMessage::Staking {
+                referrer_id,
+                stake_index,
+            }) => {
+                let stake_index = stake_index.map(|index| index as usize);
+                match self._plan_stake(&sender_id, &processor, amount.0, stake_index) {
+                    Ok(StakePlan { quote, refund }) => {
+                        if let Some(referrer_id) = referrer_id {
+                            self._bind_referrer(&sender_id, referrer_id);
+                        }
+                        self._collect_fee(&sender_id, &processor, "deposit", quote.fee.0);
+                        match stake_index {
+                            Some(index) => self._add_to_stake(&sender_id, index, quote.staked),
+                            None => self._stake(sender_id.clone(), processor, quote.staked),
+                        }
+                        log_str(
+                            format!(
+                                "Account={} stake {} is successfully",
+                                sender_id, quote.staked.0
+                            )
+                            .as_str(),
+                        );
+                        unused_amount = refund.0;
+                    }
+                    Err(reason) => log_str(reason.as_str()),
+                }
+            }
+            Some(TransferMessage::VeLock { lock_duration }) => {
+                match self._ve_lock(&sender_id, processor, amount.0, lock_duration) {
+                    Ok(()) => unused_amount = 0,
+                    Err(reason) => log_str(reason.as_str()),
+                }
+            }
+            Some(TransferMessage::FundAirdrop { campaign_id }) => {
+                match self._fund_airdrop(campaign_id, &processor, amount.0) {
+                    Ok(unused) => unused_amount = unused,
+                    Err(reason) => log_str(reason.as_str()),
+                }
             }
-            _ => {
-                "Oops".to_string();
+            None => {
+                log_str("Oops");
             }
         }
-        PromiseOrValue::Value(U128(result))
+        PromiseOrValue::Value(U128(unused_amount))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_new() {
+        testing_env!(get_context(accounts(1)).build());
+        let contract = Stakeable::new(accounts(1));
+        assert_eq!(contract.get_owner(), accounts(1));
+        assert_eq!(contract.decimals(), 24);
+        assert_eq!(contract.get_state_version(), STATE_VERSION);
+    }
+
+    #[test]
+    fn test_client_gas_covers_callbacks() {
+        let registration = STORAGE_BALANCE_OF_GAS.0
+            + ON_STORAGE_CHECKED_GAS.0
+            + STORAGE_DEPOSIT_GAS.0
+            + ON_ACCOUNT_REGISTERED_GAS.0;
+        // (gas a call attaches to what follows it, gas clients give it)
+        let budgets = [
+            (
+                registration + FT_METADATA_GAS.0 + ON_TOKEN_METADATA_GAS.0,
+                ALLOW_TOKEN_GAS.0,
+            ),
+            (SETTLEMENT_PAYOUT_GAS.0, WITHDRAW_STAKE_GAS.0),
+        ];
+        for (needed, given) in budgets {
+            assert!(needed < given, "{} >= {}", needed, given);
+        }
+    }
+
+    #[test]
+    fn test_has_stake_unknown_account() {
+        let contract = setup();
+        let summary = contract.has_stake(accounts(3));
+        assert_eq!(summary.total_amount.0, 0);
+        assert!(summary.stakes.is_empty());
+    }
+
+    #[test]
+    fn test_ft_on_transfer_stakes() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.total_amount.0, 3 * AMOUNT);
+        assert_eq!(summary.stakes.len(), 2);
+        assert_eq!(summary.stakes[0].token_id, token());
+        assert_eq!(contract.total_staked, 3 * AMOUNT);
+    }
+
+    #[test]
+    fn test_ft_on_transfer_unknown_message_refunds() {
+        let mut contract = setup();
+        testing_env!(get_context(token()).build());
+        let refund = contract.ft_on_transfer(accounts(2), U128(AMOUNT), "hello".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(AMOUNT))));
+        assert!(contract.has_stake(accounts(2)).stakes.is_empty());
+    }
+
+    #[test]
+    fn test_reward_accrual() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        for (elapsed, reward) in [
+            (0, 0),
+            (ONE_HOUR / 2, HOURLY_REWARD / 2),
+            (ONE_HOUR, HOURLY_REWARD),
+            (24 * ONE_HOUR, 24 * HOURLY_REWARD),
+        ] {
+            call_at(accounts(2), elapsed);
+            let summary = contract.has_stake(accounts(2));
+            assert_eq!(summary.stakes[0].claimable.0, reward);
+        }
+    }
+
+    #[test]
+    fn test_partial_withdraw() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        let result = contract.withdraw_stake(U128(400_000), 0);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        let summary = contract.has_stake(accounts(2));
+        assert_eq!(summary.total_amount.0, 600_000);
+        // the reward was paid with the withdrawal and the timer restarted
+        assert_eq!(summary.stakes[0].claimable.0, 0);
+        assert_eq!(summary.stakes[0].since, ONE_HOUR);
+        assert_eq!(contract.total_staked, 600_000);
+    }
+
+    #[test]
+    fn test_full_withdraw_removes_stake() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        assert!(contract.has_stake(accounts(2)).stakes.is_empty());
+        assert_eq!(contract.total_staked, 0);
+    }
+
+    #[test]
+    fn test_withdraw_index_handling() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        stake(&mut contract, accounts(2), 3 * AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        // the stakes after the removed one move down
+        assert_eq!(
+            amounts(&contract, accounts(2)),
+            vec![2 * AMOUNT, 3 * AMOUNT]
+        );
+        contract.withdraw_stake(U128(AMOUNT), 1);
+        assert_eq!(
+            amounts(&contract, accounts(2)),
+            vec![2 * AMOUNT, 2 * AMOUNT]
+        );
+    }
+
+    #[test]
+    fn test_withdraw_rejected() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        assert_eq!(
+            withdrawal_error(&contract, 0, AMOUNT + 1),
+            "Staking: Cannot withdraw more than you have staked"
+        );
+        assert_eq!(
+            withdrawal_error(&contract, 3, AMOUNT),
+            "Staking: Stake 3 does not exist"
+        );
+    }
+
+    #[test]
+    fn test_stake_into_existing_stake() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        let msg = r#"{"action": "staking", "stake_index": 0}"#.to_string();
+        testing_env!(get_context(token())
+            .block_timestamp(at_ms(ONE_HOUR))
+            .build());
+        let preview = contract.preview_stake(accounts(2), token(), U128(AMOUNT), msg.clone());
+        let refund = contract.ft_on_transfer(accounts(2), U128(AMOUNT), msg.clone());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+        let stakes = contract.has_stake(accounts(2)).stakes;
+        assert_eq!(stakes.len(), 1);
+        assert_eq!(stakes[0].amount.0, 2 * AMOUNT);
+        assert_eq!(stakes[0].claimable.0, HOURLY_REWARD);
+        assert_eq!(preview.stakes[0].amount.0, 2 * AMOUNT);
+        // the reward settled before the deposit is kept, the new principal earns from now
+        call_at(accounts(2), 2 * ONE_HOUR);
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            3 * HOURLY_REWARD
+        );
+        // a stake that does not exist refunds the deposit
+        testing_env!(get_context(token()).build());
+        let msg = r#"{"action": "staking", "stake_index": 1}"#.to_string();
+        let refund = contract.ft_on_transfer(accounts(2), U128(AMOUNT), msg);
+        assert!(matches!(refund, PromiseOrValue::Value(U128(AMOUNT))));
     }
 }
@@ -0,0 +1,162 @@
+use crate::*;
+
+/**
+ * @notice
+ * StakeLimits is the json view of the minimum stake, the per account cap and the pool capacity
+ */
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeLimits {
+    pub min_stake: U128,
+    pub max_stake_per_account: Option<U128>,
+    pub pool_capacity: Option<U128>,
+    pub total_staked: U128,
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _accepted_stake_amount returns how much of a deposit of token_id can be staked for the account,
+     * the deposit is cut down to the remaining capacity and the rest has to be refunded.
+     * The limits are in the unit of Config.decimals and converted to the decimals of the token
+     */
+    pub(crate) fn _accepted_stake_amount(
+        &self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Result<Balance, String> {
+        if !self._can_stake(account_id) {
+            return Err(format!("Staking: {} is not on the allowlist", account_id));
+        }
+        let decimals = self._token_decimals(token_id);
+        let accepted = match self._account_remaining_capacity(account_id) {
+            Some(remaining) => {
+                amount.min(convert_decimals(remaining, self.config.decimals, decimals))
+            }
+            None => amount,
+        };
+        if accepted == 0 {
+            return Err("Staking: Stake capacity is reached".to_string());
+        }
+        if self._normalized_amount(token_id, accepted) < self.config.min_stake.0 {
+            return Err(format!(
+                "Staking: Cannot stake less than {}",
+                self.config.min_stake.0
+            ));
+        }
+        Ok(accepted)
+    }
+
+    pub(crate) fn _pool_remaining_capacity(&self) -> Option<Balance> {
+        self.config
+            .pool_capacity
+            .map(|capacity| capacity.0.saturating_sub(self.total_staked))
+    }
+
+    pub(crate) fn _account_remaining_capacity(&self, account_id: &AccountId) -> Option<Balance> {
+        let account_remaining = self.config.max_stake_per_account.map(|max| {
+            let staked = self
+                ._get_stakeholder(account_id)
+                .map(|stakeholder| self._normalized_total(&stakeholder))
+                .unwrap_or(0);
+            max.0.saturating_sub(staked)
+        });
+        match (account_remaining, self._pool_remaining_capacity()) {
+            (Some(account), Some(pool)) => Some(account.min(pool)),
+            (account, pool) => account.or(pool),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * set_stake_limits updates the minimum stake, the maximum total per account and the pool capacity,
+     * None removes a cap. Lowering a cap never touches stakes that are already above it.
+     * The owner can only call it until the first stake, then the limits change with a proposal
+     */
+    pub fn set_stake_limits(
+        &mut self,
+        min_stake: U128,
+        max_stake_per_account: Option<U128>,
+        pool_capacity: Option<U128>,
+    ) {
+        self.assert_owner_before_staking();
+        assert!(min_stake.0 > 0, "Staking: Minimum stake must be positive");
+        self.config.min_stake = min_stake;
+        self.config.max_stake_per_account = max_stake_per_account;
+        self.config.pool_capacity = pool_capacity;
+    }
+
+    // * readonly
+    pub fn get_stake_limits(&self) -> StakeLimits {
+        StakeLimits {
+            min_stake: self.config.min_stake,
+            max_stake_per_account: self.config.max_stake_per_account,
+            pool_capacity: self.config.pool_capacity,
+            total_staked: U128(self.total_staked),
+        }
+    }
+
+    /**
+     * @notice
+     * readonly
+     * Remaining capacity of the pool, None when the pool is not capped
+     */
+    pub fn get_pool_remaining_capacity(&self) -> Option<U128> {
+        self._pool_remaining_capacity().map(U128)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * How much more the account can stake, taking the pool capacity into account. None when nothing is capped
+     */
+    pub fn get_account_remaining_capacity(&self, account_id: AccountId) -> Option<U128> {
+        self._account_remaining_capacity(&account_id).map(U128)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_stake_limits() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        call_at(accounts(1), 0);
+        contract.set_stake_limits(U128(100), Some(U128(3 * AMOUNT)), Some(U128(4 * AMOUNT)));
+        // below the minimum
+        assert_eq!(transfer(&mut contract, accounts(2), 99, "staking"), 99);
+        // cut down to the account cap, the rest is refunded
+        assert_eq!(
+            transfer(&mut contract, accounts(2), 5 * AMOUNT, "staking"),
+            2 * AMOUNT
+        );
+        assert_eq!(contract.has_stake(accounts(2)).total_amount.0, 3 * AMOUNT);
+        assert_eq!(
+            contract
+                .get_account_remaining_capacity(accounts(2))
+                .unwrap()
+                .0,
+            0
+        );
+        assert_eq!(
+            transfer(&mut contract, accounts(2), AMOUNT, "staking"),
+            AMOUNT
+        );
+        // cut down to the pool capacity
+        assert_eq!(
+            transfer(&mut contract, accounts(3), 2 * AMOUNT, "staking"),
+            AMOUNT
+        );
+        assert_eq!(contract.get_pool_remaining_capacity().unwrap().0, 0);
+        assert_eq!(contract.get_stake_limits().total_staked.0, 4 * AMOUNT);
+    }
+}
@@ -0,0 +1,38 @@
+use crate::*;
+
+/**
+ * @notice
+ * TransferMessage is the `msg` of ft_transfer_call.
+ * The plain "staking" string is still accepted, other actions are passed as json, e.g.
+ * {"action": "staking", "referrer_id": "alice.testnet"}
+ * stake_index adds the deposit to an existing stake of the same token instead of making a new one, like the vault does
+ */
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TransferMessage {
+    Staking {
+        referrer_id: Option<AccountId>,
+        stake_index: Option<u64>,
+    },
+    // {"action": "ve_lock", "lock_duration": 31536000000}, lock_duration in ms
+    VeLock {
+        lock_duration: Option<u64>,
+    },
+    // {"action": "fund_airdrop", "campaign_id": 0}
+    FundAirdrop {
+        campaign_id: u64,
+    },
+}
+
+impl TransferMessage {
+    pub fn parse(msg: &str) -> Option<Self> {
+        match msg {
+            "staking" => Some(TransferMessage::Staking {
+                referrer_id: None,
+                stake_index: None,
+            }),
+            _ => near_sdk::serde_json::from_str(msg).ok(),
+        }
+    }
+}
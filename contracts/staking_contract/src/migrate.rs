@@ -0,0 +1,435 @@
+use crate::*;
+
+/**
+ * @notice
+ * Version of the `Stakeable` layout written by this code.
+ * Bump it together with a new conversion in `migrate` whenever a field is added to the contract state.
+ * Version 2 is the first versioned layout, version 3 the current one
+ */
+pub const STATE_VERSION: u16 = 3;
+
+// Storage prefix used by the first deployed layout, where stakeholders were stored as plain Borsh
+pub const LEGACY_STAKEHOLDERS_PREFIX: &[u8] = b"stakeholders";
+// The first layout only paid out this token, so legacy stakes are attributed to it
+pub const LEGACY_TOKEN_ID: &str = "dev-1653846714290-58446128043200";
+
+/**
+ * @notice
+ * Layout of `Stake` before stakes recorded their token
+ */
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StakeV1 {
+    address: AccountId,
+    amount: U128,
+    since: u64,
+    claimable: U128,
+}
+
+/**
+ * @notice
+ * Layout of `StakeHolder` before stakes recorded their token, used by the legacy records and by `V2` records
+ */
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StakeHolderV1 {
+    address: AccountId,
+    address_stakes: Vec<StakeV1>,
+}
+
+impl StakeHolderV1 {
+    /**
+     * @notice
+     * Legacy stakes were all paid in LEGACY_TOKEN_ID, had no lock and their stakeholders no referrer or boost
+     */
+    fn upgrade(self) -> StakeHolder {
+        let token_id: AccountId = LEGACY_TOKEN_ID.parse().unwrap();
+        StakeHolder {
+            address: self.address,
+            address_stakes: self
+                .address_stakes
+                .into_iter()
+                .map(|stake| Stake {
+                    address: stake.address,
+                    token_id: token_id.clone(),
+                    amount: stake.amount,
+                    since: stake.since,
+                    claimable: stake.claimable,
+                    locked_until: 0,
+                })
+                .collect(),
+            referrer: None,
+            boost: None,
+        }
+    }
+}
+
+/**
+ * @notice
+ * Every record in `stakeholders` is wrapped in this enum so a record can be read
+ * whatever version it was written with, and is upgraded the next time it is saved.
+ * Every change of the `StakeHolder` or `Stake` layout adds a variant, existing variants are never edited
+ */
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VersionedStakeHolder {
+    V2(StakeHolderV1),
+    V3(StakeHolder),
+}
+
+impl From<StakeHolder> for VersionedStakeHolder {
+    fn from(stakeholder: StakeHolder) -> Self {
+        VersionedStakeHolder::V3(stakeholder)
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ConfigV1 {
+    reward_numerator: u32,
+    reward_denumerator: u32,
+    decimals: u32,
+}
+
+/**
+ * @notice
+ * Layout of `Stakeable` as it was deployed before the state was versioned
+ */
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StakeableV1 {
+    stakeholders: LookupMap<AccountId, StakeHolderV1>,
+    owner_id: AccountId,
+    allowed_token: LookupSet<AccountId>,
+    config: ConfigV1,
+}
+
+/**
+ * @notice
+ * Layout of `Stakeable` at state version 2, the first one with versioned stakeholder records
+ */
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StakeableV2 {
+    stakeholders: LookupMap<AccountId, VersionedStakeHolder>,
+    owner_id: AccountId,
+    allowed_token: LookupSet<AccountId>,
+    config: ConfigV1,
+    state_version: u16,
+}
+
+impl From<StakeableV1> for StakeableV2 {
+    fn from(old: StakeableV1) -> Self {
+        Self {
+            stakeholders: LookupMap::new(b"sh".to_vec()),
+            owner_id: old.owner_id,
+            allowed_token: old.allowed_token,
+            config: old.config,
+            state_version: 2,
+        }
+    }
+}
+
+impl From<StakeableV2> for Stakeable {
+    fn from(old: StakeableV2) -> Self {
+        Self {
+            stakeholders: old.stakeholders,
+            owner_id: old.owner_id,
+            allowed_token: old.allowed_token,
+            token_states: LookupMap::new(b"tokenStates".to_vec()),
+            token_metadata: LookupMap::new(b"tokenMetadata".to_vec()),
+            token_liabilities: LookupMap::new(b"tokenLiabilities".to_vec()),
+            token_solvency: LookupMap::new(b"tokenSolvency".to_vec()),
+            config: Config {
+                reward_numerator: old.config.reward_numerator,
+                reward_denumerator: old.config.reward_denumerator,
+                decimals: old.config.decimals,
+                ..Config::default()
+            },
+            total_staked: 0,
+            operators: UnorderedSet::new(b"operators".to_vec()),
+            allowlist: UnorderedSet::new(b"allowlist".to_vec()),
+            referral_rewards: LookupMap::new(b"referralRewards".to_vec()),
+            boosted_nfts: LookupMap::new(b"boostedNfts".to_vec()),
+            ve_locks: LookupMap::new(b"veLocks".to_vec()),
+            ve_points: LookupMap::new(b"vePoints".to_vec()),
+            ve_total: VePoint::default(),
+            ve_slope_changes: LookupMap::new(b"veSlopeChanges".to_vec()),
+            proposals: Vector::new(b"proposals".to_vec()),
+            proposal_votes: LookupMap::new(b"proposalVotes".to_vec()),
+            proposal_voters: LookupMap::new(b"proposalVoters".to_vec()),
+            account_checkpoints: LookupMap::new(b"accountCheckpoints".to_vec()),
+            total_checkpoints: Vector::new(b"totalCheckpoints".to_vec()),
+            storage_balances: LookupMap::new(b"storageBalances".to_vec()),
+            storage_reserve: 0,
+            treasury: UnorderedMap::new(b"treasury".to_vec()),
+            vestings: LookupMap::new(b"vestings".to_vec()),
+            vesting_exempt: UnorderedSet::new(b"vestingExempt".to_vec()),
+            rate_history: Vec::new(),
+            shortfalls: LookupMap::new(b"shortfalls".to_vec()),
+            airdrops: Vector::new(b"airdrops".to_vec()),
+            airdrop_claims: LookupMap::new(b"airdropClaims".to_vec()),
+            state_version: STATE_VERSION,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * migrate converts the stored state into the current layout, it has to be called right after new code is deployed.
+     * Stakeholder records are not touched here, they are converted lazily when they are next read
+     */
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let raw_state = env::storage_read(b"STATE").expect("Staking: Contract is not initialized");
+        if let Ok(state) = Stakeable::try_from_slice(&raw_state) {
+            assert!(
+                state.state_version <= STATE_VERSION,
+                "Staking: Cannot migrate from a newer state version"
+            );
+            return Self {
+                state_version: STATE_VERSION,
+                ..state
+            };
+        }
+        if let Ok(old_state) = StakeableV2::try_from_slice(&raw_state) {
+            log_str("Staking: Migrated state from version 2");
+            return old_state.into();
+        }
+        let old_state = StakeableV1::try_from_slice(&raw_state)
+            .unwrap_or_else(|_| env::panic_str("Staking: Unknown state layout"));
+        log_str("Staking: Migrated state from version 1");
+        StakeableV2::from(old_state).into()
+    }
+
+    /**
+     * @notice
+     * backfill_stakeholders saves the given stakeholders in the current layout, so stakes written before total_staked,
+     * the checkpoints and the liabilities existed are counted in them. Stakeholder maps cannot be iterated,
+     * the accounts come from the indexer and are sent in pages. The attached deposit pays the checkpoints
+     * of the backfilled accounts and what is left is refunded. Returns how many records were converted
+     */
+    #[payable]
+    pub fn backfill_stakeholders(&mut self, account_ids: Vec<AccountId>) -> u32 {
+        self.assert_owner_or_operator();
+        let mut deposit = env::attached_deposit();
+        let mut converted = 0;
+        for account_id in account_ids {
+            if self._counted_stakeholder(&account_id).is_some() {
+                continue;
+            }
+            let stakeholder = match self._get_stakeholder(&account_id) {
+                Some(stakeholder) => stakeholder,
+                None => continue,
+            };
+            let storage_balance = self.storage_balances.get(&account_id);
+            let balance = storage_balance.unwrap_or(0);
+            self.storage_balances
+                .insert(&account_id, &(balance + deposit));
+            self._save_stakeholder(&account_id, stakeholder);
+            deposit = self.storage_balances.get(&account_id).unwrap_or(0) - balance;
+            match storage_balance {
+                Some(balance) => self.storage_balances.insert(&account_id, &balance),
+                None => self.storage_balances.remove(&account_id),
+            };
+            converted += 1;
+        }
+        if deposit > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(deposit);
+        }
+        log_str(&format!("Staking: Backfilled {} stakeholders", converted));
+        converted
+    }
+
+    // * readonly
+    pub fn get_state_version(&self) -> u16 {
+        self.state_version
+    }
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * Returns a stakeholder whatever version it was stored with,
+     * records written by the first layout are read from the legacy prefix
+     */
+    pub(crate) fn _get_stakeholder(&self, account_id: &AccountId) -> Option<StakeHolder> {
+        if let Some(stakeholder) = self.stakeholders.get(account_id) {
+            return Some(self._upgrade_stakeholder(stakeholder));
+        }
+        let legacy: LookupMap<AccountId, StakeHolderV1> =
+            LookupMap::new(LEGACY_STAKEHOLDERS_PREFIX.to_vec());
+        legacy
+            .get(account_id)
+            .map(|stakeholder| self._upgrade_stakeholder(VersionedStakeHolder::V2(stakeholder)))
+    }
+
+    fn _upgrade_stakeholder(&self, versioned: VersionedStakeHolder) -> StakeHolder {
+        match versioned {
+            VersionedStakeHolder::V2(stakeholder) => stakeholder.upgrade(),
+            VersionedStakeHolder::V3(stakeholder) => stakeholder,
+        }
+    }
+
+    /**
+     * @notice
+     * Returns the stakeholder as it is counted in total_staked, the checkpoints and the liabilities.
+     * V2 records were written before those existed and are only counted once they are saved again
+     */
+    fn _counted_stakeholder(&self, account_id: &AccountId) -> Option<StakeHolder> {
+        match self.stakeholders.get(account_id)? {
+            VersionedStakeHolder::V3(stakeholder) => Some(stakeholder),
+            VersionedStakeHolder::V2(_) => None,
+        }
+    }
+
+    /**
+     * @notice
+     * Saves a stakeholder in the current version, drops its legacy record if there is one and keeps total_staked
+     * and the liabilities of its tokens in sync
+     */
+    pub(crate) fn _save_stakeholder(&mut self, account_id: &AccountId, stakeholder: StakeHolder) {
+        let mut legacy: LookupMap<AccountId, StakeHolderV1> =
+            LookupMap::new(LEGACY_STAKEHOLDERS_PREFIX.to_vec());
+        legacy.remove(account_id);
+        // legacy and older records are added the first time they are saved again, see backfill_stakeholders
+        let previous = self._counted_stakeholder(account_id);
+        let previous_total = previous
+            .as_ref()
+            .map(|stakeholder| self._normalized_total(stakeholder))
+            .unwrap_or(0);
+        let new_total = self._normalized_total(&stakeholder);
+        self._track_liabilities(previous.as_ref(), &stakeholder);
+        self.total_staked = self.total_staked.saturating_sub(previous_total) + new_total;
+        self.stakeholders.insert(account_id, &stakeholder.into());
+        if new_total != previous_total {
+            self._write_checkpoints(account_id, new_total);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    // writes a stakeholder record with the Borsh tag and fields of an older layout
+    fn write_stakeholder<T: BorshSerialize>(account_id: &AccountId, tag: u8, record: T) {
+        let mut key = b"sh".to_vec();
+        key.extend(account_id.try_to_vec().unwrap());
+        let mut value = vec![tag];
+        value.extend(record.try_to_vec().unwrap());
+        env::storage_write(&key, &value);
+    }
+
+    #[test]
+    fn test_migrate_from_state_version_2() {
+        testing_env!(get_context(accounts(0)).build());
+        let owner_id = accounts(1);
+        let allowed_token: LookupSet<AccountId> = LookupSet::new(b"allowedToken".to_vec());
+        let state = (
+            b"sh".to_vec(),
+            owner_id.clone(),
+            allowed_token,
+            (15u32, 1000u32, 24u32),
+            2u16,
+        );
+        env::storage_write(b"STATE", &state.try_to_vec().unwrap());
+        // the layout written at state version 2, before stakes recorded their token
+        let stake_v1 = (accounts(2), U128(AMOUNT), 0u64, U128(1));
+        write_stakeholder(&accounts(2), 0, (accounts(2), vec![stake_v1]));
+
+        let contract = Stakeable::migrate();
+        assert_eq!(contract.get_state_version(), STATE_VERSION);
+        assert_eq!(contract.get_owner(), owner_id);
+        let stakeholder = contract._get_stakeholder(&accounts(2)).unwrap();
+        let stake = &stakeholder.address_stakes[0];
+        assert_eq!(stake.token_id.as_str(), LEGACY_TOKEN_ID);
+        assert_eq!(stake.claimable.0, 1);
+        assert_eq!(stake.locked_until, 0);
+        assert!(stakeholder.referrer.is_none());
+        assert!(stakeholder.boost.is_none());
+    }
+
+    #[test]
+    fn test_backfill_counts_legacy_stakes() {
+        let mut contract = setup();
+        let legacy_token: AccountId = LEGACY_TOKEN_ID.parse().unwrap();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        let mut key = LEGACY_STAKEHOLDERS_PREFIX.to_vec();
+        key.extend(accounts(3).try_to_vec().unwrap());
+        let record = (
+            accounts(3),
+            vec![(accounts(3), U128(AMOUNT), 0u64, U128(0))],
+        );
+        env::storage_write(&key, &record.try_to_vec().unwrap());
+        write_stakeholder(
+            &accounts(4),
+            0,
+            (
+                accounts(4),
+                vec![(accounts(4), U128(2 * AMOUNT), 0u64, U128(0))],
+            ),
+        );
+        assert_eq!(contract.get_stake_limits().total_staked.0, AMOUNT);
+        // no weight before the backfill writes the checkpoints
+        assert_eq!(contract.staked_balance_at(accounts(3), 0).0, 0);
+
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(at_ms(ONE_HOUR))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        let accounts_page = vec![accounts(2), accounts(3), accounts(4), accounts(5)];
+        assert_eq!(contract.backfill_stakeholders(accounts_page.clone()), 2);
+        assert_eq!(contract.get_stake_limits().total_staked.0, 4 * AMOUNT);
+        let liabilities = contract.get_liabilities(legacy_token);
+        assert_eq!(liabilities.principal.0, 3 * AMOUNT);
+        assert_eq!(liabilities.accrued_rewards.0, 3 * HOURLY_REWARD);
+        // the deposit paid the checkpoints, the storage balances of the accounts are untouched
+        assert!(contract.storage_balance_of(accounts(3)).is_none());
+        assert_eq!(contract.staked_balance_at(accounts(3), 0).0, AMOUNT);
+        // converted records are not counted twice
+        assert_eq!(contract.backfill_stakeholders(accounts_page), 0);
+        assert_eq!(contract.get_stake_limits().total_staked.0, 4 * AMOUNT);
+    }
+
+    #[test]
+    fn test_migrate_from_unversioned_state() {
+        testing_env!(get_context(accounts(0)).build());
+        let allowed_token: LookupSet<AccountId> = LookupSet::new(b"allowedToken".to_vec());
+        let state = (
+            LEGACY_STAKEHOLDERS_PREFIX.to_vec(),
+            accounts(1),
+            allowed_token,
+            (20u32, 1000u32, 18u32),
+        );
+        env::storage_write(b"STATE", &state.try_to_vec().unwrap());
+        let contract = Stakeable::migrate();
+        assert_eq!(contract.get_state_version(), STATE_VERSION);
+        assert_eq!(contract.get_owner(), accounts(1));
+        assert_eq!(contract.config.reward_numerator, 20);
+        assert_eq!(contract.config.decimals, 18);
+        assert_eq!(contract.get_stake_limits().min_stake.0, 1);
+    }
+
+    #[test]
+    fn test_migrate_keeps_current_state() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        contract.state_version = 2;
+        env::state_write(&contract);
+        let contract = Stakeable::migrate();
+        assert_eq!(contract.get_state_version(), STATE_VERSION);
+        assert_eq!(contract.has_stake(accounts(2)).total_amount.0, AMOUNT);
+        assert_eq!(contract.get_stake_limits().total_staked.0, AMOUNT);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Cannot migrate from a newer state version")]
+    fn test_migrate_from_newer_state() {
+        let mut contract = setup();
+        contract.state_version = STATE_VERSION + 1;
+        env::state_write(&contract);
+        Stakeable::migrate();
+    }
+}
@@ -0,0 +1,308 @@
+use crate::*;
+
+/**
+ * @notice
+ * StakePlan is how ft_on_transfer splits a deposit, worked out by _plan_stake
+ */
+pub struct StakePlan {
+    pub quote: DepositQuote,
+    // part of the deposit over the stake limits, returned to the sender
+    pub refund: U128,
+}
+
+/**
+ * @notice
+ * PayoutPreview is what one transfer of a withdrawal or a claim would pay, there is one per token
+ */
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PayoutPreview {
+    pub token_id: AccountId,
+    // principal after the withdraw fee
+    pub principal_out: U128,
+    // reward after the reward fee, the penalty and what vests
+    pub reward_out: U128,
+    pub fees: U128,
+    // instant claim penalty
+    pub penalty: U128,
+    // reward moved into a vesting entry instead of being paid
+    pub vested: U128,
+    // part of principal_out + reward_out not paid because the token is underfunded
+    pub shortfall: U128,
+}
+
+/**
+ * @notice
+ * ActionPreview is the outcome of a call simulated without writing state.
+ * stakes are the stakes of the account afterwards, as has_stake would list them.
+ * When the call would fail error holds the reason and nothing else is set
+ */
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionPreview {
+    pub payouts: Vec<PayoutPreview>,
+    pub deposit_fee: Option<U128>,
+    pub staked: Option<U128>,
+    // returned to the sender by ft_on_transfer
+    pub refund: Option<U128>,
+    pub stakes: Vec<Stake>,
+    pub error: Option<String>,
+}
+
+impl ActionPreview {
+    fn failed(error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _preview_positions runs the withdrawals of _settle_positions and the vesting of _vest_rewards on a copy of the stakeholder
+     */
+    fn _preview_positions(
+        &self,
+        account_id: &AccountId,
+        mut positions: Vec<(usize, Balance)>,
+        instant: bool,
+    ) -> Result<ActionPreview, String> {
+        let mut stakeholder = self
+            ._get_stakeholder(account_id)
+            .ok_or_else(|| "Staking: Account has no stakes".to_string())?;
+        if !sort_positions(&mut positions) {
+            return Err(DUPLICATE_POSITION.to_string());
+        }
+        let mut settlements: Vec<Settlement> = Vec::new();
+        for (index, amount) in positions {
+            let withdrawal = self._compute_withdrawal(&stakeholder, index, amount)?;
+            stakeholder.apply_withdrawal(index, &withdrawal);
+            add_to_settlements(&mut settlements, withdrawal);
+        }
+        let payouts = settlements
+            .into_iter()
+            .map(|settlement| {
+                let reward = settlement.reward.0 - settlement.reward_fee.0;
+                let (penalty, vested) = self._reward_vesting(account_id, reward, instant);
+                let payout = settlement.payout.0 - penalty - vested;
+                let paid = self._payout_share(&settlement.token_id, payout);
+                PayoutPreview {
+                    token_id: settlement.token_id,
+                    principal_out: U128(settlement.principal.0 - settlement.principal_fee.0),
+                    reward_out: U128(reward - penalty - vested),
+                    fees: U128(settlement.principal_fee.0 + settlement.reward_fee.0),
+                    penalty: U128(penalty),
+                    vested: U128(vested),
+                    shortfall: U128(payout - paid),
+                }
+            })
+            .collect();
+        Ok(ActionPreview {
+            payouts,
+            stakes: stakeholder.address_stakes,
+            ..Default::default()
+        })
+    }
+
+    /**
+     * @notice
+     * _preview_deposit runs _plan_stake for amount and adds the result to stakes, the stakes of the account before it
+     */
+    fn _preview_deposit(
+        &self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+        stake_index: Option<usize>,
+        mut stakes: Vec<Stake>,
+    ) -> Result<ActionPreview, String> {
+        let StakePlan { quote, refund } =
+            self._plan_stake(account_id, token_id, amount, stake_index)?;
+        match stake_index {
+            Some(index) => {
+                let stakeholder = self._get_stakeholder(account_id);
+                let boost_collection = stakeholder
+                    .as_ref()
+                    .and_then(|stakeholder| stakeholder.boost_collection());
+                self._grow_stake(&mut stakes[index], quote.staked.0, boost_collection);
+            }
+            None => stakes.push(self._new_stake(account_id, token_id, quote.staked, 0)),
+        }
+        Ok(ActionPreview {
+            deposit_fee: Some(quote.fee),
+            staked: Some(quote.staked),
+            refund: Some(refund),
+            stakes,
+            ..Default::default()
+        })
+    }
+
+    fn _stakes_of(&self, account_id: &AccountId) -> Vec<Stake> {
+        self._get_stakeholder(account_id)
+            .map(|stakeholder| stakeholder.address_stakes)
+            .unwrap_or_default()
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * readonly
+     * preview_withdraw is what withdraw_stake(amount, stake_index) would do if account_id called it now
+     */
+    pub fn preview_withdraw(
+        &self,
+        account_id: AccountId,
+        stake_index: usize,
+        amount: U128,
+    ) -> ActionPreview {
+        self._preview_positions(&account_id, vec![(stake_index, amount.0)], false)
+            .unwrap_or_else(ActionPreview::failed)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * preview_claim is what claim_all(instant) would do if account_id called it now
+     */
+    pub fn preview_claim(&self, account_id: AccountId, instant: Option<bool>) -> ActionPreview {
+        let positions = (0..self._stakes_of(&account_id).len())
+            .map(|index| (index, 0))
+            .collect();
+        self._preview_positions(&account_id, positions, instant.unwrap_or(false))
+            .unwrap_or_else(ActionPreview::failed)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * preview_stake is what ft_transfer_call of amount token_id with msg from account_id would stake now
+     */
+    pub fn preview_stake(
+        &self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> ActionPreview {
+        let stake_index = match TransferMessage::parse(&msg) {
+            Some(TransferMessage::Staking { stake_index, .. }) => {
+                stake_index.map(|index| index as usize)
+            }
+            _ => {
+                return ActionPreview::failed(format!(
+                    "Staking: {:?} is not a staking message, the transfer would be refunded",
+                    msg
+                ))
+            }
+        };
+        self._preview_deposit(
+            &account_id,
+            &token_id,
+            amount.0,
+            stake_index,
+            self._stakes_of(&account_id),
+        )
+        .unwrap_or_else(ActionPreview::failed)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_preview_withdraw_matches_withdraw() {
+        let mut contract = setup();
+        contract.config.fees.withdraw_fee_bps = 100;
+        contract.config.fees.reward_fee_bps = 1_000;
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        let preview = contract.preview_withdraw(accounts(2), 0, U128(AMOUNT));
+        assert!(preview.error.is_none());
+        let payout = &preview.payouts[0];
+        assert_eq!(payout.principal_out.0, AMOUNT - AMOUNT / 100);
+        assert_eq!(payout.reward_out.0, HOURLY_REWARD - HOURLY_REWARD / 10);
+        assert_eq!(payout.fees.0, AMOUNT / 100 + HOURLY_REWARD / 10);
+        assert_eq!(
+            payout.principal_out.0 + payout.reward_out.0,
+            contract
+                .quote_withdraw(accounts(2), 0, U128(AMOUNT))
+                .payout
+                .0
+        );
+        let expected: Vec<Balance> = preview.stakes.iter().map(|stake| stake.amount.0).collect();
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        assert_eq!(amounts(&contract, accounts(2)), expected);
+        assert_eq!(expected, vec![2 * AMOUNT]);
+
+        let preview = contract.preview_withdraw(accounts(2), 3, U128(AMOUNT));
+        assert_eq!(preview.error.unwrap(), "Staking: Stake 3 does not exist");
+        assert!(preview.payouts.is_empty());
+        let preview = contract.preview_withdraw(accounts(3), 0, U128(AMOUNT));
+        assert_eq!(preview.error.unwrap(), "Staking: Account has no stakes");
+    }
+
+    #[test]
+    fn test_preview_claim_with_vesting() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_vesting_config(VestingConfig {
+            duration: 10 * ONE_HOUR,
+            instant_claim_penalty_bps: 2_000,
+        });
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        let vesting = contract.preview_claim(accounts(2), None);
+        assert_eq!(vesting.payouts.len(), 1);
+        assert_eq!(vesting.payouts[0].vested.0, 2 * HOURLY_REWARD);
+        assert_eq!(vesting.payouts[0].reward_out.0, 0);
+        assert_eq!(vesting.stakes.len(), 2);
+        let instant = contract.preview_claim(accounts(2), Some(true));
+        assert_eq!(instant.payouts[0].penalty.0, 2 * HOURLY_REWARD / 5);
+        assert_eq!(instant.payouts[0].reward_out.0, 2 * HOURLY_REWARD * 4 / 5);
+        assert_eq!(instant.payouts[0].principal_out.0, 0);
+        // nothing was written
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            HOURLY_REWARD
+        );
+    }
+
+    #[test]
+    fn test_preview_stake() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_stake_limits(U128(1), Some(U128(3 * AMOUNT / 2)), None);
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        contract.config.fees.deposit_fee_bps = 1_000;
+        let preview =
+            contract.preview_stake(accounts(2), token(), U128(AMOUNT), "staking".to_string());
+        assert!(preview.error.is_none());
+        assert_eq!(preview.refund.unwrap().0, AMOUNT / 2);
+        assert_eq!(preview.deposit_fee.unwrap().0, AMOUNT / 20);
+        assert_eq!(preview.staked.unwrap().0, AMOUNT / 2 - AMOUNT / 20);
+        assert_eq!(preview.stakes.len(), 2);
+        assert_eq!(preview.stakes[1].amount, preview.staked.unwrap());
+        let preview =
+            contract.preview_stake(accounts(2), token(), U128(AMOUNT), "hello".to_string());
+        assert!(preview.error.unwrap().contains("not a staking message"));
+        contract.config.fees.deposit_fee_bps = 0;
+        stake(&mut contract, accounts(2), AMOUNT / 2, 0);
+        let preview = contract.preview_stake(
+            accounts(2),
+            token(),
+            U128(AMOUNT),
+            r#"{"action": "staking"}"#.to_string(),
+        );
+        assert_eq!(preview.error.unwrap(), "Staking: Stake capacity is reached");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardProjection {
+    pub amount: U128,
+    pub duration: u64,
+    pub boost_bps: u16,
+    pub reward: U128,
+    pub reward_fee: U128,
+    // reward - reward_fee, what the staker receives
+    pub net_reward: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeRewardProjection {
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+    // claimable at until, the reward settled so far included
+    pub reward: U128,
+    pub reward_fee: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountRewardProjection {
+    pub account_id: AccountId,
+    pub until: u64,
+    pub boost_bps: u16,
+    pub stakes: Vec<StakeRewardProjection>,
+    pub total_reward: U128,
+}
+
+impl Stakeable {
+    // boost_bps a new stake would earn, boosts are the only reward tier of the contract
+    fn _configured_boost_bps(&self, boosted: bool) -> u16 {
+        match (boosted, &self.config.boost_nft_contract_id) {
+            (true, Some(_)) => self.config.boost_bps,
+            _ => 0,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * readonly
+     * get_apr_bps is the yearly reward rate of a stake without boost in basis points, what reward_per_hour rounds
+     */
+    pub fn get_apr_bps(&self) -> U128 {
+        U128(apr_bps(&self.config, 0))
+    }
+
+    /**
+     * @notice
+     * readonly
+     * get_apy_bps is the yearly rate in basis points when the reward is restaked every compounding_period ms
+     */
+    pub fn get_apy_bps(&self, compounding_period: u64) -> U128 {
+        assert!(
+            compounding_period > 0,
+            "Staking: Compounding period must be more than 0"
+        );
+        U128(apy_bps(&self.config, 0, compounding_period))
+    }
+
+    /**
+     * @notice
+     * readonly
+     * project_rewards is the reward of a new stake of amount kept for duration ms, with the configured boost when boosted
+     */
+    pub fn project_rewards(
+        &self,
+        amount: U128,
+        duration: u64,
+        boosted: Option<bool>,
+    ) -> RewardProjection {
+        let boost_bps = self._configured_boost_bps(boosted.unwrap_or(false));
+        let reward = project_reward(amount.0, duration, &self.config, boost_bps);
+        let reward_fee = fee_of(reward, self.config.fees.reward_fee_bps);
+        RewardProjection {
+            amount,
+            duration,
+            boost_bps,
+            reward: U128(reward),
+            reward_fee: U128(reward_fee),
+            net_reward: U128(reward - reward_fee),
+        }
+    }
+
+    /**
+     * @notice
+     * readonly
+     * project_account_rewards is what every stake of account_id could claim at until_ts (ms) if nothing changes before
+     */
+    pub fn project_account_rewards(
+        &self,
+        account_id: AccountId,
+        until_ts: u64,
+    ) -> AccountRewardProjection {
+        assert!(
+            until_ts >= env::block_timestamp_ms(),
+            "Staking: Projection must end in the future"
+        );
+        let stakeholder = self._get_stakeholder(&account_id);
+        let boost_bps = stakeholder
+            .as_ref()
+            .map(|stakeholder| self._boost_bps(stakeholder))
+            .unwrap_or(0);
+        let boost_collection = stakeholder
+            .as_ref()
+            .and_then(|stakeholder| stakeholder.boost_collection().cloned());
+        let stakes: Vec<StakeRewardProjection> = stakeholder
+            .map(|stakeholder| stakeholder.address_stakes)
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(index, stake)| {
+                let end = self._reward_end(&stake.token_id, until_ts);
+                let accrued = self._accrued_reward(stake, end, |period| {
+                    period.boost_bps_of(boost_collection.as_ref())
+                });
+                let reward = stake.claimable.0.saturating_add(accrued);
+                StakeRewardProjection {
+                    stake_index: index as u64,
+                    token_id: stake.token_id.clone(),
+                    amount: stake.amount,
+                    reward: U128(reward),
+                    reward_fee: U128(fee_of(reward, self.config.fees.reward_fee_bps)),
+                }
+            })
+            .collect();
+        AccountRewardProjection {
+            account_id,
+            until: until_ts,
+            boost_bps,
+            total_reward: U128(stakes.iter().map(|stake| stake.reward.0).sum()),
+            stakes,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_apr_and_projection_views() {
+        let mut contract = setup();
+        assert_eq!(contract.get_apr_bps().0, 150 * 24 * 365);
+        assert_eq!(contract.get_apy_bps(ONE_YEAR).0, contract.get_apr_bps().0);
+        contract.config.fees.reward_fee_bps = 1_000;
+        let projection = contract.project_rewards(U128(AMOUNT), ONE_HOUR, Some(true));
+        assert_eq!(projection.boost_bps, 0);
+        assert_eq!(projection.reward.0, HOURLY_REWARD);
+        assert_eq!(projection.reward_fee.0, HOURLY_REWARD / 10);
+        assert_eq!(projection.net_reward.0, HOURLY_REWARD * 9 / 10);
+        contract.config.boost_nft_contract_id = Some(accounts(4));
+        contract.config.boost_bps = 5_000;
+        let projection = contract.project_rewards(U128(AMOUNT), ONE_HOUR, Some(true));
+        assert_eq!(projection.reward.0, HOURLY_REWARD * 3 / 2);
+        assert_eq!(
+            contract
+                .project_rewards(U128(AMOUNT), ONE_HOUR, None)
+                .reward
+                .0,
+            HOURLY_REWARD
+        );
+    }
+
+    #[test]
+    fn test_project_account_rewards() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), 2 * AMOUNT, ONE_HOUR);
+        call_at(accounts(2), ONE_HOUR);
+        let projection = contract.project_account_rewards(accounts(2), 3 * ONE_HOUR);
+        assert_eq!(projection.stakes.len(), 2);
+        assert_eq!(projection.stakes[0].reward.0, 3 * HOURLY_REWARD);
+        assert_eq!(projection.stakes[1].reward.0, 4 * HOURLY_REWARD);
+        assert_eq!(projection.total_reward.0, 7 * HOURLY_REWARD);
+        // matches the claimable of has_stake once the time has come
+        call_at(accounts(2), 3 * ONE_HOUR);
+        let stakes = contract.has_stake(accounts(2)).stakes;
+        assert_eq!(stakes[1].claimable.0, 4 * HOURLY_REWARD);
+        let unknown = contract.project_account_rewards(accounts(3), 3 * ONE_HOUR);
+        assert!(unknown.stakes.is_empty());
+        assert_eq!(unknown.total_reward.0, 0);
+    }
+}
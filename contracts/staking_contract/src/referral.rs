@@ -0,0 +1,330 @@
+use crate::*;
+
+pub const MAX_REFERRAL_BPS: u16 = 5_000;
+pub const ON_REFERRAL_PAYOUT_GAS: Gas = Gas(10_000_000_000_000);
+
+impl Stakeable {
+    /**
+     * @notice
+     * _bind_referrer sets the referrer of an account, it is ignored once a referrer is set
+     */
+    pub(crate) fn _bind_referrer(&mut self, account_id: &AccountId, referrer_id: AccountId) {
+        if &referrer_id == account_id {
+            log_str("Staking: Cannot refer yourself");
+            return;
+        }
+        let mut stakeholder = match self._get_stakeholder(account_id) {
+            Some(stakeholder) => stakeholder,
+            None => self._add_stakeholder(account_id.clone()),
+        };
+        if stakeholder.referrer.is_some() {
+            return;
+        }
+        stakeholder.referrer = Some(referrer_id.clone());
+        self._save_stakeholder(account_id, stakeholder);
+        ReferralBound {
+            account_id: account_id.clone(),
+            referrer_id,
+        }
+        .emit();
+    }
+
+    /**
+     * @notice
+     * _referral_share is what the referrer of the stakeholder earns when it claims reward
+     */
+    pub(crate) fn _referral_share(&self, stakeholder: &StakeHolder, reward: Balance) -> Balance {
+        match stakeholder.referrer {
+            Some(_) => reward * self.config.referral_bps as u128 / BPS_DENOMINATOR,
+            None => 0,
+        }
+    }
+
+    /**
+     * @notice
+     * _credit_referral adds the referral share of a claimed reward to the referrer.
+     * reward is the gross reward, the share is taken before the reward fee and paid on top of it,
+     * the referee still gets the full amount less the fee
+     */
+    pub(crate) fn _credit_referral(
+        &mut self,
+        stakeholder: &StakeHolder,
+        token_id: &AccountId,
+        reward: Balance,
+    ) {
+        let referrer_id = match &stakeholder.referrer {
+            Some(referrer_id) => referrer_id,
+            None => return,
+        };
+        let amount = self._referral_share(stakeholder, reward);
+        if amount == 0 {
+            return;
+        }
+        self._add_referral_reward(referrer_id, token_id, amount);
+        ReferralRewardCredited {
+            referrer_id: referrer_id.clone(),
+            referee_id: stakeholder.address.clone(),
+            token_id: token_id.clone(),
+            amount: U128(amount),
+        }
+        .emit();
+    }
+
+    fn _add_referral_reward(
+        &mut self,
+        referrer_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut rewards = self.referral_rewards.get(referrer_id).unwrap_or_default();
+        let accrued = rewards.entry(token_id.clone()).or_insert(U128(0));
+        accrued.0 += amount;
+        self.referral_rewards.insert(referrer_id, &rewards);
+        self._reserve(token_id, amount);
+    }
+
+    /**
+     * @notice
+     * _remove_referral_reward takes back a referral credit of a withdrawal that was rolled back,
+     * what the referrer already claimed in between cannot be taken back
+     */
+    pub(crate) fn _remove_referral_reward(
+        &mut self,
+        referrer_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let mut rewards = self.referral_rewards.get(referrer_id).unwrap_or_default();
+        if let Some(accrued) = rewards.get_mut(token_id) {
+            let removed = amount.min(accrued.0);
+            accrued.0 -= removed;
+            self.referral_rewards.insert(referrer_id, &rewards);
+            self._unreserve(token_id, removed);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * set_referrer binds the caller to a referrer, it can only be done once
+     */
+    pub fn set_referrer(&mut self, referrer_id: AccountId) {
+        let account_id = env::predecessor_account_id();
+        if let Some(referrer) = self
+            ._get_stakeholder(&account_id)
+            .and_then(|stakeholder| stakeholder.referrer)
+        {
+            env::panic_str(format!("Staking: Referrer is already set to {}", referrer).as_str());
+        }
+        assert_ne!(referrer_id, account_id, "Staking: Cannot refer yourself");
+        self._bind_referrer(&account_id, referrer_id);
+    }
+
+    /**
+     * @notice
+     * Like the fee setters, the owner can only call it until the first stake, then it takes a proposal
+     */
+    pub fn set_referral_bps(&mut self, referral_bps: u16) {
+        self.assert_owner_before_staking();
+        assert!(
+            referral_bps <= MAX_REFERRAL_BPS,
+            "Staking: Referral share cannot be more than {} bps",
+            MAX_REFERRAL_BPS
+        );
+        self.config.referral_bps = referral_bps;
+    }
+
+    // * readonly
+    pub fn get_referrer(&self, account_id: AccountId) -> Option<AccountId> {
+        self._get_stakeholder(&account_id)
+            .and_then(|stakeholder| stakeholder.referrer)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * Referral rewards accrued by a referrer and not claimed yet, per token
+     */
+    pub fn get_referral_rewards(&self, referrer_id: AccountId) -> HashMap<AccountId, U128> {
+        self.referral_rewards.get(&referrer_id).unwrap_or_default()
+    }
+
+    /**
+     * @notice
+     * claim_referral_rewards transfers the accrued referral rewards of a token to the caller, cut pro rata when the token is underfunded,
+     * the balance is restored in on_referral_payout if the transfer fails
+     */
+    pub fn claim_referral_rewards(&mut self, token_id: AccountId) -> Promise {
+        let referrer_id = env::predecessor_account_id();
+        let mut rewards = self.referral_rewards.get(&referrer_id).unwrap_or_default();
+        let amount = rewards
+            .remove(&token_id)
+            .filter(|amount| amount.0 > 0)
+            .unwrap_or_else(|| env::panic_str("Staking: No referral rewards to claim"));
+        if rewards.is_empty() {
+            self.referral_rewards.remove(&referrer_id);
+        } else {
+            self.referral_rewards.insert(&referrer_id, &rewards);
+        }
+        self._unreserve(&token_id, amount.0);
+        let paid = U128(self._pro_rata_payout(&referrer_id, &token_id, amount.0));
+        self._register_on_token(&token_id, &referrer_id)
+            .then(
+                ext_ft::ext(token_id.clone())
+                    .with_static_gas(FT_TRANSFER_GAS)
+                    .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                    .ft_transfer(
+                        referrer_id.to_string(),
+                        paid.0.to_string(),
+                        Some("referral reward".to_string()),
+                    ),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_REFERRAL_PAYOUT_GAS)
+                    .on_referral_payout(referrer_id, token_id, amount, paid),
+            )
+    }
+
+    #[private]
+    pub fn on_referral_payout(
+        &mut self,
+        referrer_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        paid: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                ReferralRewardPaid {
+                    referrer_id,
+                    token_id,
+                    amount: paid,
+                }
+                .emit();
+                paid
+            }
+            _ => {
+                log_str("Staking: Referral payout failed, rewards are restored");
+                self._remove_shortfall(&referrer_id, &token_id, amount.0 - paid.0);
+                self._add_referral_reward(&referrer_id, &token_id, amount.0);
+                U128(0)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    // a stake of sender_id whose message names a referrer
+    fn stake_referred(
+        contract: &mut Stakeable,
+        sender_id: AccountId,
+        referrer_id: AccountId,
+        amount: Balance,
+        timestamp: u64,
+    ) {
+        testing_env!(get_context(token())
+            .block_timestamp(at_ms(timestamp))
+            .build());
+        let msg = near_sdk::serde_json::json!({"action": "staking", "referrer_id": referrer_id});
+        let refund = contract.ft_on_transfer(sender_id, U128(amount), msg.to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+    }
+
+    fn referral_reward_of(contract: &Stakeable, referrer_id: AccountId) -> Balance {
+        contract
+            .get_referral_rewards(referrer_id)
+            .get(&token())
+            .map_or(0, |amount| amount.0)
+    }
+
+    #[test]
+    fn test_referrer_is_bound_once() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        stake_referred(&mut contract, accounts(2), accounts(3), AMOUNT, 0);
+        assert_eq!(contract.get_referrer(accounts(2)), Some(accounts(3)));
+        // a later referrer is ignored
+        stake_referred(&mut contract, accounts(2), accounts(4), AMOUNT, 0);
+        assert_eq!(contract.get_referrer(accounts(2)), Some(accounts(3)));
+        // so is a referral of yourself, the stake still goes through
+        stake_referred(&mut contract, accounts(3), accounts(3), AMOUNT, 0);
+        assert_eq!(contract.get_referrer(accounts(3)), None);
+        assert_eq!(amounts(&contract, accounts(3)), vec![AMOUNT]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Cannot refer yourself")]
+    fn test_set_referrer_rejects_self() {
+        let mut contract = setup();
+        call_at(accounts(2), 0);
+        contract.set_referrer(accounts(2));
+    }
+
+    #[test]
+    fn test_referral_share_is_credited_and_paid() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_referral_bps(1_000);
+        contract.set_fee_config(FeeConfig {
+            reward_fee_bps: 2_000,
+            ..FeeConfig::default()
+        });
+        stake_referred(&mut contract, accounts(2), accounts(3), AMOUNT, 0);
+
+        // the share is 10% of the gross reward, the referee pays the 20% fee and nothing more
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(0), 0);
+        assert_eq!(ft_transfer_amounts(), vec![HOURLY_REWARD * 8 / 10]);
+        let share = HOURLY_REWARD / 10;
+        let fee = HOURLY_REWARD * 2 / 10;
+        assert_eq!(referral_reward_of(&contract, accounts(3)), share);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, fee + share);
+
+        call_at(accounts(3), ONE_HOUR);
+        contract.claim_referral_rewards(token());
+        assert_eq!(ft_transfer_amounts(), vec![share]);
+        assert_eq!(referral_reward_of(&contract, accounts(3)), 0);
+
+        // a failed transfer restores the referral rewards
+        callback_at(ONE_HOUR, PromiseResult::Failed);
+        let paid = contract.on_referral_payout(accounts(3), token(), U128(share), U128(share));
+        assert_eq!(paid.0, 0);
+        assert_eq!(referral_reward_of(&contract, accounts(3)), share);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, fee + share);
+    }
+
+    #[test]
+    fn test_withdraw_rollback_takes_back_the_referral_share() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_referral_bps(1_000);
+        stake_referred(&mut contract, accounts(2), accounts(3), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        let share = HOURLY_REWARD / 10;
+        assert_eq!(referral_reward_of(&contract, accounts(3)), share);
+
+        callback_at(ONE_HOUR, PromiseResult::Failed);
+        let rolled_back = Settlement {
+            referral_reward: U128(share),
+            ..settlement(AMOUNT, HOURLY_REWARD)
+        };
+        contract.on_withdraw_resolved(accounts(2), rolled_back);
+        assert_eq!(referral_reward_of(&contract, accounts(3)), 0);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, 0);
+        assert_eq!(amounts(&contract, accounts(2)), vec![AMOUNT]);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::*;
+
+pub const STORAGE_BALANCE_OF_GAS: Gas = Gas(5_000_000_000_000);
+pub const STORAGE_DEPOSIT_GAS: Gas = Gas(10_000_000_000_000);
+pub const ON_ACCOUNT_REGISTERED_GAS: Gas = Gas(5_000_000_000_000);
+pub const ON_STORAGE_CHECKED_GAS: Gas = Gas(25_000_000_000_000);
+// storage_balance_bounds().min of the near-contract-standards tokens like nolan_token, 125 bytes
+pub const TOKEN_REGISTRATION_DEPOSIT: Balance = 1_250_000_000_000_000_000_000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+/**
+ * @notice
+ * Account: the storage balance of the account in this contract
+ * Reserve: the storage reserve funded with fund_storage_reserve and allow_token
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationPayer {
+    Account,
+    Reserve,
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _register_on_token makes sure account_id is registered on the token before a transfer chained after it.
+     * The transfer runs whatever happens, a failed registration only makes the transfer fail and roll back
+     */
+    pub(crate) fn _register_on_token(
+        &self,
+        token_id: &AccountId,
+        account_id: &AccountId,
+    ) -> Promise {
+        ext_ft::ext(token_id.clone())
+            .with_static_gas(STORAGE_BALANCE_OF_GAS)
+            .storage_balance_of(account_id.clone())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_STORAGE_CHECKED_GAS)
+                    .on_storage_checked(token_id.clone(), account_id.clone()),
+            )
+    }
+
+    /**
+     * @notice
+     * _take_registration_deposit pays a registration from the storage balance of the account, else from the reserve
+     */
+    fn _take_registration_deposit(&mut self, account_id: &AccountId) -> Option<RegistrationPayer> {
+        let balance = self.storage_balances.get(account_id).unwrap_or(0);
+        if account_id != &env::current_account_id() && balance >= TOKEN_REGISTRATION_DEPOSIT {
+            self.storage_balances
+                .insert(account_id, &(balance - TOKEN_REGISTRATION_DEPOSIT));
+            return Some(RegistrationPayer::Account);
+        }
+        if self.storage_reserve >= TOKEN_REGISTRATION_DEPOSIT {
+            self.storage_reserve -= TOKEN_REGISTRATION_DEPOSIT;
+            return Some(RegistrationPayer::Reserve);
+        }
+        None
+    }
+
+    fn _refund_registration_deposit(&mut self, account_id: &AccountId, payer: RegistrationPayer) {
+        match payer {
+            RegistrationPayer::Account => {
+                let balance = self.storage_balances.get(account_id).unwrap_or(0);
+                self.storage_balances
+                    .insert(account_id, &(balance + TOKEN_REGISTRATION_DEPOSIT));
+            }
+            RegistrationPayer::Reserve => self.storage_reserve += TOKEN_REGISTRATION_DEPOSIT,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * on_storage_checked registers account_id on the token with storage_deposit when storage_balance_of found nothing.
+     * Tokens without storage_balance_of are treated as registered, returns whether the account is registered
+     */
+    #[private]
+    pub fn on_storage_checked(
+        &mut self,
+        token_id: AccountId,
+        account_id: AccountId,
+    ) -> PromiseOrValue<bool> {
+        let registered = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<Option<StorageBalance>>(&value)
+                    .map(|balance| balance.is_some())
+                    .unwrap_or(true)
+            }
+            _ => true,
+        };
+        if registered {
+            return PromiseOrValue::Value(true);
+        }
+        let payer = match self._take_registration_deposit(&account_id) {
+            Some(payer) => payer,
+            None => {
+                log_str(&format!(
+                    "Staking: No storage balance or reserve to register {} on {}",
+                    account_id, token_id
+                ));
+                return PromiseOrValue::Value(false);
+            }
+        };
+        PromiseOrValue::Promise(
+            ext_ft::ext(token_id.clone())
+                .with_static_gas(STORAGE_DEPOSIT_GAS)
+                .with_attached_deposit(TOKEN_REGISTRATION_DEPOSIT)
+                .storage_deposit(Some(account_id.clone()), Some(true))
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(ON_ACCOUNT_REGISTERED_GAS)
+                        .on_account_registered(token_id, account_id, payer),
+                ),
+        )
+    }
+
+    /**
+     * @notice
+     * on_account_registered gives the deposit back to whoever paid it when the registration failed
+     */
+    #[private]
+    pub fn on_account_registered(
+        &mut self,
+        token_id: AccountId,
+        account_id: AccountId,
+        payer: RegistrationPayer,
+    ) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                TokenAccountRegistered {
+                    token_id,
+                    account_id,
+                    deposit: U128(TOKEN_REGISTRATION_DEPOSIT),
+                    payer,
+                }
+                .emit();
+                true
+            }
+            _ => {
+                log_str(&format!(
+                    "Staking: Registering {} on {} failed",
+                    account_id, token_id
+                ));
+                self._refund_registration_deposit(&account_id, payer);
+                false
+            }
+        }
+    }
+
+    /**
+     * @notice
+     * fund_storage_reserve adds the attached deposit to the reserve that pays token registrations
+     */
+    #[payable]
+    pub fn fund_storage_reserve(&mut self) -> U128 {
+        assert!(
+            env::attached_deposit() > 0,
+            "Staking: Requires attached deposit"
+        );
+        self.storage_reserve += env::attached_deposit();
+        U128(self.storage_reserve)
+    }
+
+    pub fn withdraw_storage_reserve(&mut self, amount: U128) -> Promise {
+        self.assert_owner();
+        assert!(
+            amount.0 <= self.storage_reserve,
+            "Staking: Not enough in the storage reserve"
+        );
+        self.storage_reserve -= amount.0;
+        Promise::new(self.owner_id.clone()).transfer(amount.0)
+    }
+
+    // * readonly
+    pub fn get_storage_reserve(&self) -> U128 {
+        U128(self.storage_reserve)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_token_registration() {
+        let mut contract = setup();
+        let unregistered = || PromiseResult::Successful(b"null".to_vec());
+        // already registered, nothing to pay
+        callback_at(
+            0,
+            PromiseResult::Successful(br#"{"total":"1","available":"0"}"#.to_vec()),
+        );
+        assert!(matches!(
+            contract.on_storage_checked(token(), accounts(2)),
+            PromiseOrValue::Value(true)
+        ));
+        // paid from the storage balance of the account and given back when storage_deposit fails
+        let balance = contract
+            .storage_balance_of(accounts(2))
+            .unwrap()
+            .available
+            .0;
+        callback_at(0, unregistered());
+        assert!(matches!(
+            contract.on_storage_checked(token(), accounts(2)),
+            PromiseOrValue::Promise(_)
+        ));
+        assert_eq!(
+            contract
+                .storage_balance_of(accounts(2))
+                .unwrap()
+                .available
+                .0,
+            balance - TOKEN_REGISTRATION_DEPOSIT
+        );
+        callback_at(0, PromiseResult::Failed);
+        assert!(!contract.on_account_registered(token(), accounts(2), RegistrationPayer::Account));
+        assert_eq!(
+            contract
+                .storage_balance_of(accounts(2))
+                .unwrap()
+                .available
+                .0,
+            balance
+        );
+        // no storage balance and no reserve
+        callback_at(0, unregistered());
+        assert!(matches!(
+            contract.on_storage_checked(token(), accounts(3)),
+            PromiseOrValue::Value(false)
+        ));
+        // paid from the reserve
+        testing_env!(get_context(accounts(1)).attached_deposit(ONE_NEAR).build());
+        assert_eq!(contract.fund_storage_reserve().0, ONE_NEAR);
+        callback_at(0, unregistered());
+        assert!(matches!(
+            contract.on_storage_checked(token(), accounts(3)),
+            PromiseOrValue::Promise(_)
+        ));
+        callback_at(0, PromiseResult::Successful(vec![]));
+        assert!(contract.on_account_registered(token(), accounts(3), RegistrationPayer::Reserve));
+        assert_eq!(
+            contract.get_storage_reserve().0,
+            ONE_NEAR - TOKEN_REGISTRATION_DEPOSIT
+        );
+    }
+}
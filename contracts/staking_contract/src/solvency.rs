@@ -0,0 +1,823 @@
+use crate::*;
+
+pub const FT_BALANCE_OF_GAS: Gas = Gas(5_000_000_000_000);
+pub const ON_BALANCE_RECONCILED_GAS: Gas = Gas(20_000_000_000_000);
+pub const ON_SHORTFALL_PAID_GAS: Gas = Gas(10_000_000_000_000);
+
+/**
+ * @notice
+ * TokenLiabilities is what the contract owes in one token, kept up to date by _save_stakeholder and the reserves.
+ * The weights are the sums of amount * (active time at since - origin) of the stakes,
+ * so the rewards accrued by all of them are worked out at once with weighted_reward.
+ * When the rates change, what was accrued at the old ones is folded into claimable and the weights restart there
+ */
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default)]
+pub struct TokenLiabilities {
+    pub principal: Balance,
+    // principal of the stakeholders holding a boost NFT
+    pub boosted_principal: Balance,
+    // rewards settled on the stakes
+    pub claimable: Balance,
+    pub weight: i128,
+    pub boosted_weight: i128,
+    // active time the weights start from, keeps them small enough for an i128
+    pub origin: u64,
+    // vesting rewards, referral rewards, ve locks and shortfalls, the treasury and airdrops are read from their own records
+    pub reserved: Balance,
+    // periods of the rate history folded into claimable
+    pub rate_periods: u64,
+}
+
+impl TokenLiabilities {
+    // claimable is the settled reward of the stake and what it accrued in the periods folded so far
+    fn apply(
+        &mut self,
+        amount: Balance,
+        claimable: Balance,
+        boosted: bool,
+        active_since: u64,
+        adding: bool,
+    ) {
+        let weight = (amount as i128).saturating_mul(active_since as i128 - self.origin as i128);
+        if adding {
+            self.principal += amount;
+            self.claimable += claimable;
+            self.weight = self.weight.saturating_add(weight);
+            if boosted {
+                self.boosted_principal += amount;
+                self.boosted_weight = self.boosted_weight.saturating_add(weight);
+            }
+        } else {
+            // stakes saved before liabilities were tracked were never added
+            self.principal = self.principal.saturating_sub(amount);
+            self.claimable = self.claimable.saturating_sub(claimable);
+            self.weight = self.weight.saturating_sub(weight);
+            if boosted {
+                self.boosted_principal = self.boosted_principal.saturating_sub(amount);
+                self.boosted_weight = self.boosted_weight.saturating_sub(weight);
+            }
+        }
+    }
+
+    // amount * ms staked by all the stakes up to active_end, what stakes started after it add is ignored
+    fn accrued_weight(principal: Balance, weight: i128, origin: u64, active_end: u64) -> u128 {
+        let elapsed = active_end as i128 - origin as i128;
+        (principal as i128)
+            .saturating_mul(elapsed)
+            .saturating_sub(weight)
+            .max(0) as u128
+    }
+
+    /**
+     * @notice
+     * fold moves what the stakes accrued until active_end, the end of period, into claimable at the rate of the period
+     * and restarts every weight there, as if all the stakes started at the end of the period
+     */
+    fn fold(&mut self, period: &RatePeriod, active_end: u64) {
+        let accrued = weighted_period_reward(
+            Self::accrued_weight(self.principal, self.weight, self.origin, active_end),
+            period,
+        );
+        let boosted = weighted_period_reward(
+            Self::accrued_weight(
+                self.boosted_principal,
+                self.boosted_weight,
+                self.origin,
+                active_end,
+            ),
+            period,
+        );
+        self.claimable += accrued + boosted * liability_boost_bps(period) as u128 / BPS_DENOMINATOR;
+        let elapsed = active_end as i128 - self.origin as i128;
+        self.weight = (self.principal as i128).saturating_mul(elapsed);
+        self.boosted_weight = (self.boosted_principal as i128).saturating_mul(elapsed);
+        self.rate_periods += 1;
+    }
+}
+
+// the liabilities count every boosted stakeholder at the boost of the period, whatever the collection of its NFT
+fn liability_boost_bps(period: &RatePeriod) -> u16 {
+    match period.boost_nft_contract_id {
+        Some(_) => period.boost_bps,
+        None => 0,
+    }
+}
+
+/**
+ * @notice
+ * TokenSolvency is the outcome of the last reconcile of a token and the pauses of its rewards.
+ * Rewards stop accruing while the token is underfunded and start again once a reconcile finds it covered
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSolvency {
+    // ft_balance_of the contract and the liabilities it was compared with
+    pub balance: U128,
+    pub liabilities: U128,
+    pub checked_at: u64,
+    // rewards are paused since then because the balance does not cover the liabilities
+    pub paused_since: Option<u64>,
+    // earlier pauses as (start, end) in ms
+    pub pauses: Vec<(u64, u64)>,
+}
+
+impl TokenSolvency {
+    pub fn underfunded(&self) -> bool {
+        self.paused_since.is_some()
+    }
+
+    /**
+     * @notice
+     * active_time is t minus the time rewards were paused before t, rewards accrue between two active times
+     */
+    pub fn active_time(&self, t: u64) -> u64 {
+        active_time(&self.pauses, self.paused_since, t)
+    }
+}
+
+/**
+ * @notice
+ * Liabilities are the tokens the contract owes in one token, total is what its balance has to cover
+ */
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Liabilities {
+    pub principal: U128,
+    // settled and accrued rewards of the stakes, boosts included
+    pub accrued_rewards: U128,
+    // vesting and referral rewards, ve locks, shortfalls, the treasury and airdrops not claimed yet
+    pub reserved: U128,
+    pub total: U128,
+}
+
+/**
+ * @notice
+ * pro_rata is the share balance / liabilities of amount, the ratio is scaled down when the product does not fit
+ */
+pub fn pro_rata(amount: Balance, balance: Balance, liabilities: Balance) -> Balance {
+    let (mut balance, mut liabilities) = (balance, liabilities);
+    loop {
+        if liabilities == 0 {
+            return 0;
+        }
+        match amount.checked_mul(balance) {
+            Some(product) => return product / liabilities,
+            None => {
+                balance >>= 1;
+                liabilities >>= 1;
+            }
+        }
+    }
+}
+
+impl Stakeable {
+    pub(crate) fn _active_time(&self, token_id: &AccountId, t: u64) -> u64 {
+        self.token_solvency
+            .get(token_id)
+            .map(|solvency| solvency.active_time(t))
+            .unwrap_or(t)
+    }
+
+    pub(crate) fn _underfunded(&self, token_id: &AccountId) -> bool {
+        self.token_solvency
+            .get(token_id)
+            .map(|solvency| solvency.underfunded())
+            .unwrap_or(false)
+    }
+
+    /**
+     * @notice
+     * _token_liabilities reads the liabilities of a token with the rate periods that ended since they were saved folded in
+     */
+    fn _token_liabilities(&self, token_id: &AccountId) -> TokenLiabilities {
+        let mut record = self
+            .token_liabilities
+            .get(token_id)
+            .unwrap_or_else(|| TokenLiabilities {
+                origin: self._active_time(token_id, env::block_timestamp_ms()),
+                rate_periods: self.rate_history.len() as u64,
+                ..Default::default()
+            });
+        while let Some(period) = self.rate_history.get(record.rate_periods as usize) {
+            let active_end = self._active_time(token_id, self._reward_end(token_id, period.end));
+            record.fold(period, active_end);
+        }
+        record
+    }
+
+    /**
+     * @notice
+     * _folded_stake is where a stake counts from in liabilities that folded rate_periods periods and what it accrued
+     * in those periods, a stake started before the end of the last one counts from there
+     */
+    fn _folded_stake(&self, stake: &Stake, boosted: bool, rate_periods: u64) -> (u64, Balance) {
+        let folded_until = match (rate_periods as usize).checked_sub(1) {
+            Some(last) => self.rate_history[last].end,
+            None => return (stake.since, 0),
+        };
+        if stake.since >= folded_until {
+            return (stake.since, 0);
+        }
+        let end = self._reward_end(&stake.token_id, folded_until);
+        let accrued = self._accrued_reward(stake, end, |period| match boosted {
+            true => liability_boost_bps(period),
+            false => 0,
+        });
+        (folded_until, accrued)
+    }
+
+    /**
+     * @notice
+     * _track_liabilities replaces the stakes of previous with the stakes of current in the liabilities of their tokens
+     */
+    pub(crate) fn _track_liabilities(
+        &mut self,
+        previous: Option<&StakeHolder>,
+        current: &StakeHolder,
+    ) {
+        let mut records: Vec<(AccountId, TokenLiabilities)> = Vec::new();
+        let changes = previous
+            .map(|stakeholder| (stakeholder, false))
+            .into_iter()
+            .chain(std::iter::once((current, true)));
+        for (stakeholder, adding) in changes {
+            let boosted = stakeholder.boost.is_some();
+            for stake in stakeholder.address_stakes.iter() {
+                let position = match records
+                    .iter()
+                    .position(|(token_id, _)| token_id == &stake.token_id)
+                {
+                    Some(position) => position,
+                    None => {
+                        let record = self._token_liabilities(&stake.token_id);
+                        records.push((stake.token_id.clone(), record));
+                        records.len() - 1
+                    }
+                };
+                let record = &mut records[position].1;
+                let (since, folded) = self._folded_stake(stake, boosted, record.rate_periods);
+                let active_since = self._active_time(&stake.token_id, since);
+                record.apply(
+                    stake.amount.0,
+                    stake.claimable.0 + folded,
+                    boosted,
+                    active_since,
+                    adding,
+                );
+            }
+        }
+        for (token_id, record) in records {
+            self.token_liabilities.insert(&token_id, &record);
+        }
+    }
+
+    /**
+     * @notice
+     * _reserve and _unreserve count tokens owed outside the stakes, like vesting rewards
+     */
+    pub(crate) fn _reserve(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        let mut record = self._token_liabilities(token_id);
+        record.reserved += amount;
+        self.token_liabilities.insert(token_id, &record);
+    }
+
+    pub(crate) fn _unreserve(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        let mut record = self._token_liabilities(token_id);
+        record.reserved = record.reserved.saturating_sub(amount);
+        self.token_liabilities.insert(token_id, &record);
+    }
+
+    pub(crate) fn _liabilities(&self, token_id: &AccountId) -> Liabilities {
+        let record = self._token_liabilities(token_id);
+        let now = env::block_timestamp_ms();
+        let active_end = self._active_time(token_id, self._reward_end(token_id, now));
+        let boost_bps = match self.config.boost_nft_contract_id {
+            Some(_) => self.config.boost_bps,
+            None => 0,
+        };
+        let accrued = weighted_reward(
+            TokenLiabilities::accrued_weight(
+                record.principal,
+                record.weight,
+                record.origin,
+                active_end,
+            ),
+            &self.config,
+        );
+        let boosted = weighted_reward(
+            TokenLiabilities::accrued_weight(
+                record.boosted_principal,
+                record.boosted_weight,
+                record.origin,
+                active_end,
+            ),
+            &self.config,
+        );
+        let accrued_rewards =
+            record.claimable + accrued + boosted * boost_bps as u128 / BPS_DENOMINATOR;
+        let airdrops: Balance = self
+            .airdrops
+            .iter()
+            .filter(|campaign| &campaign.token_id == token_id && !campaign.swept)
+            .map(|campaign| campaign.funded.0.saturating_sub(campaign.claimed.0))
+            .sum();
+        let reserved = record.reserved + self.treasury.get(token_id).unwrap_or(0) + airdrops;
+        Liabilities {
+            principal: U128(record.principal),
+            accrued_rewards: U128(accrued_rewards),
+            reserved: U128(reserved),
+            total: U128(record.principal + accrued_rewards + reserved),
+        }
+    }
+
+    /**
+     * @notice
+     * _payout_share is what is paid of a payout of token_id, all of it unless the token is underfunded
+     */
+    pub(crate) fn _payout_share(&self, token_id: &AccountId, payout: Balance) -> Balance {
+        match self.token_solvency.get(token_id) {
+            Some(solvency) if solvency.underfunded() => {
+                pro_rata(payout, solvency.balance.0, solvency.liabilities.0)
+            }
+            _ => payout,
+        }
+    }
+
+    /**
+     * @notice
+     * _pro_rata_payout is _payout_share of an outgoing transfer to account_id, a cut is recorded with ProRataPayout.
+     * Whoever is paid later gets the same share, the rest is owed to account_id as a shortfall paid by claim_shortfall
+     * once the token is covered again. Callbacks of a failed transfer take the shortfall back with _remove_shortfall
+     */
+    pub(crate) fn _pro_rata_payout(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Balance {
+        let paid = self._payout_share(token_id, amount);
+        if paid != amount {
+            ProRataPayout {
+                account_id: account_id.clone(),
+                token_id: token_id.clone(),
+                amount: U128(amount),
+                paid: U128(paid),
+                shortfall: U128(amount - paid),
+            }
+            .emit();
+            self._add_shortfall(account_id, token_id, amount - paid);
+        }
+        paid
+    }
+
+    fn _add_shortfall(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        let mut shortfalls = self.shortfalls.get(account_id).unwrap_or_default();
+        shortfalls.entry(token_id.clone()).or_insert(U128(0)).0 += amount;
+        self.shortfalls.insert(account_id, &shortfalls);
+        self._reserve(token_id, amount);
+    }
+
+    pub(crate) fn _remove_shortfall(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let mut shortfalls = self.shortfalls.get(account_id).unwrap_or_default();
+        let removed = match shortfalls.get_mut(token_id) {
+            Some(owed) => {
+                let removed = amount.min(owed.0);
+                owed.0 -= removed;
+                removed
+            }
+            None => return,
+        };
+        shortfalls.retain(|_, owed| owed.0 > 0);
+        if shortfalls.is_empty() {
+            self.shortfalls.remove(account_id);
+        } else {
+            self.shortfalls.insert(account_id, &shortfalls);
+        }
+        self._unreserve(token_id, removed);
+    }
+
+    /**
+     * @notice
+     * _pro_rata_settlements cuts every payout of an underfunded token to the share of the liabilities the balance covered
+     * at the last reconcile
+     */
+    pub(crate) fn _pro_rata_settlements(
+        &mut self,
+        account_id: &AccountId,
+        settlements: &mut [Settlement],
+    ) {
+        for settlement in settlements.iter_mut() {
+            let paid = self._pro_rata_payout(account_id, &settlement.token_id, settlement.payout.0);
+            settlement.shortfall = U128(settlement.payout.0 - paid);
+            settlement.payout = U128(paid);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * reconcile compares ft_balance_of the contract with its liabilities in token_id.
+     * Only the owner and the operators can call it, every pause it opens is kept in the history active_time reads
+     */
+    pub fn reconcile(&mut self, token_id: AccountId) -> Promise {
+        self.assert_owner_or_operator();
+        assert!(
+            self._token_state(&token_id).is_some(),
+            "Staking: {} is not allowed",
+            token_id
+        );
+        ext_ft::ext(token_id.clone())
+            .with_static_gas(FT_BALANCE_OF_GAS)
+            .ft_balance_of(env::current_account_id().to_string())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_BALANCE_RECONCILED_GAS)
+                    .on_balance_reconciled(token_id),
+            )
+    }
+
+    /**
+     * @notice
+     * on_balance_reconciled pauses the rewards of an underfunded token and resumes them once it is covered again.
+     * Returns None when the balance could not be read
+     */
+    #[private]
+    pub fn on_balance_reconciled(&mut self, token_id: AccountId) -> Option<TokenSolvency> {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).ok()
+            }
+            _ => None,
+        };
+        let balance = match balance {
+            Some(balance) => balance,
+            None => {
+                log_str(&format!("Staking: Cannot read the balance of {}", token_id));
+                return None;
+            }
+        };
+        let now = env::block_timestamp_ms();
+        let liabilities = self._liabilities(&token_id);
+        let underfunded = balance.0 < liabilities.total.0;
+        let mut solvency = self
+            .token_solvency
+            .get(&token_id)
+            .unwrap_or_else(|| TokenSolvency {
+                balance,
+                liabilities: liabilities.total,
+                checked_at: now,
+                paused_since: None,
+                pauses: Vec::new(),
+            });
+        match (solvency.paused_since, underfunded) {
+            (None, true) => solvency.paused_since = Some(now),
+            (Some(start), false) => {
+                solvency.pauses.push((start, now));
+                solvency.paused_since = None;
+            }
+            _ => {}
+        }
+        solvency.balance = balance;
+        solvency.liabilities = liabilities.total;
+        solvency.checked_at = now;
+        self.token_solvency.insert(&token_id, &solvency);
+        SolvencyChecked {
+            token_id,
+            balance,
+            principal: liabilities.principal,
+            accrued_rewards: liabilities.accrued_rewards,
+            reserved: liabilities.reserved,
+            liabilities: liabilities.total,
+            underfunded,
+        }
+        .emit();
+        Some(solvency)
+    }
+
+    // * readonly
+    pub fn get_liabilities(&self, token_id: AccountId) -> Liabilities {
+        self._liabilities(&token_id)
+    }
+
+    // * readonly
+    pub fn get_solvency(&self, token_id: AccountId) -> Option<TokenSolvency> {
+        self.token_solvency.get(&token_id)
+    }
+
+    /**
+     * @notice
+     * claim_shortfall pays the caller what pro rata payouts of token_id did not pay, once a reconcile found the token covered.
+     * The shortfall is restored in on_shortfall_paid if the transfer fails
+     */
+    pub fn claim_shortfall(&mut self, token_id: AccountId) -> Promise {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            !self._underfunded(&token_id),
+            "Staking: {} is still underfunded",
+            token_id
+        );
+        let amount = self
+            .shortfalls
+            .get(&account_id)
+            .and_then(|shortfalls| shortfalls.get(&token_id).copied())
+            .filter(|amount| amount.0 > 0)
+            .unwrap_or_else(|| env::panic_str("Staking: No shortfall to claim"));
+        self._remove_shortfall(&account_id, &token_id, amount.0);
+        self._register_on_token(&token_id, &account_id)
+            .then(
+                ext_ft::ext(token_id.clone())
+                    .with_static_gas(FT_TRANSFER_GAS)
+                    .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                    .ft_transfer(
+                        account_id.to_string(),
+                        amount.0.to_string(),
+                        Some("shortfall".to_string()),
+                    ),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_SHORTFALL_PAID_GAS)
+                    .on_shortfall_paid(account_id, token_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn on_shortfall_paid(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                ShortfallPaid {
+                    account_id,
+                    token_id,
+                    amount,
+                }
+                .emit();
+                amount
+            }
+            _ => {
+                log_str("Staking: Shortfall transfer failed, it is owed again");
+                self._add_shortfall(&account_id, &token_id, amount.0);
+                U128(0)
+            }
+        }
+    }
+
+    // * readonly
+    pub fn get_shortfalls(&self, account_id: AccountId) -> HashMap<AccountId, U128> {
+        self.shortfalls.get(&account_id).unwrap_or_default()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_liabilities_follow_stakes() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        stake(&mut contract, accounts(2), AMOUNT, ONE_HOUR);
+        call_at(accounts(2), 2 * ONE_HOUR);
+        let liabilities = contract.get_liabilities(token());
+        assert_eq!(liabilities.principal.0, 2 * AMOUNT);
+        assert_eq!(liabilities.accrued_rewards.0, 3 * HOURLY_REWARD);
+        contract.withdraw_stake(U128(AMOUNT / 2), 0);
+        let claimable: Balance = contract
+            .has_stake(accounts(2))
+            .stakes
+            .iter()
+            .map(|stake| stake.claimable.0)
+            .sum();
+        let liabilities = contract.get_liabilities(token());
+        assert_eq!(liabilities.principal.0, AMOUNT + AMOUNT / 2);
+        assert_eq!(liabilities.accrued_rewards.0, claimable);
+        assert_eq!(
+            liabilities.total.0,
+            liabilities.principal.0 + liabilities.accrued_rewards.0 + liabilities.reserved.0
+        );
+    }
+
+    #[test]
+    fn test_reconcile_pauses_rewards_and_cuts_payouts() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        let balance =
+            |amount: Balance| PromiseResult::Successful(format!("\"{}\"", amount).into_bytes());
+        // the balance only covers the principal
+        call_at(accounts(1), ONE_HOUR);
+        contract.reconcile(token());
+        callback_at(ONE_HOUR, balance(AMOUNT));
+        let solvency = contract.on_balance_reconciled(token()).unwrap();
+        assert_eq!(solvency.paused_since, Some(ONE_HOUR));
+        assert_eq!(solvency.liabilities.0, AMOUNT + HOURLY_REWARD);
+
+        call_at(accounts(2), 3 * ONE_HOUR);
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            HOURLY_REWARD
+        );
+        assert_eq!(
+            contract.get_liabilities(token()).accrued_rewards.0,
+            HOURLY_REWARD
+        );
+        let preview =
+            contract.preview_stake(accounts(2), token(), U128(AMOUNT), "staking".to_string());
+        assert_eq!(
+            preview.error.unwrap(),
+            format!("Staking: {} is underfunded", token())
+        );
+        let preview = contract.preview_withdraw(accounts(2), 0, U128(AMOUNT));
+        assert_eq!(preview.payouts[0].shortfall.0, HOURLY_REWARD);
+
+        // topped up, rewards accrue again from the reconcile
+        callback_at(3 * ONE_HOUR, balance(AMOUNT + HOURLY_REWARD));
+        let solvency = contract.on_balance_reconciled(token()).unwrap();
+        assert_eq!(solvency.paused_since, None);
+        assert_eq!(solvency.pauses, vec![(ONE_HOUR, 3 * ONE_HOUR)]);
+        call_at(accounts(2), 4 * ONE_HOUR);
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            2 * HOURLY_REWARD
+        );
+        assert_eq!(
+            contract.get_liabilities(token()).accrued_rewards.0,
+            2 * HOURLY_REWARD
+        );
+        let preview = contract.preview_withdraw(accounts(2), 0, U128(AMOUNT));
+        assert_eq!(preview.payouts[0].shortfall.0, 0);
+        // an unreadable balance changes nothing
+        callback_at(4 * ONE_HOUR, PromiseResult::Failed);
+        assert!(contract.on_balance_reconciled(token()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Only the owner or an operator can call this method")]
+    fn test_reconcile_is_for_operators() {
+        let mut contract = setup();
+        call_at(accounts(3), 0);
+        contract.reconcile(token());
+    }
+
+    // reconcile of the token by the owner, with the contract holding balance
+    fn reconcile_at(contract: &mut Stakeable, timestamp: u64, balance: Balance) {
+        call_at(accounts(1), timestamp);
+        contract.reconcile(token());
+        callback_at(
+            timestamp,
+            PromiseResult::Successful(format!("\"{}\"", balance).into_bytes()),
+        );
+        contract.on_balance_reconciled(token()).unwrap();
+    }
+
+    fn shortfall_of(contract: &Stakeable, account_id: AccountId) -> Balance {
+        contract
+            .get_shortfalls(account_id)
+            .get(&token())
+            .map_or(0, |amount| amount.0)
+    }
+
+    #[test]
+    fn test_pro_rata_shortfall_is_paid_later() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        // the balance only covers the principal, the reward is owed
+        reconcile_at(&mut contract, ONE_HOUR, AMOUNT);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        assert_eq!(ft_transfer_amounts(), vec![AMOUNT]);
+        assert_eq!(shortfall_of(&contract, accounts(2)), HOURLY_REWARD);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, HOURLY_REWARD);
+
+        // the owner sent the missing tokens
+        reconcile_at(&mut contract, 2 * ONE_HOUR, HOURLY_REWARD);
+        call_at(accounts(2), 2 * ONE_HOUR);
+        contract.claim_shortfall(token());
+        assert_eq!(ft_transfer_amounts(), vec![HOURLY_REWARD]);
+        assert_eq!(shortfall_of(&contract, accounts(2)), 0);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, 0);
+
+        // a failed transfer owes it again
+        callback_at(2 * ONE_HOUR, PromiseResult::Failed);
+        let paid = contract.on_shortfall_paid(accounts(2), token(), U128(HOURLY_REWARD));
+        assert_eq!(paid.0, 0);
+        assert_eq!(shortfall_of(&contract, accounts(2)), HOURLY_REWARD);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, HOURLY_REWARD);
+    }
+
+    #[test]
+    fn test_rolled_back_payout_takes_the_shortfall_back() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        reconcile_at(&mut contract, ONE_HOUR, AMOUNT);
+        call_at(accounts(2), ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        assert_eq!(shortfall_of(&contract, accounts(2)), HOURLY_REWARD);
+
+        callback_at(ONE_HOUR, PromiseResult::Failed);
+        let cut = Settlement {
+            payout: U128(AMOUNT),
+            shortfall: U128(HOURLY_REWARD),
+            ..settlement(AMOUNT, HOURLY_REWARD)
+        };
+        contract.on_withdraw_resolved(accounts(2), cut);
+        assert_eq!(shortfall_of(&contract, accounts(2)), 0);
+        assert_eq!(contract.get_liabilities(token()).reserved.0, 0);
+        assert_eq!(amounts(&contract, accounts(2)), vec![AMOUNT]);
+    }
+
+    #[test]
+    fn test_underfunded_token_cuts_every_payout() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_fee_config(FeeConfig {
+            deposit_fee_bps: 100,
+            treasury_id: Some(accounts(4)),
+            ..FeeConfig::default()
+        });
+        stake(&mut contract, accounts(2), 100 * AMOUNT, 0);
+        let proof = airdrop_campaign(&mut contract, None);
+        // the balance covers half of the liabilities
+        call_at(accounts(1), 0);
+        let liabilities = contract.get_liabilities(token()).total.0;
+        contract.reconcile(token());
+        callback_at(
+            0,
+            PromiseResult::Successful(format!("\"{}\"", liabilities / 2).into_bytes()),
+        );
+        contract.on_balance_reconciled(token()).unwrap();
+        let half = |amount: Balance| pro_rata(amount, liabilities / 2, liabilities);
+
+        call_at(accounts(1), 0);
+        contract.withdraw_treasury(token(), None);
+        assert_eq!(ft_transfer_amounts(), vec![half(AMOUNT)]);
+        // a failed transfer puts back all the fees
+        callback_at(0, PromiseResult::Failed);
+        contract.on_treasury_withdraw(accounts(4), token(), U128(AMOUNT), U128(half(AMOUNT)));
+        assert_eq!(
+            contract.get_treasury_balances(),
+            vec![(token(), U128(AMOUNT))]
+        );
+        callback_at(0, PromiseResult::Successful(vec![]));
+        assert_eq!(
+            contract
+                .on_treasury_withdraw(accounts(4), token(), U128(AMOUNT), U128(half(AMOUNT)))
+                .0,
+            half(AMOUNT)
+        );
+
+        call_at(accounts(2), ONE_HOUR);
+        contract.claim_airdrop(0, 0, U128(AMOUNT), proof);
+        assert_eq!(ft_transfer_amounts(), vec![half(AMOUNT)]);
+
+        call_at(accounts(2), ONE_HOUR);
+        contract.vestings.insert(
+            &accounts(2),
+            &vec![VestingEntry {
+                token_id: token(),
+                total: U128(AMOUNT),
+                released: U128(0),
+                start: 0,
+                end: 0,
+            }],
+        );
+        contract.release_vested();
+        assert_eq!(ft_transfer_amounts(), vec![half(AMOUNT)]);
+
+        call_at(accounts(3), ONE_HOUR);
+        contract
+            .referral_rewards
+            .insert(&accounts(3), &HashMap::from([(token(), U128(AMOUNT))]));
+        contract.claim_referral_rewards(token());
+        assert_eq!(ft_transfer_amounts(), vec![half(AMOUNT)]);
+    }
+
+    #[test]
+    fn test_pro_rata() {
+        assert_eq!(pro_rata(10, 1, 2), 5);
+        assert_eq!(pro_rata(10, 1, 0), 0);
+        assert_eq!(pro_rata(1 << 127, 1 << 126, 1 << 127), 1 << 126);
+    }
+}
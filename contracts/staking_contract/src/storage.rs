@@ -0,0 +1,209 @@
+use crate::*;
+
+/**
+ * @notice
+ * StorageBalanceBounds of NEP-145. Accounts need no registration, storage is charged from the balance as it is used
+ */
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _charge_storage takes the cost of the storage used since initial_storage from the storage balance of the account.
+     * Same rule as refund_deposit in the notes: whoever makes the state grow pays for it
+     */
+    pub(crate) fn _charge_storage(&mut self, account_id: &AccountId, initial_storage: u64) {
+        let storage_used = env::storage_usage().saturating_sub(initial_storage);
+        if storage_used == 0 {
+            return;
+        }
+        let required_cost = env::storage_byte_cost() * Balance::from(storage_used);
+        let balance = self.storage_balances.get(account_id).unwrap_or(0);
+        // a callback must not fail halfway through a rollback, the contract pays what the account cannot
+        if balance < required_cost && env::predecessor_account_id() == env::current_account_id() {
+            log_str(
+                format!(
+                    "Staking: {} is short of storage balance, the contract covers it",
+                    account_id
+                )
+                .as_str(),
+            );
+            self.storage_balances.insert(account_id, &0);
+            return;
+        }
+        assert!(
+            balance >= required_cost,
+            "Staking: {} needs {} yoctoNEAR of storage balance, call storage_deposit",
+            account_id,
+            required_cost
+        );
+        self.storage_balances
+            .insert(account_id, &(balance - required_cost));
+    }
+
+    /**
+     * @notice
+     * _deposit_storage adds the attached deposit of the call to the storage balance of the caller
+     */
+    pub(crate) fn _deposit_storage(&mut self, account_id: &AccountId) {
+        let attached_deposit = env::attached_deposit();
+        if attached_deposit > 0 {
+            let balance = self.storage_balances.get(account_id).unwrap_or(0);
+            self.storage_balances
+                .insert(account_id, &(balance + attached_deposit));
+        }
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * storage_deposit prepays the storage an account uses, for example the balance checkpoints written when it stakes.
+     * NEP-145: with registration_only the deposit is refunded above the minimum, all of it when the account is registered
+     */
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let attached_deposit = env::attached_deposit();
+        let registered = self.storage_balances.contains_key(&account_id);
+        if registration_only.unwrap_or(false) {
+            let min = self.storage_balance_bounds().min.0;
+            let refund = if registered {
+                attached_deposit
+            } else {
+                assert!(
+                    attached_deposit >= min,
+                    "Staking: The attached deposit is less than the minimum storage balance"
+                );
+                self.storage_balances.insert(&account_id, &min);
+                attached_deposit - min
+            };
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        } else {
+            let balance = self.storage_balances.get(&account_id).unwrap_or(0);
+            self.storage_balances
+                .insert(&account_id, &(balance + attached_deposit));
+        }
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    /**
+     * @notice
+     * storage_withdraw returns the unused storage balance of the caller, all of it when amount is not given.
+     * The storage already charged is spent, it is not part of the balance
+     */
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self
+            .storage_balances
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: Account is not registered"));
+        let amount = amount.map(|amount| amount.0).unwrap_or(balance);
+        assert!(amount <= balance, "Staking: Not enough storage balance");
+        self.storage_balances
+            .insert(&account_id, &(balance - amount));
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    /**
+     * @notice
+     * storage_unregister refunds the storage balance of the caller and forgets the account, false when it was not registered.
+     * An account with stakes cannot unregister, even with force, their storage stays in use until they are withdrawn
+     */
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        // force would burn the stakes, they are kept and the call fails instead
+        let _ = force;
+        let account_id = env::predecessor_account_id();
+        let balance = match self.storage_balances.get(&account_id) {
+            Some(balance) => balance,
+            None => return false,
+        };
+        assert!(
+            self._get_stakeholder(&account_id)
+                .is_none_or(|stakeholder| stakeholder.address_stakes.is_empty()),
+            "Staking: Withdraw the stakes before unregistering"
+        );
+        self.storage_balances.remove(&account_id);
+        if balance > 0 {
+            Promise::new(account_id).transfer(balance);
+        }
+        true
+    }
+
+    // * readonly
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(0),
+            max: None,
+        }
+    }
+
+    // * readonly
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_balances
+            .get(&account_id)
+            .map(|balance| StorageBalance {
+                total: U128(balance),
+                available: U128(balance),
+            })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_storage_management() {
+        let mut contract = setup();
+        assert_eq!(contract.storage_balance_bounds().min.0, 0);
+        assert!(contract.storage_balance_of(accounts(3)).is_none());
+        testing_env!(get_context(accounts(3)).attached_deposit(ONE_NEAR).build());
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, 0);
+        // registration_only refunds everything once registered
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.available.0, 0);
+        let balance = contract.storage_deposit(Some(accounts(3)), None);
+        assert_eq!(balance.available.0, ONE_NEAR);
+
+        testing_env!(get_context(accounts(3)).attached_deposit(1).build());
+        let balance = contract.storage_withdraw(Some(U128(ONE_NEAR / 4)));
+        assert_eq!(balance.available.0, 3 * ONE_NEAR / 4);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(3)).is_none());
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Withdraw the stakes before unregistering")]
+    fn test_storage_unregister_with_stakes() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        testing_env!(get_context(accounts(2)).attached_deposit(1).build());
+        contract.storage_unregister(Some(true));
+    }
+}
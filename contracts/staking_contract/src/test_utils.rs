@@ -0,0 +1,211 @@
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+use crate::*;
+
+pub(crate) const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+pub(crate) const AMOUNT: Balance = 1_000_000;
+// reward of AMOUNT staked for one hour at the default 15/1000 per hour
+pub(crate) const HOURLY_REWARD: Balance = 15_000;
+
+pub(crate) fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(accounts(0))
+        .signer_account_id(predecessor_account_id.clone())
+        .predecessor_account_id(predecessor_account_id);
+    builder
+}
+
+// block timestamps are in ns, the contract works in ms
+pub(crate) fn at_ms(timestamp: u64) -> u64 {
+    timestamp * 1_000_000
+}
+
+pub(crate) fn token() -> AccountId {
+    "token.testnet".parse().unwrap()
+}
+
+pub(crate) fn nft() -> AccountId {
+    "nft.testnet".parse().unwrap()
+}
+
+pub(crate) fn setup() -> Stakeable {
+    testing_env!(get_context(accounts(1)).build());
+    let mut contract = Stakeable::new(accounts(1));
+    allow_token(&mut contract, token(), 24, 0);
+    deposit_storage(&mut contract, accounts(2));
+    contract
+}
+
+// allow_token and its callback with the metadata of a token with decimals
+pub(crate) fn allow_token(
+    contract: &mut Stakeable,
+    token_id: AccountId,
+    decimals: u8,
+    timestamp: u64,
+) {
+    call_at(accounts(1), timestamp);
+    contract.allow_token(token_id.clone());
+    let metadata = near_sdk::serde_json::json!({
+        "spec": "ft-1.0.0",
+        "name": "Nolan Token",
+        "symbol": "NTK",
+        "icon": null,
+        "reference": null,
+        "reference_hash": null,
+        "decimals": decimals,
+    });
+    callback_at(
+        timestamp,
+        PromiseResult::Successful(metadata.to_string().into_bytes()),
+    );
+    assert!(contract.on_token_metadata(token_id));
+}
+
+pub(crate) fn deposit_storage(contract: &mut Stakeable, account_id: AccountId) {
+    testing_env!(get_context(account_id).attached_deposit(ONE_NEAR).build());
+    contract.storage_deposit(None, None);
+}
+
+pub(crate) fn stake(
+    contract: &mut Stakeable,
+    sender_id: AccountId,
+    amount: Balance,
+    timestamp: u64,
+) {
+    testing_env!(get_context(token())
+        .block_timestamp(at_ms(timestamp))
+        .build());
+    let refund = contract.ft_on_transfer(sender_id, U128(amount), "staking".to_string());
+    assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+}
+
+pub(crate) fn call_at(account_id: AccountId, timestamp: u64) {
+    testing_env!(get_context(account_id)
+        .block_timestamp(at_ms(timestamp))
+        .build());
+}
+
+pub(crate) fn callback_at(timestamp: u64, result: PromiseResult) {
+    testing_env!(
+        get_context(accounts(0))
+            .block_timestamp(at_ms(timestamp))
+            .build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        vec![result]
+    );
+}
+
+pub(crate) fn amounts(contract: &Stakeable, account_id: AccountId) -> Vec<Balance> {
+    contract
+        .has_stake(account_id)
+        .stakes
+        .iter()
+        .map(|stake| stake.amount.0)
+        .collect()
+}
+
+pub(crate) fn hex(hash: &Hash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn withdrawal_error(contract: &Stakeable, index: usize, amount: Balance) -> String {
+    let stakeholder = contract._get_stakeholder(&accounts(2)).unwrap();
+    match contract._compute_withdrawal(&stakeholder, index, amount) {
+        Ok(_) => panic!("expected the withdrawal to be rejected"),
+        Err(reason) => reason,
+    }
+}
+
+pub(crate) fn settlement(principal: Balance, reward: Balance) -> Settlement {
+    Settlement {
+        token_id: token(),
+        principal: U128(principal),
+        principal_fee: U128(0),
+        reward: U128(reward),
+        reward_fee: U128(0),
+        referral_reward: U128(0),
+        payout: U128(principal + reward),
+        locked_until: 0,
+        shortfall: U128(0),
+    }
+}
+
+pub(crate) fn airdrop_campaign(
+    contract: &mut Stakeable,
+    lock_duration: Option<u64>,
+) -> Vec<String> {
+    call_at(accounts(1), 0);
+    let leaf = airdrop_leaf(0, &accounts(2), AMOUNT);
+    let sibling = airdrop_leaf(1, &accounts(3), 2 * AMOUNT);
+    let root: Hash = env::sha256(&[leaf.min(sibling), leaf.max(sibling)].concat())
+        .try_into()
+        .unwrap();
+    let campaign_id = contract.create_airdrop(
+        token(),
+        hex(&root),
+        U128(3 * AMOUNT),
+        100 * ONE_HOUR,
+        lock_duration,
+    );
+    testing_env!(get_context(token()).build());
+    let msg = format!(
+        "{{\"action\": \"fund_airdrop\", \"campaign_id\": {}}}",
+        campaign_id
+    );
+    let refund = contract.ft_on_transfer(accounts(1), U128(4 * AMOUNT), msg);
+    // only what the campaign needs is kept
+    assert!(matches!(refund, PromiseOrValue::Value(U128(AMOUNT))));
+    vec![hex(&sibling)]
+}
+
+// amounts of the ft_transfer calls the last call created
+pub(crate) fn ft_transfer_amounts() -> Vec<Balance> {
+    near_sdk::test_utils::get_created_receipts()
+        .into_iter()
+        .flat_map(|receipt| receipt.actions)
+        .filter_map(|action| match action {
+            near_sdk::mock::VmAction::FunctionCall {
+                function_name,
+                args,
+                ..
+            } if function_name == "ft_transfer" => {
+                let args: near_sdk::serde_json::Value =
+                    near_sdk::serde_json::from_slice(&args).unwrap();
+                Some(args["amount"].as_str().unwrap().parse().unwrap())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// ft_on_transfer from the token with any message, returns the refund
+pub(crate) fn transfer(
+    contract: &mut Stakeable,
+    sender_id: AccountId,
+    amount: Balance,
+    msg: &str,
+) -> Balance {
+    testing_env!(get_context(token()).build());
+    match contract.ft_on_transfer(sender_id, U128(amount), msg.to_string()) {
+        PromiseOrValue::Value(refund) => refund.0,
+        _ => panic!("expected a value"),
+    }
+}
+
+pub(crate) fn at_block(account_id: AccountId, block_index: u64) {
+    testing_env!(get_context(account_id).block_index(block_index).build());
+}
+
+pub(crate) fn stake_at_block(
+    contract: &mut Stakeable,
+    sender_id: AccountId,
+    amount: Balance,
+    block_index: u64,
+) {
+    testing_env!(get_context(token()).block_index(block_index).build());
+    contract.ft_on_transfer(sender_id, U128(amount), "staking".to_string());
+}
@@ -0,0 +1,406 @@
+use crate::*;
+
+pub const FT_METADATA_GAS: Gas = Gas(5_000_000_000_000);
+pub const ON_TOKEN_METADATA_GAS: Gas = Gas(10_000_000_000_000);
+// gas clients attach to allow_token: the registration of the contract on the token, ft_metadata and on_token_metadata
+pub const ALLOW_TOKEN_GAS: Gas = Gas(100_000_000_000_000);
+// 10^38 is the largest power of ten that fits a u128
+const MAX_TOKEN_DECIMALS: u8 = 38;
+
+/**
+ * @notice
+ * Active: the token can be staked and withdrawn
+ * DepositsClosed: stakes can only be withdrawn or claimed, deposits are refunded
+ * Retired: like DepositsClosed and rewards stop accruing from the retirement, it cannot be allowed again
+ */
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TokenStatus {
+    Active,
+    DepositsClosed,
+    Retired,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenState {
+    pub status: TokenStatus,
+    // when the status was set, for a retired token the end of its rewards
+    pub since: u64,
+}
+
+/**
+ * @notice
+ * TokenMetadata is the part of the NEP-148 metadata the contract keeps, the other fields are ignored
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadata {
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub decimals: u8,
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _token_decimals falls back to Config.decimals for tokens allowed before their metadata was read
+     */
+    pub(crate) fn _token_decimals(&self, token_id: &AccountId) -> u32 {
+        self.token_metadata
+            .get(token_id)
+            .map(|metadata| metadata.decimals as u32)
+            .unwrap_or(self.config.decimals)
+    }
+
+    /**
+     * @notice
+     * _normalized_amount expresses an amount of token_id in the unit of Config.decimals,
+     * so amounts of tokens with different decimals can be added up and compared with the stake limits
+     */
+    pub(crate) fn _normalized_amount(&self, token_id: &AccountId, amount: Balance) -> Balance {
+        convert_decimals(amount, self._token_decimals(token_id), self.config.decimals)
+    }
+
+    pub(crate) fn _normalized_total(&self, stakeholder: &StakeHolder) -> Balance {
+        stakeholder
+            .address_stakes
+            .iter()
+            .map(|stake| self._normalized_amount(&stake.token_id, stake.amount.0))
+            .sum()
+    }
+
+    /**
+     * @notice
+     * _token_state is None for a token that was never allowed,
+     * tokens allowed before the lifecycle have no state stored and are active
+     */
+    pub(crate) fn _token_state(&self, token_id: &AccountId) -> Option<TokenState> {
+        if !self.allowed_token.contains(token_id) {
+            return None;
+        }
+        Some(self.token_states.get(token_id).unwrap_or(TokenState {
+            status: TokenStatus::Active,
+            since: 0,
+        }))
+    }
+
+    pub(crate) fn _check_token_deposits(&self, token_id: &AccountId) -> Result<(), String> {
+        match self._token_state(token_id).map(|state| state.status) {
+            Some(TokenStatus::Active) if self._underfunded(token_id) => {
+                Err(format!("Staking: {} is underfunded", token_id))
+            }
+            Some(TokenStatus::Active) => Ok(()),
+            Some(TokenStatus::DepositsClosed) => {
+                Err(format!("Staking: Deposits of {} are closed", token_id))
+            }
+            Some(TokenStatus::Retired) => Err(format!("Staking: {} is retired", token_id)),
+            None => Err(format!("Staking: {} is not allowed", token_id)),
+        }
+    }
+
+    /**
+     * @notice
+     * _reward_end is the time rewards of a token are counted up to, the retirement for a retired token
+     */
+    pub(crate) fn _reward_end(&self, token_id: &AccountId, now: u64) -> u64 {
+        let retired_since = self
+            .token_states
+            .get(token_id)
+            .filter(|state| state.status == TokenStatus::Retired)
+            .map(|state| state.since);
+        reward_end(now, retired_since)
+    }
+
+    fn _set_token_status(&mut self, token_id: &AccountId, status: TokenStatus) {
+        self.allowed_token.insert(token_id);
+        self.token_states.insert(
+            token_id,
+            &TokenState {
+                status,
+                since: env::block_timestamp_ms(),
+            },
+        );
+        TokenStatusChanged {
+            token_id: token_id.clone(),
+            status,
+        }
+        .emit();
+    }
+
+    /**
+     * @notice
+     * _allow_token registers the contract on the token and reads its metadata, on_token_metadata makes it active.
+     * Shared by allow_token and AllowToken proposals
+     */
+    pub(crate) fn _allow_token(&mut self, token_id: AccountId) -> Promise {
+        self._assert_not_retired(&token_id);
+        self._register_on_token(&token_id, &env::current_account_id())
+            .then(
+                ext_ft::ext(token_id.clone())
+                    .with_static_gas(FT_METADATA_GAS)
+                    .ft_metadata(),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_TOKEN_METADATA_GAS)
+                    .on_token_metadata(token_id),
+            )
+    }
+
+    /**
+     * @notice
+     * _disallow_token closes deposits of an allowed token, shared by disallow_token and RemoveToken proposals
+     */
+    pub(crate) fn _disallow_token(&mut self, token_id: &AccountId) {
+        assert!(
+            self.allowed_token.contains(token_id),
+            "Staking: Token is not allowed"
+        );
+        self._assert_not_retired(token_id);
+        self._set_token_status(token_id, TokenStatus::DepositsClosed);
+    }
+
+    fn _assert_not_retired(&self, token_id: &AccountId) {
+        let status = self._token_state(token_id).map(|state| state.status);
+        assert!(
+            status != Some(TokenStatus::Retired),
+            "Staking: Token is retired"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * allow_token reads the metadata of a token and opens its deposits in on_token_metadata, also after disallow_token.
+     * The contract registers itself on the token first, the attached deposit goes to the storage reserve that pays it
+     */
+    #[payable]
+    pub fn allow_token(&mut self, token_id: AccountId) -> Promise {
+        self.assert_owner();
+        self.storage_reserve += env::attached_deposit();
+        self._allow_token(token_id)
+    }
+
+    /**
+     * @notice
+     * on_token_metadata records the metadata and makes the token active, returns whether it was allowed.
+     * The decimals of a token are kept once recorded, the amounts staked so far are counted with them
+     */
+    #[private]
+    pub fn on_token_metadata(&mut self, token_id: AccountId) -> bool {
+        let metadata = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<TokenMetadata>(&value).ok()
+            }
+            _ => None,
+        };
+        let mut metadata = match metadata {
+            Some(metadata) if metadata.decimals <= MAX_TOKEN_DECIMALS => metadata,
+            _ => {
+                log_str(&format!(
+                    "Staking: Cannot read the metadata of {}, it is not allowed",
+                    token_id
+                ));
+                return false;
+            }
+        };
+        if let Some(recorded) = self.token_metadata.get(&token_id) {
+            if recorded.decimals != metadata.decimals {
+                log_str(&format!(
+                    "Staking: {} now reports {} decimals, keeping {}",
+                    token_id, metadata.decimals, recorded.decimals
+                ));
+                metadata.decimals = recorded.decimals;
+            }
+        }
+        self.token_metadata.insert(&token_id, &metadata);
+        self._set_token_status(&token_id, TokenStatus::Active);
+        true
+    }
+
+    /**
+     * @notice
+     * disallow_token closes deposits of a token, its stakes can still be withdrawn and keep earning
+     */
+    pub fn disallow_token(&mut self, token_id: AccountId) {
+        self.assert_owner();
+        self._disallow_token(&token_id);
+    }
+
+    /**
+     * @notice
+     * retire_token closes deposits of a token for good and stops its rewards now,
+     * what accrued until then can still be withdrawn with the principal
+     */
+    pub fn retire_token(&mut self, token_id: AccountId) {
+        self.assert_owner();
+        assert!(
+            self.allowed_token.contains(&token_id),
+            "Staking: Token is not allowed"
+        );
+        self._assert_not_retired(&token_id);
+        self._set_token_status(&token_id, TokenStatus::Retired);
+    }
+
+    // * readonly
+    pub fn get_token_metadata(&self, token_id: AccountId) -> Option<TokenMetadata> {
+        self.token_metadata.get(&token_id)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * get_token_decimals is what the amounts of a token are counted in, Config.decimals when its metadata is unknown
+     */
+    pub fn get_token_decimals(&self, token_id: AccountId) -> u32 {
+        self._token_decimals(&token_id)
+    }
+
+    // * readonly
+    pub fn get_token_status(&self, token_id: AccountId) -> Option<TokenState> {
+        self._token_state(&token_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_unknown_token_is_refunded() {
+        let mut contract = setup();
+        testing_env!(get_context(accounts(4)).build());
+        let refund = contract.ft_on_transfer(accounts(2), U128(AMOUNT), "staking".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(AMOUNT))));
+        assert!(contract.has_stake(accounts(2)).stakes.is_empty());
+        assert!(contract.get_token_status(accounts(4)).is_none());
+    }
+
+    #[test]
+    fn test_token_lifecycle() {
+        let mut contract = setup();
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(1), ONE_HOUR);
+        contract.disallow_token(token());
+        assert_eq!(
+            contract.get_token_status(token()).unwrap().status,
+            TokenStatus::DepositsClosed
+        );
+        testing_env!(get_context(token()).build());
+        let refund = contract.ft_on_transfer(accounts(2), U128(AMOUNT), "staking".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(AMOUNT))));
+        // stakes keep earning and can be withdrawn
+        call_at(accounts(2), 2 * ONE_HOUR);
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            2 * HOURLY_REWARD
+        );
+        contract.withdraw_stake(U128(AMOUNT / 2), 0);
+        allow_token(&mut contract, token(), 24, 2 * ONE_HOUR);
+        stake(&mut contract, accounts(2), AMOUNT, 2 * ONE_HOUR);
+
+        call_at(accounts(1), 3 * ONE_HOUR);
+        contract.retire_token(token());
+        let state = contract.get_token_status(token()).unwrap();
+        assert_eq!(state.status, TokenStatus::Retired);
+        assert_eq!(state.since, 3 * ONE_HOUR);
+        // rewards stop at the retirement
+        call_at(accounts(2), 10 * ONE_HOUR);
+        let stakes = contract.has_stake(accounts(2)).stakes;
+        assert_eq!(stakes[0].claimable.0, HOURLY_REWARD / 2);
+        assert_eq!(stakes[1].claimable.0, HOURLY_REWARD);
+        let preview =
+            contract.preview_stake(accounts(2), token(), U128(AMOUNT), "staking".to_string());
+        assert_eq!(
+            preview.error.unwrap(),
+            format!("Staking: {} is retired", token())
+        );
+        contract.withdraw_stake(U128(AMOUNT), 1);
+        assert_eq!(amounts(&contract, accounts(2)), vec![AMOUNT / 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Token is retired")]
+    fn test_retired_token_cannot_be_allowed() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.retire_token(token());
+        contract.allow_token(token());
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Only the owner can call this method")]
+    fn test_allow_token_owner_only() {
+        let mut contract = setup();
+        call_at(accounts(2), 0);
+        contract.allow_token(accounts(4));
+    }
+
+    #[test]
+    fn test_token_metadata_and_decimals() {
+        let mut contract = setup();
+        let ntk = accounts(4);
+        allow_token(&mut contract, ntk.clone(), 12, 0);
+        let metadata = contract.get_token_metadata(ntk.clone()).unwrap();
+        assert_eq!(metadata.symbol, "NTK");
+        assert_eq!(contract.get_token_decimals(ntk.clone()), 12);
+        assert_eq!(contract.get_token_decimals(token()), 24);
+        assert_eq!(contract.get_token_decimals(accounts(5)), 24);
+
+        // limits are in Config.decimals (24), 1 NTK is 10^12 with 12 decimals
+        let one_ntk: Balance = 1_000_000_000_000;
+        call_at(accounts(1), 0);
+        contract.set_stake_limits(U128(ONE_NEAR), Some(U128(3 * ONE_NEAR / 2)), None);
+        let preview = contract.preview_stake(
+            accounts(2),
+            ntk.clone(),
+            U128(one_ntk / 2),
+            "staking".to_string(),
+        );
+        assert_eq!(
+            preview.error.unwrap(),
+            format!("Staking: Cannot stake less than {}", ONE_NEAR)
+        );
+        testing_env!(get_context(ntk.clone()).build());
+        let refund = contract.ft_on_transfer(accounts(2), U128(2 * one_ntk), "staking".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(refund)) if refund == one_ntk / 2));
+        assert_eq!(amounts(&contract, accounts(2)), vec![3 * one_ntk / 2]);
+        assert_eq!(contract.get_stake_limits().total_staked.0, 3 * ONE_NEAR / 2);
+        assert_eq!(
+            contract
+                .get_account_remaining_capacity(accounts(2))
+                .unwrap()
+                .0,
+            0
+        );
+        // rewards are paid in the staked token and do not depend on decimals
+        call_at(accounts(2), ONE_HOUR);
+        assert_eq!(
+            contract.has_stake(accounts(2)).stakes[0].claimable.0,
+            3 * one_ntk / 2 * 15 / 1000
+        );
+    }
+
+    #[test]
+    fn test_token_without_metadata_is_not_allowed() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.allow_token(accounts(4));
+        callback_at(0, PromiseResult::Failed);
+        assert!(!contract.on_token_metadata(accounts(4)));
+        assert!(contract.get_token_status(accounts(4)).is_none());
+        callback_at(0, PromiseResult::Successful(b"{}".to_vec()));
+        assert!(!contract.on_token_metadata(accounts(4)));
+        assert!(contract.get_token_metadata(accounts(4)).is_none());
+    }
+}
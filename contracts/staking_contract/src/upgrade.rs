@@ -0,0 +1,96 @@
+use crate::*;
+
+pub const MIGRATE_GAS: Gas = Gas(50_000_000_000_000);
+pub const ON_UPGRADE_GAS: Gas = Gas(10_000_000_000_000);
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * upgrade deploys the wasm passed as the raw input of the call and runs migrate in the same batch.
+     * Actions of a batch are applied together, so if migrate panics the old code and state stay in place
+     */
+    pub fn upgrade(&self) -> Promise {
+        self.assert_owner();
+        let code = env::input().expect("Staking: Missing contract code");
+        let code_hash = near_sdk::bs58::encode(env::sha256(&code)).into_string();
+        log_str(format!("upgrading to code_hash={}", code_hash).as_str());
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), vec![], NO_DEPOSIT, MIGRATE_GAS)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_UPGRADE_GAS)
+                    .on_upgrade(code_hash),
+            )
+    }
+
+    /**
+     * @notice
+     * on_upgrade records the outcome of upgrade, it runs on whichever code is active after the batch
+     * so every version of the contract has to keep it
+     */
+    #[private]
+    pub fn on_upgrade(&mut self, code_hash: String) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                ContractUpgraded { code_hash }.emit();
+                true
+            }
+            _ => {
+                ContractUpgradeFailed { code_hash }.emit();
+                false
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_upgrade_deploys_and_migrates() {
+        let contract = setup();
+        let mut context = get_context(accounts(1)).build();
+        context.input = b"new code".to_vec();
+        testing_env!(context);
+        contract.upgrade();
+        let actions: Vec<near_sdk::mock::VmAction> = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .filter(|receipt| receipt.receiver_id == accounts(0))
+            .flat_map(|receipt| receipt.actions)
+            .collect();
+        assert!(matches!(
+            &actions[0],
+            near_sdk::mock::VmAction::DeployContract { code } if code == b"new code"
+        ));
+        assert!(matches!(
+            &actions[1],
+            near_sdk::mock::VmAction::FunctionCall { function_name, .. } if function_name == "migrate"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking: Only the owner can call this method")]
+    fn test_upgrade_owner_only() {
+        let contract = setup();
+        let mut context = get_context(accounts(2)).build();
+        context.input = b"new code".to_vec();
+        testing_env!(context);
+        contract.upgrade();
+    }
+
+    #[test]
+    fn test_on_upgrade_reports_the_outcome() {
+        let mut contract = setup();
+        callback_at(0, PromiseResult::Successful(vec![]));
+        assert!(contract.on_upgrade("hash".to_string()));
+        callback_at(0, PromiseResult::Failed);
+        assert!(!contract.on_upgrade("hash".to_string()));
+    }
+}
@@ -0,0 +1,508 @@
+use crate::*;
+
+pub const WEEK: u64 = 7 * 24 * 3_600_000;
+pub const ON_VE_WITHDRAW_GAS: Gas = Gas(10_000_000_000_000);
+
+/**
+ * @notice
+ * A ve lock, the voting power is amount * (end - now) / max lock duration so it decays linearly to 0 at end.
+ * end is always rounded down to a whole week so the power that expires can be tracked per week
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VeLock {
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub end: u64,
+    // account that receives the voting power of this lock, None means the owner of the lock
+    pub delegate_id: Option<AccountId>,
+}
+
+/**
+ * @notice
+ * Aggregated voting power of several locks, the power at ts is bias + remainder / max lock duration.
+ * slope is the sum of the locked amounts, the power decays by slope / max lock duration per ms.
+ * Keeping the remainder makes the decay exact, so the power of every lock reaches 0 at its end.
+ * Slopes stop at the end of each lock, these changes are stored per week in ve_slope_changes
+ */
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default)]
+pub struct VePoint {
+    pub bias: u128,
+    pub slope: u128,
+    pub ts: u64,
+    pub remainder: u128,
+}
+
+/**
+ * @notice
+ * ve_power is amount * duration / max_lock_duration as a quotient and a remainder.
+ * duration is at most max_lock_duration so it cannot overflow whatever the amount
+ */
+pub fn ve_power(amount: u128, duration: u64, max_lock_duration: u64) -> (u128, u128) {
+    let (duration, max) = (duration as u128, max_lock_duration as u128);
+    let low = (amount % max) * duration;
+    (amount / max * duration + low / max, low % max)
+}
+
+impl VePoint {
+    fn add(&mut self, (quotient, remainder): (u128, u128), max_lock_duration: u64) {
+        self.bias += quotient;
+        self.remainder += remainder;
+        if self.remainder >= max_lock_duration as u128 {
+            self.remainder -= max_lock_duration as u128;
+            self.bias += 1;
+        }
+    }
+
+    fn sub(&mut self, (quotient, remainder): (u128, u128), max_lock_duration: u64) {
+        let borrow = u128::from(self.remainder < remainder);
+        if self.bias < quotient + borrow {
+            self.bias = 0;
+            self.remainder = 0;
+            return;
+        }
+        self.bias -= quotient + borrow;
+        self.remainder = self.remainder + borrow * max_lock_duration as u128 - remainder;
+    }
+
+    fn decay(&mut self, duration: u64, max_lock_duration: u64) {
+        self.sub(
+            ve_power(self.slope, duration, max_lock_duration),
+            max_lock_duration,
+        );
+    }
+}
+
+// None is the key of the total voting power
+pub type VeHolder = Option<AccountId>;
+
+impl VeLock {
+    // voting power of the lock at now, exact for any amount
+    pub fn power(&self, now: u64, max_lock_duration: u64) -> (u128, u128) {
+        ve_power(
+            self.amount.0,
+            self.end.saturating_sub(now),
+            max_lock_duration,
+        )
+    }
+
+    pub fn holder(&self, owner_id: &AccountId) -> AccountId {
+        self.delegate_id.clone().unwrap_or_else(|| owner_id.clone())
+    }
+}
+
+impl Stakeable {
+    /**
+     * @notice
+     * _ve_point_at brings the point of a holder forward to `now`, applying the slope changes of every week in between
+     */
+    pub(crate) fn _ve_point_at(&self, holder: &VeHolder, now: u64) -> VePoint {
+        let mut point = match holder {
+            Some(account_id) => self.ve_points.get(account_id).unwrap_or_default(),
+            None => self.ve_total.clone(),
+        };
+        if point.ts >= now {
+            return point;
+        }
+        let max_lock_duration = self.config.ve_max_lock_duration;
+        let mut week = (point.ts / WEEK + 1) * WEEK;
+        while week <= now && point.slope > 0 {
+            point.decay(week - point.ts, max_lock_duration);
+            point.slope = point.slope.saturating_sub(
+                self.ve_slope_changes
+                    .get(&(holder.clone(), week))
+                    .unwrap_or(0),
+            );
+            point.ts = week;
+            week += WEEK;
+        }
+        if point.slope > 0 {
+            point.decay(now - point.ts, max_lock_duration);
+        }
+        point.ts = now;
+        point
+    }
+
+    fn _ve_save_point(&mut self, holder: &VeHolder, point: VePoint) {
+        match holder {
+            Some(account_id) => {
+                self.ve_points.insert(account_id, &point);
+            }
+            None => self.ve_total = point,
+        }
+    }
+
+    /**
+     * @notice
+     * _ve_checkpoint replaces the contribution of an old lock by the one of a new lock for a holder
+     */
+    fn _ve_checkpoint(
+        &mut self,
+        holder: &VeHolder,
+        old_lock: Option<&VeLock>,
+        new_lock: Option<&VeLock>,
+    ) {
+        let now = env::block_timestamp_ms();
+        let max_lock_duration = self.config.ve_max_lock_duration;
+        let mut point = self._ve_point_at(holder, now);
+        if let Some(lock) = old_lock.filter(|lock| lock.end > now) {
+            let slope = lock.amount.0;
+            point.sub(lock.power(now, max_lock_duration), max_lock_duration);
+            point.slope = point.slope.saturating_sub(slope);
+            let key = (holder.clone(), lock.end);
+            let change = self.ve_slope_changes.get(&key).unwrap_or(0);
+            self.ve_slope_changes
+                .insert(&key, &change.saturating_sub(slope));
+        }
+        if let Some(lock) = new_lock.filter(|lock| lock.end > now) {
+            let slope = lock.amount.0;
+            point.add(lock.power(now, max_lock_duration), max_lock_duration);
+            point.slope += slope;
+            let key = (holder.clone(), lock.end);
+            let change = self.ve_slope_changes.get(&key).unwrap_or(0);
+            self.ve_slope_changes.insert(&key, &(change + slope));
+        }
+        self._ve_save_point(holder, point);
+    }
+
+    /**
+     * @notice
+     * _ve_update_lock saves a lock and moves its voting power in the total and in the points of the holders
+     */
+    pub(crate) fn _ve_update_lock(
+        &mut self,
+        account_id: &AccountId,
+        old_lock: Option<VeLock>,
+        new_lock: Option<VeLock>,
+    ) {
+        self._ve_checkpoint(&None, old_lock.as_ref(), new_lock.as_ref());
+        let old_holder = old_lock.as_ref().map(|lock| lock.holder(account_id));
+        let new_holder = new_lock.as_ref().map(|lock| lock.holder(account_id));
+        if old_holder == new_holder {
+            self._ve_checkpoint(&new_holder, old_lock.as_ref(), new_lock.as_ref());
+        } else {
+            if old_holder.is_some() {
+                self._ve_checkpoint(&old_holder, old_lock.as_ref(), None);
+            }
+            if new_holder.is_some() {
+                self._ve_checkpoint(&new_holder, None, new_lock.as_ref());
+            }
+        }
+        if let Some(lock) = old_lock.as_ref() {
+            self._unreserve(&lock.token_id, lock.amount.0);
+        }
+        match new_lock {
+            Some(lock) => {
+                self._reserve(&lock.token_id, lock.amount.0);
+                self.ve_locks.insert(account_id, &lock)
+            }
+            None => self.ve_locks.remove(account_id),
+        };
+    }
+
+    fn _ve_lock_end(&self, lock_duration: u64) -> u64 {
+        let now = env::block_timestamp_ms();
+        let end = (now + lock_duration) / WEEK * WEEK;
+        assert!(end > now, "Staking: Lock duration is too short");
+        assert!(
+            end <= now + self.config.ve_max_lock_duration,
+            "Staking: Lock duration cannot be more than {} ms",
+            self.config.ve_max_lock_duration
+        );
+        end
+    }
+
+    /**
+     * @notice
+     * _ve_lock is called from ft_on_transfer, it creates a lock or adds to the existing one.
+     * lock_duration is required for a new lock and extends an existing one when it is given
+     */
+    pub(crate) fn _ve_lock(
+        &mut self,
+        account_id: &AccountId,
+        token_id: AccountId,
+        amount: Balance,
+        lock_duration: Option<u64>,
+    ) -> Result<(), String> {
+        if self.config.ve_token_id.as_ref() != Some(&token_id) {
+            return Err("Staking: Token cannot be locked".to_string());
+        }
+        let now = env::block_timestamp_ms();
+        let old_lock = self.ve_locks.get(account_id);
+        let new_lock = match &old_lock {
+            Some(lock) if lock.end <= now => {
+                return Err("Staking: Withdraw the expired lock first".to_string())
+            }
+            Some(lock) => {
+                let end = match lock_duration {
+                    Some(duration) => self._ve_lock_end(duration).max(lock.end),
+                    None => lock.end,
+                };
+                VeLock {
+                    amount: U128(lock.amount.0 + amount),
+                    end,
+                    ..lock.clone()
+                }
+            }
+            None => {
+                let duration = lock_duration
+                    .ok_or_else(|| "Staking: A new lock needs a lock_duration".to_string())?;
+                VeLock {
+                    token_id,
+                    amount: U128(amount),
+                    end: self._ve_lock_end(duration),
+                    delegate_id: None,
+                }
+            }
+        };
+        VeLocked {
+            account_id: account_id.clone(),
+            amount: new_lock.amount,
+            end: new_lock.end,
+        }
+        .emit();
+        self._ve_update_lock(account_id, old_lock, Some(new_lock));
+        Ok(())
+    }
+
+    pub(crate) fn _set_ve_config(
+        &mut self,
+        ve_token_id: Option<AccountId>,
+        max_lock_duration: u64,
+    ) {
+        assert!(
+            max_lock_duration >= WEEK,
+            "Staking: Maximum lock duration must be at least a week"
+        );
+        // the decay of the existing locks is counted with the maximum, it cannot change until they all expire
+        assert!(
+            max_lock_duration == self.config.ve_max_lock_duration
+                || self._ve_point_at(&None, env::block_timestamp_ms()).slope == 0,
+            "Staking: Maximum lock duration cannot change while locks have voting power"
+        );
+        self.config.ve_token_id = ve_token_id;
+        self.config.ve_max_lock_duration = max_lock_duration;
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    /**
+     * @notice
+     * set_ve_config sets the token that can be locked and the maximum lock duration in ms.
+     * The maximum cannot change while locks have voting power since their decay is counted with it
+     */
+    pub fn set_ve_config(&mut self, ve_token_id: Option<AccountId>, max_lock_duration: u64) {
+        self.assert_owner_before_staking();
+        self._set_ve_config(ve_token_id, max_lock_duration);
+    }
+
+    /**
+     * @notice
+     * extend_ve_lock moves the end of the caller's lock to now + lock_duration
+     */
+    pub fn extend_ve_lock(&mut self, lock_duration: u64) {
+        let account_id = env::predecessor_account_id();
+        let old_lock = self
+            .ve_locks
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: No lock"));
+        let end = self._ve_lock_end(lock_duration);
+        assert!(end > old_lock.end, "Staking: Can only extend a lock");
+        let new_lock = VeLock {
+            end,
+            ..old_lock.clone()
+        };
+        VeLocked {
+            account_id: account_id.clone(),
+            amount: new_lock.amount,
+            end,
+        }
+        .emit();
+        self._ve_update_lock(&account_id, Some(old_lock), Some(new_lock));
+    }
+
+    /**
+     * @notice
+     * delegate_voting_power gives the voting power of the caller's lock to another account, None takes it back
+     */
+    pub fn delegate_voting_power(&mut self, delegate_id: Option<AccountId>) {
+        let account_id = env::predecessor_account_id();
+        let old_lock = self
+            .ve_locks
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: No lock"));
+        let delegate_id = delegate_id.filter(|delegate_id| delegate_id != &account_id);
+        let new_lock = VeLock {
+            delegate_id: delegate_id.clone(),
+            ..old_lock.clone()
+        };
+        self._ve_update_lock(&account_id, Some(old_lock), Some(new_lock));
+        VeDelegated {
+            account_id,
+            delegate_id,
+        }
+        .emit();
+    }
+
+    /**
+     * @notice
+     * withdraw_ve_lock transfers an expired lock back to the caller,
+     * the lock is restored in on_ve_withdraw if the transfer fails
+     */
+    pub fn withdraw_ve_lock(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let lock = self
+            .ve_locks
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("Staking: No lock"));
+        assert!(
+            lock.end <= env::block_timestamp_ms(),
+            "Staking: The lock has not expired"
+        );
+        self.ve_locks.remove(&account_id);
+        self._unreserve(&lock.token_id, lock.amount.0);
+        self._register_on_token(&lock.token_id, &account_id)
+            .then(
+                ext_ft::ext(lock.token_id.clone())
+                    .with_static_gas(FT_TRANSFER_GAS)
+                    .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                    .ft_transfer(
+                        account_id.to_string(),
+                        lock.amount.0.to_string(),
+                        Some("ve lock withdrawn".to_string()),
+                    ),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_VE_WITHDRAW_GAS)
+                    .on_ve_withdraw(account_id, lock),
+            )
+    }
+
+    #[private]
+    pub fn on_ve_withdraw(&mut self, account_id: AccountId, lock: VeLock) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                VeWithdrawn {
+                    account_id,
+                    amount: lock.amount,
+                }
+                .emit();
+                lock.amount
+            }
+            _ => {
+                log_str("Staking: Transfer failed, the lock is restored");
+                // the lock has expired so it does not carry voting power anymore
+                self._reserve(&lock.token_id, lock.amount.0);
+                self.ve_locks.insert(&account_id, &lock);
+                U128(0)
+            }
+        }
+    }
+
+    // * readonly
+    pub fn get_ve_lock(&self, account_id: AccountId) -> Option<VeLock> {
+        self.ve_locks.get(&account_id)
+    }
+
+    /**
+     * @notice
+     * readonly
+     * Voting power of an account, from its own lock and from the locks delegated to it
+     */
+    pub fn get_voting_power(&self, account_id: AccountId) -> U128 {
+        U128(
+            self._ve_point_at(&Some(account_id), env::block_timestamp_ms())
+                .bias,
+        )
+    }
+
+    // * readonly
+    pub fn get_total_voting_power(&self) -> U128 {
+        U128(self._ve_point_at(&None, env::block_timestamp_ms()).bias)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_ve_lock_decays() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_ve_config(Some(token()), 4 * WEEK);
+        let lock = format!(r#"{{"action": "ve_lock", "lock_duration": {}}}"#, 4 * WEEK);
+        let amount = 4 * WEEK as u128 * 1_000;
+        assert_eq!(transfer(&mut contract, accounts(2), amount, &lock), 0);
+        assert_eq!(contract.get_ve_lock(accounts(2)).unwrap().end, 4 * WEEK);
+
+        call_at(accounts(2), 0);
+        assert_eq!(contract.get_voting_power(accounts(2)).0, amount);
+        call_at(accounts(2), WEEK);
+        assert_eq!(contract.get_voting_power(accounts(2)).0, amount * 3 / 4);
+        assert_eq!(contract.get_total_voting_power().0, amount * 3 / 4);
+
+        // delegated power moves with the remaining decay
+        contract.delegate_voting_power(Some(accounts(3)));
+        assert_eq!(contract.get_voting_power(accounts(2)).0, 0);
+        assert_eq!(contract.get_voting_power(accounts(3)).0, amount * 3 / 4);
+        call_at(accounts(2), 3 * WEEK);
+        assert_eq!(contract.get_voting_power(accounts(3)).0, amount / 4);
+        call_at(accounts(2), 4 * WEEK);
+        assert_eq!(contract.get_voting_power(accounts(3)).0, 0);
+        assert_eq!(contract.get_total_voting_power().0, 0);
+    }
+
+    #[test]
+    fn test_ve_power_is_exact() {
+        let mut contract = setup();
+        deposit_storage(&mut contract, accounts(3));
+        call_at(accounts(1), 0);
+        contract.set_ve_config(Some(token()), 4 * WEEK);
+        // a lock smaller than the maximum duration in the smallest unit still votes
+        let lock = format!(r#"{{"action": "ve_lock", "lock_duration": {}}}"#, 2 * WEEK);
+        assert_eq!(transfer(&mut contract, accounts(2), 1_000, &lock), 0);
+        // 1e9 tokens of 24 decimals does not overflow
+        let large = 1_000_000_000 * ONE_NEAR + 7;
+        let lock = format!(r#"{{"action": "ve_lock", "lock_duration": {}}}"#, 4 * WEEK);
+        assert_eq!(transfer(&mut contract, accounts(3), large, &lock), 0);
+
+        call_at(accounts(2), 0);
+        assert_eq!(contract.get_voting_power(accounts(2)).0, 500);
+        assert_eq!(contract.get_voting_power(accounts(3)).0, large);
+        call_at(accounts(2), WEEK + 1);
+        let small = ve_power(1_000, WEEK - 1, 4 * WEEK).0;
+        let big = ve_power(large, 3 * WEEK - 1, 4 * WEEK).0;
+        assert_eq!(contract.get_voting_power(accounts(2)).0, small);
+        assert_eq!(contract.get_voting_power(accounts(3)).0, big);
+        let total = contract.get_total_voting_power().0;
+        assert!(total >= small + big && total <= small + big + 1);
+        // every lock is worth nothing at its end
+        call_at(accounts(2), 2 * WEEK);
+        assert_eq!(contract.get_voting_power(accounts(2)).0, 0);
+        call_at(accounts(2), 4 * WEEK);
+        assert_eq!(contract.get_voting_power(accounts(3)).0, 0);
+        assert_eq!(contract.get_total_voting_power().0, 0);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Staking: Maximum lock duration cannot change while locks have voting power"
+    )]
+    fn test_ve_max_duration_is_kept_while_locked() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_ve_config(Some(token()), 4 * WEEK);
+        let lock = format!(r#"{{"action": "ve_lock", "lock_duration": {}}}"#, WEEK);
+        transfer(&mut contract, accounts(2), AMOUNT, &lock);
+        call_at(accounts(1), 0);
+        // the token can change, the maximum cannot
+        contract.set_ve_config(None, 4 * WEEK);
+        contract.set_ve_config(None, 8 * WEEK);
+    }
+}
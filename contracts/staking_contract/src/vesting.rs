@@ -0,0 +1,417 @@
+use crate::*;
+
+pub const ON_VESTING_RELEASED_GAS: Gas = Gas(10_000_000_000_000);
+
+/**
+ * @notice
+ * VestingEntry is one claimed reward, unlocking linearly from start to end
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingEntry {
+    pub token_id: AccountId,
+    pub total: U128,
+    pub released: U128,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl VestingEntry {
+    // part of total unlocked at timestamp, released or not
+    pub fn vested_at(&self, timestamp: u64) -> Balance {
+        if timestamp >= self.end {
+            return self.total.0;
+        }
+        if timestamp <= self.start {
+            return 0;
+        }
+        self.total.0 * (timestamp - self.start) as u128 / (self.end - self.start) as u128
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingView {
+    pub token_id: AccountId,
+    pub total: U128,
+    pub locked: U128,
+    // unlocked and not released yet
+    pub unlocked: U128,
+    pub released: U128,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Stakeable {
+    pub(crate) fn _set_vesting_config(&mut self, vesting: VestingConfig) {
+        vesting.assert_valid();
+        self.config.vesting = vesting;
+    }
+
+    /**
+     * @notice
+     * _reward_vesting splits the net reward of a settlement into the instant claim penalty and the part that vests,
+     * (0, 0) when it is paid right away. Accounts exempt from vesting are always paid right away
+     */
+    pub(crate) fn _reward_vesting(
+        &self,
+        account_id: &AccountId,
+        reward: Balance,
+        instant: bool,
+    ) -> (Balance, Balance) {
+        let vesting = &self.config.vesting;
+        if self.vesting_exempt.contains(account_id) {
+            return (0, 0);
+        }
+        match (vesting.duration, instant) {
+            (0, _) => (0, 0),
+            (_, true) => (fee_of(reward, vesting.instant_claim_penalty_bps), 0),
+            (_, false) => (0, reward),
+        }
+    }
+
+    /**
+     * @notice
+     * _vest_rewards moves the reward of every settlement into a vesting entry, the transfer then only pays the principal.
+     * With instant the reward is paid right away minus the instant claim penalty, which stays in the pool.
+     * Nothing changes while vesting is disabled or for accounts exempt from it
+     */
+    pub(crate) fn _vest_rewards(
+        &mut self,
+        account_id: &AccountId,
+        settlements: &mut [Settlement],
+        instant: bool,
+    ) {
+        let vesting = self.config.vesting.clone();
+        if vesting.duration == 0 || self.vesting_exempt.contains(account_id) {
+            return;
+        }
+        let initial_storage = env::storage_usage();
+        let now = env::block_timestamp_ms();
+        let mut entries = self.vestings.get(account_id).unwrap_or_default();
+        for settlement in settlements.iter_mut() {
+            let reward = settlement.reward.0 - settlement.reward_fee.0;
+            if reward == 0 {
+                continue;
+            }
+            let (penalty, vested) = self._reward_vesting(account_id, reward, instant);
+            if penalty > 0 {
+                settlement.payout.0 -= penalty;
+                RewardForfeited {
+                    account_id: account_id.clone(),
+                    token_id: settlement.token_id.clone(),
+                    amount: U128(penalty),
+                }
+                .emit();
+            }
+            if vested == 0 {
+                continue;
+            }
+            let entry = VestingEntry {
+                token_id: settlement.token_id.clone(),
+                total: U128(vested),
+                released: U128(0),
+                start: now,
+                end: now + vesting.duration,
+            };
+            RewardVested {
+                account_id: account_id.clone(),
+                token_id: entry.token_id.clone(),
+                amount: entry.total,
+                end: entry.end,
+            }
+            .emit();
+            self._reserve(&entry.token_id, vested);
+            entries.push(entry);
+            // the reward is settled for good, a failed transfer only rolls back the principal
+            settlement.payout.0 -= vested;
+            settlement.reward = U128(0);
+            settlement.reward_fee = U128(0);
+            settlement.referral_reward = U128(0);
+        }
+        if !entries.is_empty() {
+            self.vestings.insert(account_id, &entries);
+        }
+        self._charge_storage(account_id, initial_storage);
+    }
+
+    /**
+     * @notice
+     * _add_unlocked_vesting gives back a release whose transfer failed, as an entry that is already unlocked
+     */
+    fn _add_unlocked_vesting(
+        &mut self,
+        account_id: &AccountId,
+        token_id: AccountId,
+        amount: Balance,
+    ) {
+        let now = env::block_timestamp_ms();
+        self._reserve(&token_id, amount);
+        let mut entries = self.vestings.get(account_id).unwrap_or_default();
+        entries.push(VestingEntry {
+            token_id,
+            total: U128(amount),
+            released: U128(0),
+            start: now,
+            end: now,
+        });
+        self.vestings.insert(account_id, &entries);
+    }
+}
+
+#[near_bindgen]
+impl Stakeable {
+    pub fn set_vesting_config(&mut self, vesting: VestingConfig) {
+        self.assert_owner_before_staking();
+        self._set_vesting_config(vesting);
+    }
+
+    // * readonly
+    pub fn get_vesting_config(&self) -> VestingConfig {
+        self.config.vesting.clone()
+    }
+
+    /**
+     * @notice
+     * Exempts accounts from vesting, their rewards are always paid when claimed.
+     * Meant for contracts staking here, like the vault, which cannot release vesting entries.
+     * Returns how many were not exempt yet
+     */
+    pub fn add_vesting_exempt(&mut self, account_ids: Vec<AccountId>) -> u64 {
+        self.assert_owner();
+        account_ids
+            .iter()
+            .filter(|account_id| self.vesting_exempt.insert(account_id))
+            .count() as u64
+    }
+
+    /**
+     * @notice
+     * Ends the vesting exemption of accounts, returns how many were exempt
+     */
+    pub fn remove_vesting_exempt(&mut self, account_ids: Vec<AccountId>) -> u64 {
+        self.assert_owner();
+        account_ids
+            .iter()
+            .filter(|account_id| self.vesting_exempt.remove(account_id))
+            .count() as u64
+    }
+
+    // * readonly
+    pub fn is_vesting_exempt(&self, account_id: AccountId) -> bool {
+        self.vesting_exempt.contains(&account_id)
+    }
+
+    // * readonly
+    pub fn get_vesting_exempt(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<AccountId> {
+        self.vesting_exempt
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+            .collect()
+    }
+
+    /**
+     * @notice
+     * release_vested transfers everything unlocked so far to the caller, one transfer per token,
+     * cut pro rata when the token is underfunded. Entries are dropped once they are fully released
+     */
+    pub fn release_vested(&mut self) -> PromiseOrValue<U128> {
+        let account_id = env::predecessor_account_id();
+        let now = env::block_timestamp_ms();
+        let mut entries = self.vestings.get(&account_id).unwrap_or_default();
+        let mut releases: Vec<(AccountId, Balance)> = Vec::new();
+        for entry in entries.iter_mut() {
+            let amount = entry.vested_at(now) - entry.released.0;
+            if amount == 0 {
+                continue;
+            }
+            entry.released.0 += amount;
+            match releases
+                .iter_mut()
+                .find(|(token_id, _)| token_id == &entry.token_id)
+            {
+                Some((_, total)) => *total += amount,
+                None => releases.push((entry.token_id.clone(), amount)),
+            }
+        }
+        entries.retain(|entry| entry.released.0 < entry.total.0);
+        if entries.is_empty() {
+            self.vestings.remove(&account_id);
+        } else {
+            self.vestings.insert(&account_id, &entries);
+        }
+        let mut payment: Option<Promise> = None;
+        for (token_id, amount) in releases {
+            self._unreserve(&token_id, amount);
+            let paid = self._pro_rata_payout(&account_id, &token_id, amount);
+            let register = self._register_on_token(&token_id, &account_id);
+            let transfer = ext_ft::ext(token_id.clone())
+                .with_static_gas(FT_TRANSFER_GAS)
+                .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                .ft_transfer(
+                    account_id.to_string(),
+                    paid.to_string(),
+                    Some("vesting".to_string()),
+                );
+            let resolve = Self::ext(env::current_account_id())
+                .with_static_gas(ON_VESTING_RELEASED_GAS)
+                .on_vesting_released(account_id.clone(), token_id, U128(amount), U128(paid));
+            payment = Some(match payment {
+                Some(previous) => previous.then(register).then(transfer).then(resolve),
+                None => register.then(transfer).then(resolve),
+            });
+        }
+        match payment {
+            Some(promise) => PromiseOrValue::Promise(promise),
+            None => PromiseOrValue::Value(U128(0)),
+        }
+    }
+
+    #[private]
+    pub fn on_vesting_released(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        paid: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                VestingReleased {
+                    account_id,
+                    token_id,
+                    amount: paid,
+                }
+                .emit();
+                paid
+            }
+            _ => {
+                log_str("Staking: Vesting release failed, the amount is unlocked again");
+                self._remove_shortfall(&account_id, &token_id, amount.0 - paid.0);
+                self._add_unlocked_vesting(&account_id, token_id, amount.0);
+                U128(0)
+            }
+        }
+    }
+
+    /**
+     * @notice
+     * readonly
+     * get_vestings shows the locked, unlocked and released amount of every vesting entry of the account
+     */
+    pub fn get_vestings(&self, account_id: AccountId) -> Vec<VestingView> {
+        let now = env::block_timestamp_ms();
+        self.vestings
+            .get(&account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| {
+                let vested = entry.vested_at(now);
+                VestingView {
+                    locked: U128(entry.total.0 - vested),
+                    unlocked: U128(vested - entry.released.0),
+                    token_id: entry.token_id,
+                    total: entry.total,
+                    released: entry.released,
+                    start: entry.start,
+                    end: entry.end,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_vested_claim_and_release() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_vesting_config(VestingConfig {
+            duration: 10 * ONE_HOUR,
+            instant_claim_penalty_bps: 2_000,
+        });
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        // the reward vests, there is nothing to transfer yet
+        assert!(matches!(
+            contract.claim_all(None),
+            PromiseOrValue::Value(U128(0))
+        ));
+        call_at(accounts(2), 6 * ONE_HOUR);
+        let vestings = contract.get_vestings(accounts(2));
+        assert_eq!(vestings.len(), 1);
+        assert_eq!(vestings[0].total.0, HOURLY_REWARD);
+        assert_eq!(vestings[0].unlocked.0, HOURLY_REWARD / 2);
+        assert_eq!(vestings[0].locked.0, HOURLY_REWARD / 2);
+        assert!(matches!(
+            contract.release_vested(),
+            PromiseOrValue::Promise(_)
+        ));
+        let vestings = contract.get_vestings(accounts(2));
+        assert_eq!(vestings[0].released.0, HOURLY_REWARD / 2);
+        assert_eq!(vestings[0].unlocked.0, 0);
+        call_at(accounts(2), 11 * ONE_HOUR);
+        contract.release_vested();
+        assert!(contract.get_vestings(accounts(2)).is_empty());
+    }
+
+    #[test]
+    fn test_vault_harvest_skips_vesting() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_vesting_config(VestingConfig {
+            duration: 10 * ONE_HOUR,
+            instant_claim_penalty_bps: 2_000,
+        });
+        assert_eq!(contract.add_vesting_exempt(vec![accounts(4)]), 1);
+        deposit_storage(&mut contract, accounts(4));
+        stake(&mut contract, accounts(4), AMOUNT, 0);
+        // the vault harvests with withdraw_stake(0, 0)
+        call_at(accounts(4), ONE_HOUR);
+        assert!(matches!(
+            contract.withdraw_stake(U128(0), 0),
+            PromiseOrValue::Promise(_)
+        ));
+        assert_eq!(ft_transfer_amounts(), vec![HOURLY_REWARD]);
+        assert!(contract.get_vestings(accounts(4)).is_empty());
+        assert_eq!(amounts(&contract, accounts(4)), vec![AMOUNT]);
+
+        // without the exemption the same harvest would strand the reward in a vesting entry
+        call_at(accounts(1), ONE_HOUR);
+        contract.remove_vesting_exempt(vec![accounts(4)]);
+        call_at(accounts(4), 2 * ONE_HOUR);
+        assert!(matches!(
+            contract.withdraw_stake(U128(0), 0),
+            PromiseOrValue::Value(U128(0))
+        ));
+        assert_eq!(contract.get_vestings(accounts(4)).len(), 1);
+    }
+
+    #[test]
+    fn test_instant_claim_skips_vesting() {
+        let mut contract = setup();
+        call_at(accounts(1), 0);
+        contract.set_vesting_config(VestingConfig {
+            duration: 10 * ONE_HOUR,
+            instant_claim_penalty_bps: 2_000,
+        });
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        call_at(accounts(2), ONE_HOUR);
+        assert!(matches!(
+            contract.claim_all(Some(true)),
+            PromiseOrValue::Promise(_)
+        ));
+        assert!(contract.get_vestings(accounts(2)).is_empty());
+    }
+}
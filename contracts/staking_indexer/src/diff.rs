@@ -0,0 +1,114 @@
+use crate::*;
+
+/**
+ * @notice
+ * Mismatch is a difference between the indexed stakeholder and the output of has_stake for the same account
+ */
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Mismatch {
+    pub account_id: AccountId,
+    pub stake_index: Option<u64>,
+    pub field: String,
+    pub indexed: String,
+    pub view: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.stake_index {
+            Some(index) => write!(f, "{} stake {}", self.account_id, index)?,
+            None => write!(f, "{}", self.account_id)?,
+        }
+        write!(
+            f,
+            ": {} is {} in the logs and {} in has_stake",
+            self.field, self.indexed, self.view
+        )
+    }
+}
+
+pub fn parse_views(json: &str) -> Result<BTreeMap<AccountId, StakingSummary>, String> {
+    serde_json::from_str(json).map_err(|err| {
+        format!(
+            "Invalid has_stake output, pass an object of account id to has_stake: {}",
+            err
+        )
+    })
+}
+
+fn mismatch<T: ToString>(
+    account_id: &AccountId,
+    stake_index: Option<usize>,
+    field: &str,
+    indexed: T,
+    view: T,
+) -> Mismatch {
+    Mismatch {
+        account_id: account_id.clone(),
+        stake_index: stake_index.map(|index| index as u64),
+        field: field.to_string(),
+        indexed: indexed.to_string(),
+        view: view.to_string(),
+    }
+}
+
+/**
+ * @notice
+ * diff compares every account of views with the indexed stakeholders, accounts missing from views are not checked
+ */
+pub fn diff(indexer: &Indexer, views: &BTreeMap<AccountId, StakingSummary>) -> Vec<Mismatch> {
+    let empty = IndexedStakeholder::default();
+    let mut mismatches = vec![];
+    for (account_id, view) in views {
+        let indexed = indexer.stakeholders.get(account_id).unwrap_or(&empty);
+        if indexed.total_amount != view.total_amount {
+            mismatches.push(mismatch(
+                account_id,
+                None,
+                "total_amount",
+                indexed.total_amount.0,
+                view.total_amount.0,
+            ));
+        }
+        if indexed.stakes.len() != view.stakes.len() {
+            mismatches.push(mismatch(
+                account_id,
+                None,
+                "stakes",
+                indexed.stakes.len(),
+                view.stakes.len(),
+            ));
+        }
+        for (index, (indexed, view)) in indexed.stakes.iter().zip(&view.stakes).enumerate() {
+            if indexed.token_id != view.token_id {
+                mismatches.push(mismatch(
+                    account_id,
+                    Some(index),
+                    "token_id",
+                    &indexed.token_id,
+                    &view.token_id,
+                ));
+            }
+            if indexed.amount != view.amount {
+                mismatches.push(mismatch(
+                    account_id,
+                    Some(index),
+                    "amount",
+                    indexed.amount.0,
+                    view.amount.0,
+                ));
+            }
+            if indexed.locked_until != view.locked_until {
+                mismatches.push(mismatch(
+                    account_id,
+                    Some(index),
+                    "locked_until",
+                    indexed.locked_until,
+                    view.locked_until,
+                ));
+            }
+        }
+    }
+    mismatches
+}
@@ -0,0 +1,57 @@
+use crate::*;
+
+pub const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+/**
+ * @notice
+ * ReceiptLogs is one execution outcome of a dump, e.g. exported from an explorer or the receipts of near-cli.
+ * A dump is a json array of them, in the order they were executed
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReceiptLogs {
+    #[serde(default)]
+    pub receipt_id: Option<String>,
+    #[serde(default)]
+    pub block_height: Option<u64>,
+    // the contract that emitted the logs
+    pub executor_id: AccountId,
+    pub logs: Vec<String>,
+}
+
+impl ReceiptLogs {
+    pub fn location(&self) -> String {
+        match (&self.receipt_id, self.block_height) {
+            (Some(receipt_id), _) => format!("receipt {}", receipt_id),
+            (None, Some(block_height)) => format!("block {}", block_height),
+            (None, None) => format!("receipt of {}", self.executor_id),
+        }
+    }
+}
+
+/**
+ * @notice
+ * EventLog is the NEP-297 envelope shared by nolan_token and the staking contract
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EventLog {
+    pub standard: String,
+    pub version: String,
+    pub event: String,
+    #[serde(default)]
+    pub data: Vec<Value>,
+}
+
+pub fn parse_dump(json: &str) -> Result<Vec<ReceiptLogs>, String> {
+    serde_json::from_str(json).map_err(|err| format!("Invalid log dump: {}", err))
+}
+
+/**
+ * @notice
+ * parse_event reads an EVENT_JSON log, other logs like "stakeholder=..." are None
+ */
+pub fn parse_event(log: &str) -> Option<Result<EventLog, String>> {
+    let json = log.strip_prefix(EVENT_JSON_PREFIX)?;
+    Some(serde_json::from_str(json).map_err(|err| format!("Invalid event {}: {}", json, err)))
+}
@@ -0,0 +1,77 @@
+use crate::*;
+
+pub const FT_EVENT_STANDARD: &str = "nep141";
+pub const STAKING_EVENT_STANDARD: &str = "nolan_staking";
+
+// * nolan_token
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtMint {
+    pub owner_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtTransfer {
+    pub old_owner_id: AccountId,
+    pub new_owner_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtBurn {
+    pub owner_id: AccountId,
+    pub amount: U128,
+}
+
+// * staking contract, the events that change the stakes of has_stake
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeCreated {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub locked_until: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeIncreased {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeWithdrawn {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub remaining: U128,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeRestored {
+    pub account_id: AccountId,
+    pub stake_index: u64,
+    pub token_id: AccountId,
+    pub principal: U128,
+    // missing from the events of older versions, which merged the principal into a stake
+    #[serde(default)]
+    pub locked_until: u64,
+}
+
+pub fn parse_data<T: near_sdk::serde::de::DeserializeOwned>(
+    event: &str,
+    data: &Value,
+) -> Result<T, String> {
+    serde_json::from_value(data.clone()).map_err(|err| format!("Invalid {} data: {}", event, err))
+}
@@ -0,0 +1,29 @@
+use crate::*;
+
+pub const CSV_HEADER: &str = "account_id,stake_index,token_id,amount,locked_until";
+
+/**
+ * @notice
+ * to_csv writes one row per indexed stake, account ids cannot contain commas so nothing is quoted
+ */
+pub fn to_csv(indexer: &Indexer) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for (account_id, stakeholder) in &indexer.stakeholders {
+        for (index, stake) in stakeholder.stakes.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                account_id, index, stake.token_id, stake.amount.0, stake.locked_until
+            ));
+        }
+    }
+    csv
+}
+
+/**
+ * @notice
+ * to_json writes the stakeholders in the shape of has_stake, with the token balances and the warnings
+ */
+pub fn to_json(indexer: &Indexer) -> String {
+    serde_json::to_string_pretty(indexer).unwrap()
+}
@@ -0,0 +1,281 @@
+/**
+ * @notice
+ * Rebuilds the stakeholders of the staking contract from the EVENT_JSON logs of nolan_token and the staking contract,
+ * so the views can be checked against what actually happened. Everything is read from local json dumps
+ */
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, Value};
+use near_sdk::{AccountId, Balance};
+use staking_types::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+mod diff;
+mod dump;
+mod events;
+mod export;
+mod model;
+pub use crate::diff::*;
+pub use crate::dump::*;
+pub use crate::events::*;
+pub use crate::export::*;
+pub use crate::model::*;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseOrValue, PromiseResult, RuntimeFeesConfig, VMConfig};
+    use staking_contract::Stakeable;
+
+    use super::*;
+
+    const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+    const AMOUNT: Balance = 1_000_000;
+
+    fn token() -> AccountId {
+        "token.testnet".parse().unwrap()
+    }
+
+    fn call(predecessor_account_id: AccountId, attached_deposit: Balance, timestamp: u64) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(predecessor_account_id)
+            .attached_deposit(attached_deposit)
+            .block_timestamp(timestamp * 1_000_000)
+            .build());
+    }
+
+    fn receipt(executor_id: AccountId, logs: Vec<String>) -> ReceiptLogs {
+        ReceiptLogs {
+            receipt_id: None,
+            block_height: None,
+            executor_id,
+            logs,
+        }
+    }
+
+    fn event_log(standard: &str, event: &str, data: Value) -> String {
+        format!(
+            "{}{}",
+            EVENT_JSON_PREFIX,
+            json!({"standard": standard, "version": "1.0.0", "event": event, "data": [data]})
+        )
+    }
+
+    fn stake(contract: &mut Stakeable, sender_id: AccountId, amount: Balance, timestamp: u64) {
+        call(token(), 0, timestamp);
+        let refund = contract.ft_on_transfer(sender_id, U128(amount), "staking".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+    }
+
+    #[test]
+    fn test_replay_matches_contract() {
+        call(accounts(1), 0, 0);
+        let mut contract = Stakeable::new(accounts(1));
+        contract.allow_token(token());
+        let metadata =
+            json!({"spec": "ft-1.0.0", "name": "Nolan Token", "symbol": "NTK", "decimals": 24});
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(accounts(0))
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(metadata.to_string().into_bytes())]
+        );
+        assert!(contract.on_token_metadata(token()));
+        let mut receipts = vec![];
+        for account_id in [accounts(2), accounts(3)] {
+            call(account_id, ONE_NEAR, 0);
+            contract.storage_deposit(None, None);
+        }
+        stake(&mut contract, accounts(2), AMOUNT, 0);
+        receipts.push(receipt(accounts(0), get_logs()));
+        stake(&mut contract, accounts(2), 2 * AMOUNT, 0);
+        receipts.push(receipt(accounts(0), get_logs()));
+        stake(&mut contract, accounts(3), 3 * AMOUNT, 0);
+        receipts.push(receipt(accounts(0), get_logs()));
+        call(token(), 0, ONE_HOUR / 2);
+        let msg = json!({"action": "staking", "stake_index": 0}).to_string();
+        contract.ft_on_transfer(accounts(3), U128(AMOUNT), msg);
+        receipts.push(receipt(accounts(0), get_logs()));
+        call(accounts(2), 0, ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT), 0);
+        receipts.push(receipt(accounts(0), get_logs()));
+        call(accounts(2), 0, 2 * ONE_HOUR);
+        contract.withdraw_stake(U128(AMOUNT / 2), 0);
+        receipts.push(receipt(accounts(0), get_logs()));
+        call(accounts(3), 0, 2 * ONE_HOUR);
+        contract.claim_all(None);
+        receipts.push(receipt(accounts(0), get_logs()));
+
+        let dump = serde_json::to_string(&json!(receipts
+            .iter()
+            .map(|receipt| json!({"executor_id": receipt.executor_id, "logs": receipt.logs}))
+            .collect::<Vec<Value>>()))
+        .unwrap();
+        let mut indexer = Indexer::new(accounts(0));
+        indexer.apply_dump(&parse_dump(&dump).unwrap()).unwrap();
+        assert!(indexer.warnings.is_empty());
+
+        let views: BTreeMap<AccountId, StakingSummary> = [accounts(2), accounts(3), accounts(4)]
+            .into_iter()
+            .map(|account_id| (account_id.clone(), contract.has_stake(account_id)))
+            .collect();
+        assert_eq!(diff(&indexer, &views), vec![]);
+        let stakes = &indexer.stakeholders[&accounts(2)].stakes;
+        assert_eq!(stakes.len(), 1);
+        assert_eq!(stakes[0].amount.0, 3 * AMOUNT / 2);
+        assert_eq!(
+            indexer.stakeholders[&accounts(3)].total_amount.0,
+            4 * AMOUNT
+        );
+    }
+
+    #[test]
+    fn test_restored_stake() {
+        let staking = accounts(0);
+        let created = |index: u64| {
+            event_log(
+                STAKING_EVENT_STANDARD,
+                "stake_created",
+                json!({"account_id": "bob", "stake_index": index, "token_id": "token.testnet",
+                    "amount": "10", "since": 0, "locked_until": 0}),
+            )
+        };
+        let withdrawn = event_log(
+            STAKING_EVENT_STANDARD,
+            "stake_withdrawn",
+            json!({"account_id": "bob", "stake_index": 0, "token_id": "token.testnet",
+                "amount": "10", "reward": "1", "remaining": "0"}),
+        );
+        let restored = |index: u64| {
+            event_log(
+                STAKING_EVENT_STANDARD,
+                "stake_restored",
+                json!({"account_id": "bob", "stake_index": index, "token_id": "token.testnet",
+                    "principal": "10", "reward": "1", "locked_until": 5}),
+            )
+        };
+        let mut indexer = Indexer::new(accounts(0));
+        indexer
+            .apply_receipt(&receipt(staking, vec![created(0), withdrawn, restored(0)]))
+            .unwrap();
+        assert!(indexer.warnings.is_empty());
+        let bob: AccountId = "bob".parse().unwrap();
+        assert_eq!(indexer.stakeholders[&bob].total_amount.0, 10);
+        assert_eq!(indexer.stakeholders[&bob].stakes[0].amount.0, 10);
+        assert_eq!(indexer.stakeholders[&bob].stakes[0].locked_until, 5);
+        indexer
+            .apply_receipt(&receipt(accounts(0), vec![restored(3)]))
+            .unwrap();
+        assert_eq!(indexer.warnings.len(), 1);
+        assert_eq!(indexer.stakeholders[&bob].total_amount.0, 10);
+    }
+
+    #[test]
+    fn test_forged_staking_event_is_skipped() {
+        let created = event_log(
+            STAKING_EVENT_STANDARD,
+            "stake_created",
+            json!({"account_id": "bob", "stake_index": 0, "token_id": "token.testnet",
+                "amount": "10", "since": 0, "locked_until": 0}),
+        );
+        let mut indexer = Indexer::new(accounts(0));
+        indexer
+            .apply_receipt(&receipt(accounts(4), vec![created.clone()]))
+            .unwrap();
+        assert!(indexer.stakeholders.is_empty());
+        assert_eq!(indexer.events, 0);
+        assert_eq!(indexer.warnings.len(), 1);
+
+        indexer
+            .apply_receipt(&receipt(accounts(0), vec![created]))
+            .unwrap();
+        let bob: AccountId = "bob".parse().unwrap();
+        assert_eq!(indexer.stakeholders[&bob].total_amount.0, 10);
+        assert_eq!(indexer.events, 1);
+    }
+
+    #[test]
+    fn test_token_balances() {
+        let logs = vec![
+            "stakeholder=bob".to_string(),
+            event_log(
+                FT_EVENT_STANDARD,
+                "ft_mint",
+                json!({"owner_id": "alice", "amount": "100", "memo": "new"}),
+            ),
+            event_log(
+                FT_EVENT_STANDARD,
+                "ft_transfer",
+                json!({"old_owner_id": "alice", "new_owner_id": "bob", "amount": "30"}),
+            ),
+            event_log(
+                FT_EVENT_STANDARD,
+                "ft_burn",
+                json!({"owner_id": "bob", "amount": "10"}),
+            ),
+        ];
+        let mut indexer = Indexer::new(accounts(0));
+        indexer.apply_receipt(&receipt(token(), logs)).unwrap();
+        let balances = &indexer.token_balances[&token()];
+        assert_eq!(balances[&"alice".parse::<AccountId>().unwrap()].0, 70);
+        assert_eq!(balances[&"bob".parse::<AccountId>().unwrap()].0, 20);
+        assert_eq!(indexer.events, 3);
+        assert!(indexer.stakeholders.is_empty());
+
+        let invalid = format!("{}{{\"standard\": \"nep141\"", EVENT_JSON_PREFIX);
+        assert!(indexer
+            .apply_receipt(&receipt(token(), vec![invalid]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_diff_and_export() {
+        let mut indexer = Indexer::new(accounts(0));
+        indexer
+            .apply_receipt(&receipt(
+                accounts(0),
+                vec![event_log(
+                    STAKING_EVENT_STANDARD,
+                    "stake_created",
+                    json!({"account_id": accounts(2), "stake_index": 0, "token_id": token(),
+                        "amount": "10", "since": 0, "locked_until": 5}),
+                )],
+            ))
+            .unwrap();
+        assert_eq!(
+            to_csv(&indexer),
+            format!("{}\n{},0,{},10,5\n", CSV_HEADER, accounts(2), token())
+        );
+        let views = parse_views(
+            &json!({
+                accounts(2).as_str(): {"total_amount": "12", "stakes": [{"address": accounts(2),
+                    "token_id": token(), "amount": "12", "since": 0, "claimable": "0"}]},
+                accounts(3).as_str(): {"total_amount": "0", "stakes": []},
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let mismatches = diff(&indexer, &views);
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|mismatch| mismatch.field.as_str())
+                .collect::<Vec<_>>(),
+            vec!["total_amount", "amount", "locked_until"]
+        );
+        assert_eq!(
+            mismatches[1].to_string(),
+            format!(
+                "{} stake 0: amount is 10 in the logs and 12 in has_stake",
+                accounts(2)
+            )
+        );
+    }
+}
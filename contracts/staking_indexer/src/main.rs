@@ -0,0 +1,79 @@
+/**
+ * @notice
+ * Replays local log dumps of nolan_token and the staking contract and prints the rebuilt stakeholders.
+ * With --has-stake the result is checked against the views, the exit code is 2 when anything differs
+ */
+use clap::{Parser, ValueEnum};
+use near_sdk::AccountId;
+use staking_indexer::*;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Parser)]
+#[command(
+    name = "staking-indexer",
+    about = "Rebuild staking positions from EVENT_JSON logs"
+)]
+struct Cli {
+    /// Account id of the staking contract, staking events of other accounts are skipped
+    #[arg(long)]
+    staking_contract: AccountId,
+    /// Json array of {executor_id, logs}, repeat for more dumps in execution order
+    #[arg(long, required = true)]
+    logs: Vec<String>,
+    /// Json object of account id to the output of has_stake
+    #[arg(long)]
+    has_stake: Option<String>,
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Write the export to this file instead of stdout
+    #[arg(long)]
+    output: Option<String>,
+}
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path, err))
+}
+
+fn run(cli: Cli) -> Result<bool, String> {
+    let mut indexer = Indexer::new(cli.staking_contract.clone());
+    for path in &cli.logs {
+        indexer.apply_dump(&parse_dump(&read(path)?)?)?;
+    }
+    for warning in &indexer.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let export = match cli.format {
+        Format::Json => to_json(&indexer),
+        Format::Csv => to_csv(&indexer),
+    };
+    match &cli.output {
+        Some(path) => {
+            std::fs::write(path, export).map_err(|err| format!("Cannot write {}: {}", path, err))?
+        }
+        None => println!("{}", export.trim_end()),
+    }
+    let mismatches = match &cli.has_stake {
+        Some(path) => diff(&indexer, &parse_views(&read(path)?)?),
+        None => vec![],
+    };
+    for mismatch in &mismatches {
+        eprintln!("mismatch: {}", mismatch);
+    }
+    Ok(mismatches.is_empty())
+}
+
+fn main() {
+    match run(Cli::parse()) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(2),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::*;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IndexedStake {
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub locked_until: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IndexedStakeholder {
+    pub total_amount: U128,
+    pub stakes: Vec<IndexedStake>,
+}
+
+impl Default for IndexedStakeholder {
+    fn default() -> Self {
+        Self {
+            total_amount: U128(0),
+            stakes: vec![],
+        }
+    }
+}
+
+/**
+ * @notice
+ * Indexer replays the events of a dump into the stakeholders of the staking contract and the balances of the tokens.
+ * Events that do not fit the current model, e.g. a withdrawal from a stake that was never created, are kept as warnings.
+ * Anyone can log a staking event, only those executed by staking_contract_id change the stakeholders
+ */
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Indexer {
+    #[serde(skip)]
+    pub staking_contract_id: AccountId,
+    pub stakeholders: BTreeMap<AccountId, IndexedStakeholder>,
+    // token contract -> owner -> balance
+    pub token_balances: BTreeMap<AccountId, BTreeMap<AccountId, U128>>,
+    pub warnings: Vec<String>,
+    #[serde(skip)]
+    pub events: u64,
+}
+
+impl Indexer {
+    pub fn new(staking_contract_id: AccountId) -> Self {
+        Self {
+            staking_contract_id,
+            stakeholders: BTreeMap::new(),
+            token_balances: BTreeMap::new(),
+            warnings: vec![],
+            events: 0,
+        }
+    }
+
+    pub fn apply_dump(&mut self, receipts: &[ReceiptLogs]) -> Result<(), String> {
+        receipts
+            .iter()
+            .try_for_each(|receipt| self.apply_receipt(receipt))
+    }
+
+    pub fn apply_receipt(&mut self, receipt: &ReceiptLogs) -> Result<(), String> {
+        for log in &receipt.logs {
+            let event = match parse_event(log) {
+                Some(event) => event.map_err(|err| format!("{}: {}", receipt.location(), err))?,
+                None => continue,
+            };
+            for data in &event.data {
+                self.apply_event(&receipt.executor_id, &event, data)
+                    .map_err(|err| format!("{}: {}", receipt.location(), err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * @notice
+     * apply_event applies one entry of the data of an event, events of other standards are skipped.
+     * So are staking events of any other contract, with a warning
+     */
+    pub fn apply_event(
+        &mut self,
+        executor_id: &AccountId,
+        event: &EventLog,
+        data: &Value,
+    ) -> Result<(), String> {
+        let name = event.event.as_str();
+        if event.standard == STAKING_EVENT_STANDARD && executor_id != &self.staking_contract_id {
+            self.warnings.push(format!(
+                "{} of {} is skipped, staking events come from {}",
+                name, executor_id, self.staking_contract_id
+            ));
+            return Ok(());
+        }
+        match (event.standard.as_str(), name) {
+            (FT_EVENT_STANDARD, "ft_mint") => {
+                let mint: FtMint = parse_data(name, data)?;
+                self._credit(executor_id, &mint.owner_id, mint.amount.0);
+            }
+            (FT_EVENT_STANDARD, "ft_transfer") => {
+                let transfer: FtTransfer = parse_data(name, data)?;
+                self._debit(executor_id, &transfer.old_owner_id, transfer.amount.0);
+                self._credit(executor_id, &transfer.new_owner_id, transfer.amount.0);
+            }
+            (FT_EVENT_STANDARD, "ft_burn") => {
+                let burn: FtBurn = parse_data(name, data)?;
+                self._debit(executor_id, &burn.owner_id, burn.amount.0);
+            }
+            (STAKING_EVENT_STANDARD, "stake_created") => {
+                let created: StakeCreated = parse_data(name, data)?;
+                self._stake_created(created);
+            }
+            (STAKING_EVENT_STANDARD, "stake_increased") => {
+                let increased: StakeIncreased = parse_data(name, data)?;
+                self._stake_increased(increased);
+            }
+            (STAKING_EVENT_STANDARD, "stake_withdrawn") => {
+                let withdrawn: StakeWithdrawn = parse_data(name, data)?;
+                self._stake_withdrawn(withdrawn);
+            }
+            (STAKING_EVENT_STANDARD, "stake_restored") => {
+                let restored: StakeRestored = parse_data(name, data)?;
+                self._stake_restored(restored);
+            }
+            _ => return Ok(()),
+        }
+        self.events += 1;
+        Ok(())
+    }
+
+    fn _credit(&mut self, token_id: &AccountId, owner_id: &AccountId, amount: Balance) {
+        let balance = self
+            .token_balances
+            .entry(token_id.clone())
+            .or_default()
+            .entry(owner_id.clone())
+            .or_insert(U128(0));
+        balance.0 += amount;
+    }
+
+    fn _debit(&mut self, token_id: &AccountId, owner_id: &AccountId, amount: Balance) {
+        let balance = self
+            .token_balances
+            .entry(token_id.clone())
+            .or_default()
+            .entry(owner_id.clone())
+            .or_insert(U128(0));
+        if balance.0 < amount {
+            self.warnings.push(format!(
+                "{} of {} goes below 0, the dump misses earlier events",
+                owner_id, token_id
+            ));
+        }
+        balance.0 = balance.0.saturating_sub(amount);
+    }
+
+    fn _stakeholder(&mut self, account_id: &AccountId) -> &mut IndexedStakeholder {
+        self.stakeholders.entry(account_id.clone()).or_default()
+    }
+
+    fn _stake_created(&mut self, created: StakeCreated) {
+        let stakeholder = self._stakeholder(&created.account_id);
+        let expected = stakeholder.stakes.len() as u64;
+        stakeholder.stakes.push(IndexedStake {
+            token_id: created.token_id,
+            amount: created.amount,
+            locked_until: created.locked_until,
+        });
+        stakeholder.total_amount.0 += created.amount.0;
+        if created.stake_index != expected {
+            self.warnings.push(format!(
+                "{} stake {} was created at index {}",
+                created.account_id, created.stake_index, expected
+            ));
+        }
+    }
+
+    fn _stake_increased(&mut self, increased: StakeIncreased) {
+        let stakeholder = self._stakeholder(&increased.account_id);
+        match stakeholder.stakes.get_mut(increased.stake_index as usize) {
+            Some(stake) if stake.token_id == increased.token_id => {
+                stake.amount.0 += increased.amount.0;
+                stakeholder.total_amount.0 += increased.amount.0;
+            }
+            _ => self.warnings.push(format!(
+                "{} adds to stake {} of {} which is not indexed",
+                increased.account_id, increased.stake_index, increased.token_id
+            )),
+        }
+    }
+
+    fn _stake_withdrawn(&mut self, withdrawn: StakeWithdrawn) {
+        let index = withdrawn.stake_index as usize;
+        let stakeholder = self._stakeholder(&withdrawn.account_id);
+        match stakeholder.stakes.get_mut(index) {
+            Some(stake) if stake.token_id == withdrawn.token_id => {
+                let amount = stake.amount.0.saturating_sub(withdrawn.remaining.0);
+                stake.amount = withdrawn.remaining;
+                stakeholder.total_amount.0 = stakeholder.total_amount.0.saturating_sub(amount);
+                if withdrawn.remaining.0 == 0 {
+                    stakeholder.stakes.remove(index);
+                }
+            }
+            _ => self.warnings.push(format!(
+                "{} withdraws from stake {} of {} which is not indexed",
+                withdrawn.account_id, withdrawn.stake_index, withdrawn.token_id
+            )),
+        }
+    }
+
+    fn _stake_restored(&mut self, restored: StakeRestored) {
+        let index = restored.stake_index as usize;
+        let stakeholder = self._stakeholder(&restored.account_id);
+        let stakes = stakeholder.stakes.len();
+        match stakeholder.stakes.get_mut(index) {
+            None if index == stakes => stakeholder.stakes.push(IndexedStake {
+                token_id: restored.token_id,
+                amount: restored.principal,
+                locked_until: restored.locked_until,
+            }),
+            // older versions merged the principal into a stake of the same token
+            Some(stake) if stake.token_id == restored.token_id => {
+                stake.amount.0 += restored.principal.0;
+            }
+            _ => {
+                self.warnings.push(format!(
+                    "{} restores stake {} of {} which is not indexed",
+                    restored.account_id, restored.stake_index, restored.token_id
+                ));
+                return;
+            }
+        }
+        stakeholder.total_amount.0 += restored.principal.0;
+    }
+}
@@ -0,0 +1,194 @@
+use crate::*;
+
+pub const DEFAULT_VE_MAX_LOCK_DURATION: u64 = 4 * 365 * 24 * 3_600_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+    pub reward_numerator: u32,
+    pub reward_denumerator: u32,
+    pub decimals: u32,
+    pub min_stake: U128,
+    pub max_stake_per_account: Option<U128>,
+    pub pool_capacity: Option<U128>,
+    pub allowlist_enabled: bool,
+    // share of every claimed reward credited to the referrer, in basis points
+    pub referral_bps: u16,
+    // NEP-171 collection whose holders earn boost_bps more rewards
+    pub boost_nft_contract_id: Option<AccountId>,
+    pub boost_bps: u16,
+    pub boost_mode: BoostMode,
+    // token that can be locked for voting power, None disables ve locks
+    pub ve_token_id: Option<AccountId>,
+    pub ve_max_lock_duration: u64,
+    pub governance: GovernanceConfig,
+    pub fees: FeeConfig,
+    pub vesting: VestingConfig,
+}
+
+// APR 15% = (token_stacking * 15/100) * total_block
+// Moi block se tra thuong 715 / 1_000_000_000 --> tinh ra duoc thanh APR 15%
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            reward_numerator: 15,
+            reward_denumerator: 1000,
+            decimals: 24,
+            min_stake: U128(1),
+            max_stake_per_account: None,
+            pool_capacity: None,
+            allowlist_enabled: false,
+            referral_bps: 0,
+            boost_nft_contract_id: None,
+            boost_bps: 0,
+            boost_mode: BoostMode::Ownership,
+            ve_token_id: None,
+            ve_max_lock_duration: DEFAULT_VE_MAX_LOCK_DURATION,
+            governance: GovernanceConfig::default(),
+            fees: FeeConfig::default(),
+            vesting: VestingConfig::default(),
+        }
+    }
+}
+
+/**
+ * @notice
+ * RatePeriod is the reward rate and boost that applied until end (ms). One is kept every time they change,
+ * so what stakes accrued before the change keeps the rate it accrued at
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RatePeriod {
+    pub end: u64,
+    pub reward_numerator: u32,
+    pub reward_denumerator: u32,
+    pub boost_nft_contract_id: Option<AccountId>,
+    pub boost_bps: u16,
+}
+
+impl RatePeriod {
+    pub fn of(config: &Config, end: u64) -> Self {
+        Self {
+            end,
+            reward_numerator: config.reward_numerator,
+            reward_denumerator: config.reward_denumerator,
+            boost_nft_contract_id: config.boost_nft_contract_id.clone(),
+            boost_bps: config.boost_bps,
+        }
+    }
+
+    // whether the rates differ, the end is not compared
+    pub fn same_rates(&self, other: &RatePeriod) -> bool {
+        self.reward_numerator == other.reward_numerator
+            && self.reward_denumerator == other.reward_denumerator
+            && self.boost_nft_contract_id == other.boost_nft_contract_id
+            && self.boost_bps == other.boost_bps
+    }
+
+    // boost of a staker whose NFT is from collection, 0 unless it is the collection of the period
+    pub fn boost_bps_of(&self, collection: Option<&AccountId>) -> u16 {
+        match (collection, &self.boost_nft_contract_id) {
+            (Some(collection), Some(boosted)) if collection == boosted => self.boost_bps,
+            _ => 0,
+        }
+    }
+}
+
+/**
+ * @notice
+ * Ownership: the boost is active while the staker owns the NFT, checked with nft_token
+ * Escrow: the staker transfers the NFT to the contract and gets it back with withdraw_boost_nft
+ */
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum BoostMode {
+    Ownership,
+    Escrow,
+}
+
+pub const DEFAULT_VOTING_PERIOD: u64 = 3 * 24 * 3_600_000;
+pub const DEFAULT_EXECUTION_DELAY: u64 = 24 * 3_600_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceConfig {
+    // staked amount needed to create a proposal
+    pub proposal_threshold: U128,
+    pub voting_period: u64,
+    // share of the total staked at the snapshot that has to vote, in basis points
+    pub quorum_bps: u16,
+    // share of yes in yes + no votes needed to pass, in basis points
+    pub pass_threshold_bps: u16,
+    // time between the end of the vote and the moment a passed proposal can be executed
+    pub execution_delay: u64,
+}
+
+impl Default for GovernanceConfig {
+    fn default() -> Self {
+        Self {
+            proposal_threshold: U128(0),
+            voting_period: DEFAULT_VOTING_PERIOD,
+            quorum_bps: 1_000,
+            pass_threshold_bps: 5_000,
+            execution_delay: DEFAULT_EXECUTION_DELAY,
+        }
+    }
+}
+
+// hard caps, no config or proposal can set a fee above them
+pub const MAX_DEPOSIT_FEE_BPS: u16 = 300;
+pub const MAX_WITHDRAW_FEE_BPS: u16 = 300;
+pub const MAX_REWARD_FEE_BPS: u16 = 2_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfig {
+    pub deposit_fee_bps: u16,
+    pub withdraw_fee_bps: u16,
+    pub reward_fee_bps: u16,
+    // receiver of withdraw_treasury, the owner when not set
+    pub treasury_id: Option<AccountId>,
+}
+
+impl FeeConfig {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.deposit_fee_bps <= MAX_DEPOSIT_FEE_BPS,
+            "Staking: Deposit fee cannot be more than {} bps",
+            MAX_DEPOSIT_FEE_BPS
+        );
+        assert!(
+            self.withdraw_fee_bps <= MAX_WITHDRAW_FEE_BPS,
+            "Staking: Withdraw fee cannot be more than {} bps",
+            MAX_WITHDRAW_FEE_BPS
+        );
+        assert!(
+            self.reward_fee_bps <= MAX_REWARD_FEE_BPS,
+            "Staking: Reward fee cannot be more than {} bps",
+            MAX_REWARD_FEE_BPS
+        );
+    }
+}
+
+// an instant claim can give up at most this share of the reward
+pub const MAX_INSTANT_CLAIM_PENALTY_BPS: u16 = 5_000;
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingConfig {
+    // claimed rewards unlock linearly over this many ms, 0 pays them out right away
+    pub duration: u64,
+    // share of the reward given up to the pool by claim_all with instant
+    pub instant_claim_penalty_bps: u16,
+}
+
+impl VestingConfig {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.instant_claim_penalty_bps <= MAX_INSTANT_CLAIM_PENALTY_BPS,
+            "Staking: Instant claim penalty cannot be more than {} bps",
+            MAX_INSTANT_CLAIM_PENALTY_BPS
+        );
+    }
+}
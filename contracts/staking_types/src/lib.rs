@@ -0,0 +1,248 @@
+/**
+ * @notice
+ * Types and reward math shared by the staking contract and the off-chain tools.
+ * Nothing here calls `env`, so it builds and is tested natively like any other crate
+ */
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{AccountId, Balance};
+
+mod config;
+mod reward;
+mod stake;
+mod units;
+pub use crate::config::*;
+pub use crate::reward::*;
+pub use crate::stake::*;
+pub use crate::units::*;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    const AMOUNT: Balance = 1_000_000;
+
+    fn stake(amount: Balance, since: u64, claimable: Balance) -> Stake {
+        Stake {
+            address: "alice.testnet".parse().unwrap(),
+            token_id: "token.testnet".parse().unwrap(),
+            amount: U128(amount),
+            since,
+            claimable: U128(claimable),
+            locked_until: 0,
+        }
+    }
+
+    #[test]
+    fn test_reward_per_hour() {
+        let config = Config::default();
+        assert_eq!(reward(AMOUNT, 0, 0, &config), 0);
+        assert_eq!(reward(AMOUNT, 0, ONE_HOUR / 2, &config), 7_500);
+        assert_eq!(reward(AMOUNT, 0, ONE_HOUR, &config), 15_000);
+        assert_eq!(
+            reward(AMOUNT, ONE_HOUR, 25 * ONE_HOUR, &config),
+            24 * 15_000
+        );
+    }
+
+    #[test]
+    fn test_reward_of_old_scratch_numbers() {
+        // the numbers the old test.rs worked out by hand
+        let config = Config::default();
+        let amount = 3_000 * 10u128.pow(15);
+        assert_eq!(
+            reward(amount, 1_656_996_479_907, 1_657_006_788_911, &config),
+            128_862_550_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_reward_before_since_is_zero() {
+        assert_eq!(reward(AMOUNT, ONE_HOUR, 0, &Config::default()), 0);
+    }
+
+    #[test]
+    fn test_stake_reward_adds_claimable_and_boost() {
+        let config = Config::default();
+        let stake = stake(AMOUNT, 0, 100);
+        assert_eq!(stake_reward(&stake, ONE_HOUR, &config, 0), 15_100);
+        // the boost only applies to the accrued part
+        assert_eq!(stake_reward(&stake, ONE_HOUR, &config, 5_000), 22_600);
+    }
+
+    #[test]
+    fn test_history_reward_keeps_the_old_rate() {
+        let collection: AccountId = "nft.testnet".parse().unwrap();
+        let mut config = Config {
+            boost_nft_contract_id: Some(collection.clone()),
+            ..Default::default()
+        };
+        let history = [RatePeriod::of(&config, 2 * ONE_HOUR)];
+        config.reward_numerator = 30;
+        config.boost_bps = 5_000;
+        let active = |t: u64| t;
+        let boost = |period: &RatePeriod| period.boost_bps_of(Some(&collection));
+        // 2 hours at 15 / 1000 without boost, then 1 hour at 30 / 1000 with 50% more
+        assert_eq!(
+            history_reward(AMOUNT, 0, 3 * ONE_HOUR, &history, &config, active, boost),
+            2 * 15_000 + 45_000
+        );
+        // stakes started after the change only see the new rate
+        assert_eq!(
+            history_reward(
+                AMOUNT,
+                2 * ONE_HOUR,
+                3 * ONE_HOUR,
+                &history,
+                &config,
+                active,
+                boost
+            ),
+            45_000
+        );
+        // without history it is the boosted part of stake_reward
+        assert_eq!(
+            history_reward(AMOUNT, 0, ONE_HOUR, &[], &config, active, boost),
+            stake_reward(&stake(AMOUNT, 0, 0), ONE_HOUR, &config, 5_000)
+        );
+        // paused time earns nothing in any period
+        let paused = |t: u64| active_time(&[(ONE_HOUR, 2 * ONE_HOUR + ONE_HOUR / 2)], None, t);
+        assert_eq!(
+            history_reward(AMOUNT, 0, 3 * ONE_HOUR, &history, &config, paused, boost),
+            15_000 + 45_000 / 2
+        );
+    }
+
+    #[test]
+    fn test_accrual_skips_pauses_and_retirement() {
+        let pauses = [(ONE_HOUR, 3 * ONE_HOUR)];
+        assert_eq!(active_time(&pauses, None, 4 * ONE_HOUR), 2 * ONE_HOUR);
+        assert_eq!(
+            active_time(&pauses, Some(5 * ONE_HOUR), 6 * ONE_HOUR),
+            3 * ONE_HOUR
+        );
+        // a stake started during a pause accrues from its end
+        assert_eq!(
+            accrual_end(&pauses, None, 2 * ONE_HOUR, 4 * ONE_HOUR),
+            3 * ONE_HOUR
+        );
+        assert_eq!(reward_end(4 * ONE_HOUR, Some(ONE_HOUR)), ONE_HOUR);
+        assert_eq!(reward_end(4 * ONE_HOUR, None), 4 * ONE_HOUR);
+    }
+
+    #[test]
+    fn test_reward_past_the_u128_product() {
+        let config = Config::default();
+        // the smallest multiple of 1000 whose product with a year does not fit a u128
+        let amount = (u128::MAX / ONE_YEAR as u128 / 1_000 + 1) * 1_000;
+        assert!(amount.checked_mul(ONE_YEAR as u128).is_none());
+        assert_eq!(
+            reward(amount, 0, ONE_YEAR, &config),
+            amount / 1_000 * 15 * 24 * 365
+        );
+        assert_eq!(
+            reward(amount - 1_000, 0, ONE_YEAR, &config),
+            (amount - 1_000) / 1_000 * 15 * 24 * 365
+        );
+        // a million 24 decimal tokens over a year
+        assert_eq!(
+            reward(10u128.pow(30), 0, ONE_YEAR, &config),
+            1_314 * 10u128.pow(29)
+        );
+        // u128::MAX * 15 / 3_600_000 / 1_000
+        assert_eq!(
+            weighted_reward(u128::MAX, &config),
+            1_417_843_195_503_910_264_430_727_530_965
+        );
+        // too large for a u128 saturates instead of wrapping
+        assert_eq!(reward(u128::MAX, 0, u64::MAX, &config), u128::MAX);
+        assert_eq!(boosted_reward(u128::MAX, 10_000), u128::MAX);
+        assert_eq!(fee_of(u128::MAX, 10_000), u128::MAX);
+    }
+
+    #[test]
+    fn test_apr_bps() {
+        let config = Config::default();
+        // 1.5% per hour for a year
+        assert_eq!(apr_bps(&config, 0), 150 * 24 * 365);
+        assert_eq!(apr_bps(&config, 5_000), 225 * 24 * 365);
+    }
+
+    #[test]
+    fn test_apy_bps() {
+        // 0.01% per hour, 87.6% a year without compounding
+        let config = Config {
+            reward_numerator: 1,
+            reward_denumerator: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(apy_bps(&config, 0, ONE_YEAR), apr_bps(&config, 0));
+        assert_eq!(apy_bps(&config, 0, 2 * ONE_YEAR), 8_760);
+        assert_eq!(apy_bps(&config, 0, 7 * 24 * ONE_HOUR), 13_839);
+        assert_eq!(apy_bps(&config, 0, 24 * ONE_HOUR), 13_987);
+        assert_eq!(apy_bps(&config, 0, ONE_HOUR), 14_011);
+        // 1.5% per hour compounded daily does not fit
+        assert_eq!(apy_bps(&Config::default(), 0, 24 * ONE_HOUR), u128::MAX);
+        assert_eq!(
+            project_reward(AMOUNT, ONE_HOUR, &Config::default(), 5_000),
+            22_500
+        );
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("1", 24), Ok(10u128.pow(24)));
+        assert_eq!(parse_amount("1.5", 12), Ok(1_500_000_000_000));
+        assert_eq!(parse_amount("0.000000000001", 12), Ok(1));
+        assert_eq!(parse_amount(" 2. ", 12), Ok(2_000_000_000_000));
+        assert!(parse_amount("0.0000000000001", 12).is_err());
+        assert!(parse_amount("1,5", 12).is_err());
+        assert!(parse_amount("", 12).is_err());
+        assert!(parse_amount("-1", 12).is_err());
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(10u128.pow(24), 24), "1");
+        assert_eq!(format_amount(1_500_000_000_000, 12), "1.5");
+        assert_eq!(format_amount(1, 12), "0.000000000001");
+        assert_eq!(format_amount(0, 24), "0");
+    }
+
+    #[test]
+    fn test_convert_decimals() {
+        let one_ntk = 10u128.pow(12);
+        assert_eq!(convert_decimals(one_ntk, 12, 24), 10u128.pow(24));
+        assert_eq!(convert_decimals(10u128.pow(24), 24, 12), one_ntk);
+        assert_eq!(convert_decimals(one_ntk - 1, 12, 0), 0);
+        assert_eq!(convert_decimals(AMOUNT, 18, 18), AMOUNT);
+        assert_eq!(convert_decimals(u128::MAX, 0, 24), u128::MAX);
+    }
+
+    #[test]
+    fn test_fee_of() {
+        assert_eq!(fee_of(AMOUNT, 0), 0);
+        assert_eq!(fee_of(AMOUNT, 300), 30_000);
+    }
+
+    #[test]
+    fn test_mul_div() {
+        assert_eq!(mul_div(7, 6, 4), Some(10));
+        assert_eq!(mul_div(1, 1, 0), None);
+        let supply = 10u128.pow(27);
+        assert_eq!(mul_div(supply, supply, supply), Some(supply));
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX - 1), None);
+        assert_eq!(mul_div(u128::MAX, 3, u128::MAX / 2 + 1), Some(5));
+    }
+
+    #[test]
+    fn test_stake_json_without_lock() {
+        let stake: Stake = near_sdk::serde_json::from_str(
+            r#"{"address": "alice.testnet", "token_id": "token.testnet", "amount": "10", "since": 0, "claimable": "0"}"#,
+        )
+        .unwrap();
+        assert_eq!(stake.locked_until, 0);
+    }
+}
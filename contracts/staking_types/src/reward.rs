@@ -0,0 +1,312 @@
+use crate::*;
+
+pub const ONE_HOUR: u64 = 3_600_000;
+pub const ONE_YEAR: u64 = 365 * 24 * ONE_HOUR;
+pub const BPS_DENOMINATOR: u128 = 10_000;
+// amount the APR is worked out on, large enough that rounding does not show in basis points
+const APR_SAMPLE_AMOUNT: Balance = 1_000_000_000_000_000_000;
+// fixed point scale of the compounding growth factor, small enough that squaring it fits a u128
+const APY_SCALE: u128 = 1_000_000_000_000;
+
+/**
+ * @notice
+ * Wide is an unsigned 256 bit integer in 64 bit limbs, least significant first.
+ * The products of the reward maths are worked out in it so they cannot overflow or wrap
+ */
+#[derive(Clone, Copy)]
+struct Wide([u64; 4]);
+
+impl Wide {
+    fn from_u128(value: u128) -> Self {
+        Wide([value as u64, (value >> 64) as u64, 0, 0])
+    }
+
+    // the caller keeps the product within 256 bits, what would go above is dropped
+    fn mul(self, factor: u64) -> Self {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for (limb, value) in limbs.iter_mut().zip(self.0) {
+            let product = value as u128 * factor as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        Wide(limbs)
+    }
+
+    fn div(self, divisor: u64) -> Self {
+        let mut limbs = [0u64; 4];
+        let mut remainder = 0u128;
+        for index in (0..4).rev() {
+            let value = (remainder << 64) | self.0[index] as u128;
+            limbs[index] = (value / divisor as u128) as u64;
+            remainder = value % divisor as u128;
+        }
+        Wide(limbs)
+    }
+
+    // a * b, a 256 bit product cannot overflow
+    fn product(a: u128, b: u128) -> Self {
+        let low = Wide::from_u128(a).mul(b as u64);
+        let high = Wide::from_u128(a).mul((b >> 64) as u64);
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for (index, limb) in limbs.iter_mut().enumerate() {
+            let shifted = if index == 0 { 0 } else { high.0[index - 1] };
+            let sum = low.0[index] as u128 + shifted as u128 + carry;
+            *limb = sum as u64;
+            carry = sum >> 64;
+        }
+        Wide(limbs)
+    }
+
+    // long division bit by bit, for divisors that do not fit a u64
+    fn div_wide(self, divisor: u128) -> Self {
+        let mut limbs = [0u64; 4];
+        let mut remainder = 0u128;
+        for bit in (0..256).rev() {
+            let carry = remainder >> 127;
+            remainder = (remainder << 1) | ((self.0[bit / 64] >> (bit % 64)) & 1) as u128;
+            if carry == 1 || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                limbs[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        Wide(limbs)
+    }
+
+    fn checked_u128(self) -> Option<u128> {
+        match self.0 {
+            [low, high, 0, 0] => Some((high as u128) << 64 | low as u128),
+            _ => None,
+        }
+    }
+
+    // saturates at u128::MAX
+    fn to_u128(self) -> u128 {
+        self.checked_u128().unwrap_or(u128::MAX)
+    }
+}
+
+// the reward of a weight: weight * reward_numerator / 1 hour / reward_denumerator, both divisions round down
+fn reward_of_wide(weight: Wide, reward_numerator: u32, reward_denumerator: u32) -> Balance {
+    weight
+        .mul(reward_numerator as u64)
+        .div(ONE_HOUR * reward_denumerator as u64)
+        .to_u128()
+}
+
+// amount * bps / BPS_DENOMINATOR without overflow
+fn bps_of(amount: Balance, bps: u16) -> Balance {
+    Wide::from_u128(amount)
+        .mul(bps as u64)
+        .div(BPS_DENOMINATOR as u64)
+        .to_u128()
+}
+
+/**
+ * @notice
+ * reward is what amount earns from since until now, timestamps in ms, without any boost.
+ * We reward reward_numerator / reward_denumerator of the amount per hour staked:
+ * (duration * amount * reward_numerator / 1 hour) / reward_denumerator.
+ * The product is 256 bits wide, a reward too large for a u128 saturates
+ */
+pub fn reward(amount: Balance, since: u64, now: u64, config: &Config) -> Balance {
+    let duration = now.saturating_sub(since);
+    reward_of_wide(
+        Wide::from_u128(amount).mul(duration),
+        config.reward_numerator,
+        config.reward_denumerator,
+    )
+}
+
+/**
+ * @notice
+ * weighted_reward is the unboosted reward of weight, an amount multiplied by the ms it was staked.
+ * Summed weights of many stakes give their reward at once
+ */
+pub fn weighted_reward(weight: u128, config: &Config) -> Balance {
+    reward_of_wide(
+        Wide::from_u128(weight),
+        config.reward_numerator,
+        config.reward_denumerator,
+    )
+}
+
+// weighted_reward at the rate of a period
+pub fn weighted_period_reward(weight: u128, period: &RatePeriod) -> Balance {
+    reward_of_wide(
+        Wide::from_u128(weight),
+        period.reward_numerator,
+        period.reward_denumerator,
+    )
+}
+
+pub fn boosted_reward(accrued: Balance, boost_bps: u16) -> Balance {
+    accrued.saturating_add(bps_of(accrued, boost_bps))
+}
+
+/**
+ * @notice
+ * stake_reward is everything a stake can claim at now: the settled claimable plus the boosted reward since the stake started
+ */
+pub fn stake_reward(stake: &Stake, now: u64, config: &Config, boost_bps: u16) -> Balance {
+    let accrued = reward(stake.amount.0, stake.since, now, config);
+    stake
+        .claimable
+        .0
+        .saturating_add(boosted_reward(accrued, boost_bps))
+}
+
+/**
+ * @notice
+ * history_reward is the boosted reward amount earns from since to end (ms) at the rates of the time:
+ * every period of history, oldest first, pays its rate until its end and config pays after the last one.
+ * active_time maps a timestamp to the time rewards accrued until it, so paused time earns nothing,
+ * boost_bps is the boost of the staker in a period. Without history it is the boosted part of stake_reward
+ */
+pub fn history_reward(
+    amount: Balance,
+    since: u64,
+    end: u64,
+    history: &[RatePeriod],
+    config: &Config,
+    active_time: impl Fn(u64) -> u64,
+    boost_bps: impl Fn(&RatePeriod) -> u16,
+) -> Balance {
+    let current = RatePeriod::of(config, u64::MAX);
+    let mut total: Balance = 0;
+    let mut start = since;
+    for period in history.iter().chain(std::iter::once(&current)) {
+        if start >= end {
+            break;
+        }
+        if period.end <= start {
+            continue;
+        }
+        let stop = period.end.min(end);
+        let duration = active_time(stop).saturating_sub(active_time(start));
+        let accrued = reward_of_wide(
+            Wide::from_u128(amount).mul(duration),
+            period.reward_numerator,
+            period.reward_denumerator,
+        );
+        total = total.saturating_add(boosted_reward(accrued, boost_bps(period)));
+        start = stop;
+    }
+    total
+}
+
+/**
+ * @notice
+ * active_time is t minus the time rewards were paused before t. pauses are the closed (start, end) pauses in ms,
+ * paused_since the start of the pause still going on. Rewards accrue between two active times
+ */
+pub fn active_time(pauses: &[(u64, u64)], paused_since: Option<u64>, t: u64) -> u64 {
+    let closed: u64 = pauses
+        .iter()
+        .filter(|(start, _)| *start < t)
+        .map(|(start, end)| (*end).min(t) - start)
+        .sum();
+    let open = match paused_since {
+        Some(start) if start < t => t - start,
+        _ => 0,
+    };
+    t - closed - open
+}
+
+/**
+ * @notice
+ * accrual_end is the end to count the rewards of a stake started at since up to,
+ * moved back by the time rewards were paused in between
+ */
+pub fn accrual_end(pauses: &[(u64, u64)], paused_since: Option<u64>, since: u64, end: u64) -> u64 {
+    since
+        + active_time(pauses, paused_since, end).saturating_sub(active_time(
+            pauses,
+            paused_since,
+            since,
+        ))
+}
+
+/**
+ * @notice
+ * reward_end is now, or the retirement of the token when it is retired before now
+ */
+pub fn reward_end(now: u64, retired_since: Option<u64>) -> u64 {
+    retired_since.map_or(now, |since| now.min(since))
+}
+
+/**
+ * @notice
+ * apr_bps is the simple yearly reward rate of a stake in basis points, boost_bps included
+ */
+pub fn apr_bps(config: &Config, boost_bps: u16) -> u128 {
+    let yearly = boosted_reward(reward(APR_SAMPLE_AMOUNT, 0, ONE_YEAR, config), boost_bps);
+    yearly * BPS_DENOMINATOR / APR_SAMPLE_AMOUNT
+}
+
+/**
+ * @notice
+ * project_reward is the reward of a new stake of amount kept for duration ms, boost_bps included
+ */
+pub fn project_reward(amount: Balance, duration: u64, config: &Config, boost_bps: u16) -> Balance {
+    boosted_reward(reward(amount, 0, duration, config), boost_bps)
+}
+
+fn mul_scaled(a: u128, b: u128) -> Option<u128> {
+    a.checked_mul(b).map(|product| product / APY_SCALE)
+}
+
+/**
+ * @notice
+ * apy_bps is the yearly rate in basis points when the reward is restaked every compounding_period ms.
+ * What is left of the year after the last full period accrues without compounding.
+ * Saturates at u128::MAX when the compounded rate does not fit
+ */
+pub fn apy_bps(config: &Config, boost_bps: u16, compounding_period: u64) -> u128 {
+    assert!(
+        compounding_period > 0,
+        "Compounding period must be more than 0"
+    );
+    let period = compounding_period.min(ONE_YEAR);
+    let mut factor = APY_SCALE + project_reward(APY_SCALE, period, config, boost_bps);
+    let mut periods = ONE_YEAR / period;
+    let mut growth = Some(APY_SCALE);
+    while periods > 0 && growth.is_some() {
+        if periods % 2 == 1 {
+            growth = growth.and_then(|growth| mul_scaled(growth, factor));
+        }
+        periods /= 2;
+        if periods > 0 {
+            match mul_scaled(factor, factor) {
+                Some(squared) => factor = squared,
+                None => growth = None,
+            }
+        }
+    }
+    let remainder = ONE_YEAR % period;
+    growth
+        .and_then(|growth| {
+            let rest = APY_SCALE + project_reward(APY_SCALE, remainder, config, boost_bps);
+            mul_scaled(growth, rest)
+        })
+        .and_then(|growth| (growth - APY_SCALE).checked_mul(BPS_DENOMINATOR))
+        .map(|yearly| yearly / APY_SCALE)
+        .unwrap_or(u128::MAX)
+}
+
+/**
+ * @notice
+ * mul_div is a * b / c rounded down, with the product worked out in 256 bits.
+ * None when c is 0 or the quotient does not fit a u128
+ */
+pub fn mul_div(a: u128, b: u128, c: u128) -> Option<u128> {
+    if c == 0 {
+        return None;
+    }
+    Wide::product(a, b).div_wide(c).checked_u128()
+}
+
+pub fn fee_of(amount: Balance, fee_bps: u16) -> Balance {
+    bps_of(amount, fee_bps)
+}
@@ -0,0 +1,31 @@
+use crate::*;
+
+/**
+ * @notice
+ * A stake struct is used to represent the way we store stakes,
+ * A Stake will contain the users address, the amount staked and a timestamp,
+ * Since which is when the stake was made
+ */
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Stake {
+    pub address: AccountId,  // address
+    pub token_id: AccountId, // token contract the stake was deposited with
+    pub amount: U128,        // amount of staked
+    pub since: u64,          // start
+    pub claimable: U128,
+    // the principal cannot be withdrawn before this timestamp in ms, 0 when the stake is not locked
+    #[serde(default)]
+    pub locked_until: u64,
+}
+
+/**
+ * @notice
+ * StakingSummary is a struct that is used to contain all stakes performed by a certain account
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakingSummary {
+    pub total_amount: U128,
+    pub stakes: Vec<Stake>,
+}
@@ -0,0 +1,71 @@
+use crate::*;
+
+/**
+ * @notice
+ * parse_amount converts a human readable amount like "1.5" into the smallest unit of a token with decimals
+ */
+pub fn parse_amount(amount: &str, decimals: u32) -> Result<Balance, String> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() && fraction.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+        return Err(format!("Invalid amount {:?}", amount));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format!(
+            "Amount {} has more than {} decimals",
+            amount, decimals
+        ));
+    }
+    let unit = 10u128
+        .checked_pow(decimals)
+        .ok_or_else(|| format!("Cannot use {} decimals", decimals))?;
+    let whole: Balance = if whole.is_empty() {
+        0
+    } else {
+        whole
+            .parse()
+            .map_err(|_| format!("Amount {} is too large", amount))?
+    };
+    let fraction: Balance = format!("{:0<width$}", fraction, width = decimals as usize)
+        .parse()
+        .unwrap_or(0);
+    whole
+        .checked_mul(unit)
+        .and_then(|whole| whole.checked_add(fraction))
+        .ok_or_else(|| format!("Amount {} is too large", amount))
+}
+
+/**
+ * @notice
+ * format_amount is the reverse of parse_amount, trailing zeros of the fraction are dropped
+ */
+pub fn format_amount(amount: Balance, decimals: u32) -> String {
+    let unit = 10u128.pow(decimals);
+    let whole = amount / unit;
+    let fraction = amount % unit;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0>width$}", fraction, width = decimals as usize);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+/**
+ * @notice
+ * convert_decimals expresses amount of a token with from decimals in a unit with to decimals,
+ * rounding down when precision is lost and saturating when it does not fit
+ */
+pub fn convert_decimals(amount: Balance, from: u32, to: u32) -> Balance {
+    match from.cmp(&to) {
+        std::cmp::Ordering::Equal => amount,
+        std::cmp::Ordering::Less => 10u128
+            .checked_pow(to - from)
+            .map(|unit| amount.saturating_mul(unit))
+            .unwrap_or(Balance::MAX),
+        std::cmp::Ordering::Greater => 10u128
+            .checked_pow(from - to)
+            .map(|unit| amount / unit)
+            .unwrap_or(0),
+    }
+}
@@ -0,0 +1,7 @@
+#!/bin/bash
+# set -e
+# cd ..
+cd "`dirname $0`"
+cd ..
+RUSTFLAGS='-C link-arg=-s' cargo build --all --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/*.wasm ./res/
@@ -0,0 +1,8 @@
+#!/bin/bash
+
+cd "`dirname $0`"
+cd ..
+
+CONTRACT_FILE=./res/vault_contract.wasm
+
+near dev-deploy --wasmFile $CONTRACT_FILE
@@ -0,0 +1,14 @@
+#!/bin/bash
+OWNER_ID="nolannguyen.testnet"
+TOKEN_ID="dev-1653846714290-58446128043200"
+STAKING_ID=$(cat ../staking_contract/neardev/dev-account)
+CONTRACT_NAME=$(cat ./neardev/dev-account)
+
+near call $CONTRACT_NAME new '{"owner_id": "'$OWNER_ID'", "token_id": "'$TOKEN_ID'", "staking_id": "'$STAKING_ID'", "decimals": 12}' --accountId $CONTRACT_NAME
+
+# the vault needs a storage balance on the token and on the staking contract
+near call $TOKEN_ID storage_deposit '{"account_id": "'$CONTRACT_NAME'"}' --accountId $OWNER_ID --amount 0.00235
+near call $STAKING_ID storage_deposit '{"account_id": "'$CONTRACT_NAME'"}' --accountId $OWNER_ID --amount 0.05
+
+# rewards of the vault are compounded, they cannot vest
+near call $STAKING_ID add_vesting_exempt '{"account_ids": ["'$CONTRACT_NAME'"]}' --accountId $OWNER_ID
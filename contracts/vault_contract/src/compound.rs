@@ -0,0 +1,259 @@
+use crate::*;
+use near_sdk::serde_json;
+use near_sdk::{Promise, PromiseResult};
+
+impl Vault {
+    /**
+     * @notice
+     * sync reads the vault's token balance and its stake from the other contracts,
+     * the callback gets both results and should call read_sync
+     */
+    pub(crate) fn sync(&self) -> Promise {
+        let vault_id = env::current_account_id();
+        ext_ft::ext(self.token_id.clone())
+            .with_static_gas(FT_BALANCE_OF_GAS)
+            .ft_balance_of(vault_id.clone())
+            .and(
+                ext_staking::ext(self.staking_id.clone())
+                    .with_static_gas(HAS_STAKE_GAS)
+                    .has_stake(vault_id),
+            )
+    }
+
+    /**
+     * @notice
+     * read_sync returns the token balance and the staked principal of the vault, None when either call failed.
+     * has_stake of an account that never staked succeeds with nothing staked, a failure is not read as 0
+     */
+    pub(crate) fn read_sync(&self) -> Option<(Balance, Balance)> {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value).ok()?.0,
+            _ => return None,
+        };
+        let staked = match env::promise_result(1) {
+            PromiseResult::Successful(value) => {
+                serde_json::from_slice::<StakingSummary>(&value)
+                    .ok()?
+                    .total_amount
+                    .0
+            }
+            _ => return None,
+        };
+        Some((balance, staked))
+    }
+
+    pub(crate) fn apply_sync(&mut self, balance: Balance, staked: Balance) {
+        self.idle = balance;
+        self.staked_principal = staked;
+    }
+
+    pub(crate) fn bounty_of(&self, gained: Balance) -> Balance {
+        gained * self.bounty_bps as u128 / BPS_DENOMINATOR
+    }
+
+    fn compound_preview(&self, harvested: Balance) -> CompoundPreview {
+        let bounty = self.bounty_of(harvested);
+        CompoundPreview {
+            harvested: U128(harvested),
+            bounty: U128(bounty),
+            staked: U128(harvested - bounty + self.idle),
+            error: None,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Vault {
+    /**
+     * @notice
+     * harvest_and_compound claims the rewards of the vault's stake and stakes them with the idle tokens,
+     * the caller receives bounty_bps of what the vault gained. The principal stays staked, so calling it often
+     * only costs the caller gas
+     */
+    pub fn harvest_and_compound(&mut self) -> Promise {
+        self.assert_not_busy();
+        self.busy = true;
+        let caller = env::predecessor_account_id();
+        let assets_before = U128(self.total_assets());
+        if self.staked_principal > 0 {
+            ext_staking::ext(self.staking_id.clone())
+                .with_static_gas(WITHDRAW_STAKE_GAS)
+                .withdraw_stake(U128(0), 0)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(ON_HARVEST_WITHDRAWN_GAS)
+                        .on_harvest_withdrawn(caller, assets_before),
+                )
+        } else {
+            self.sync().then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_HARVEST_SYNCED_GAS)
+                    .on_harvest_synced(caller, assets_before),
+            )
+        }
+    }
+
+    #[private]
+    pub fn on_harvest_withdrawn(&mut self, caller: AccountId, assets_before: U128) -> Promise {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            log_str("Vault: Claiming the rewards failed");
+        }
+        self.sync().then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(ON_HARVEST_SYNCED_GAS)
+                .on_harvest_synced(caller, assets_before),
+        )
+    }
+
+    #[private]
+    pub fn on_harvest_synced(&mut self, caller: AccountId, assets_before: U128) -> U128 {
+        let (balance, staked) = match self.read_sync() {
+            Some(result) => result,
+            None => {
+                log_str("Vault: Cannot read the vault balance");
+                self.busy = false;
+                return U128(0);
+            }
+        };
+        let gained = (balance + staked).saturating_sub(assets_before.0);
+        let bounty = self.bounty_of(gained);
+        self.apply_sync(balance - bounty, staked);
+        if bounty > 0 {
+            ext_ft::ext(self.token_id.clone())
+                .with_static_gas(FT_TRANSFER_GAS)
+                .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                .ft_transfer(
+                    caller.clone(),
+                    U128(bounty),
+                    Some("harvest bounty".to_string()),
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(ON_BOUNTY_PAID_GAS)
+                        .on_bounty_paid(caller, U128(bounty)),
+                );
+        }
+        if self.idle > 0 {
+            // added to the stake at index 0 once there is one, the vault keeps a single stake
+            let msg = match staked {
+                0 => "staking".to_string(),
+                _ => r#"{"action": "staking", "stake_index": 0}"#.to_string(),
+            };
+            ext_ft::ext(self.token_id.clone())
+                .with_static_gas(FT_TRANSFER_CALL_GAS)
+                .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+                .ft_transfer_call(self.staking_id.clone(), U128(self.idle), None, msg)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(ON_COMPOUNDED_GAS)
+                        .on_compounded(),
+                );
+        } else {
+            self.busy = false;
+        }
+        log_str(format!("harvested={}, bounty={}", gained, bounty).as_str());
+        U128(gained)
+    }
+
+    /**
+     * @notice
+     * preview_compound is what harvest_and_compound would do now, read from preview_claim of the staking contract.
+     * A vault that never staked has nothing to harvest and only stakes its idle tokens
+     */
+    pub fn preview_compound(&self) -> PromiseOrValue<CompoundPreview> {
+        if self.staked_principal == 0 {
+            return PromiseOrValue::Value(self.compound_preview(0));
+        }
+        ext_staking::ext(self.staking_id.clone())
+            .with_static_gas(PREVIEW_CLAIM_GAS)
+            .preview_claim(env::current_account_id(), Some(false))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_COMPOUND_PREVIEWED_GAS)
+                    .on_compound_previewed(),
+            )
+            .into()
+    }
+
+    #[private]
+    pub fn on_compound_previewed(&self) -> CompoundPreview {
+        let claim = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<ClaimPreview>(&value).ok(),
+            _ => None,
+        };
+        match claim {
+            Some(ClaimPreview {
+                error: None,
+                payouts,
+            }) => {
+                let harvested = payouts
+                    .iter()
+                    .filter(|payout| payout.token_id == self.token_id)
+                    .map(|payout| payout.principal_out.0 + payout.reward_out.0 - payout.shortfall.0)
+                    .sum();
+                self.compound_preview(harvested)
+            }
+            Some(ClaimPreview {
+                error: Some(error), ..
+            }) => CompoundPreview::failed(error),
+            None => CompoundPreview::failed(
+                "Vault: Cannot read the claim preview of the staking contract".to_string(),
+            ),
+        }
+    }
+
+    /**
+     * @notice
+     * on_bounty_paid puts a bounty that could not be transferred back to the idle tokens
+     */
+    #[private]
+    pub fn on_bounty_paid(&mut self, caller: AccountId, bounty: U128) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => bounty,
+            _ => {
+                log!("Vault: Bounty of @{} failed, {} is kept", caller, bounty.0);
+                self.idle += bounty.0;
+                U128(0)
+            }
+        }
+    }
+
+    #[private]
+    pub fn on_compounded(&mut self) -> Promise {
+        self.sync().then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(ON_SYNCED_GAS)
+                .on_synced(),
+        )
+    }
+
+    /**
+     * @notice
+     * on_synced stores the balances read by sync and ends the running operation
+     */
+    #[private]
+    pub fn on_synced(&mut self) {
+        if let Some((balance, staked)) = self.read_sync() {
+            self.apply_sync(balance, staked);
+        }
+        self.busy = false;
+    }
+
+    /**
+     * @notice
+     * force_sync lets the owner resync the balances and unlock the vault if an operation got stuck
+     */
+    pub fn force_sync(&mut self) -> Promise {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Vault: Only the owner can call this method"
+        );
+        self.busy = true;
+        self.sync().then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(ON_SYNCED_GAS)
+                .on_synced(),
+        )
+    }
+}
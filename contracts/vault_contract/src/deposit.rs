@@ -0,0 +1,124 @@
+use crate::*;
+use near_contract_standards::fungible_token::events::FtMint;
+use near_sdk::{Promise, PromiseResult};
+
+impl Vault {
+    /**
+     * @notice
+     * mint_deposit mints the shares of amount at the current share price and adds amount to the idle tokens.
+     * Returns the amount to refund
+     */
+    fn mint_deposit(&mut self, sender_id: &AccountId, amount: Balance, msg: &str) -> Balance {
+        let mut shares = self.shares_for_amount(amount);
+        if self.token.total_supply == 0 {
+            if shares <= DEAD_SHARES {
+                log_str(&format!(
+                    "Vault: The first deposit has to be more than {}",
+                    DEAD_SHARES
+                ));
+                return amount;
+            }
+            self.mint_dead_shares();
+            shares -= DEAD_SHARES;
+        }
+        if shares == 0 {
+            log_str("Vault: Deposit is too small");
+            return amount;
+        }
+        if !self.token.accounts.contains_key(sender_id) {
+            log_str("Vault: Register with storage_deposit before depositing");
+            return amount;
+        }
+        self.token.internal_deposit(sender_id, shares);
+        self.idle += amount;
+        FtMint {
+            owner_id: sender_id,
+            amount: &U128(shares),
+            memo: Some(msg),
+        }
+        .emit();
+        0
+    }
+}
+
+#[near_bindgen]
+impl Vault {
+    /**
+     * @notice
+     * Deposits of the staked token mint shares at the current share price, any msg is accepted.
+     * While the vault has a stake its rewards are claimed first, so the shares are priced with them.
+     * The sender pays the storage of its shares with storage_deposit first, unregistered senders are refunded.
+     * Returns the amount to refund, everything while the vault is busy
+     */
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.token_id,
+            "Vault: Only the staked token can be deposited"
+        );
+        if self.busy {
+            log_str("Vault: Busy, deposit refunded");
+            return PromiseOrValue::Value(amount);
+        }
+        if !self.token.accounts.contains_key(&sender_id) {
+            log_str("Vault: Register with storage_deposit before depositing");
+            return PromiseOrValue::Value(amount);
+        }
+        if self.staked_principal == 0 {
+            return PromiseOrValue::Value(U128(self.mint_deposit(&sender_id, amount.0, &msg)));
+        }
+        self.busy = true;
+        PromiseOrValue::Promise(
+            ext_staking::ext(self.staking_id.clone())
+                .with_static_gas(WITHDRAW_STAKE_GAS)
+                .withdraw_stake(U128(0), 0)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(ON_DEPOSIT_HARVESTED_GAS)
+                        .on_deposit_harvested(sender_id, amount, msg),
+                ),
+        )
+    }
+
+    #[private]
+    pub fn on_deposit_harvested(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> Promise {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            log_str("Vault: Claiming the rewards failed");
+        }
+        self.sync().then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(ON_DEPOSIT_SYNCED_GAS)
+                .on_deposit_synced(sender_id, amount, msg),
+        )
+    }
+
+    /**
+     * @notice
+     * on_deposit_synced mints the shares of a deposit once the claimed rewards are counted.
+     * The synced balance already holds the deposit, it is left out of the assets the shares are priced on.
+     * Returns the amount to refund, everything when the balances cannot be read
+     */
+    #[private]
+    pub fn on_deposit_synced(&mut self, sender_id: AccountId, amount: U128, msg: String) -> U128 {
+        self.busy = false;
+        let (balance, staked) = match self.read_sync() {
+            Some(result) => result,
+            None => {
+                log_str("Vault: Cannot read the vault balance, deposit refunded");
+                return amount;
+            }
+        };
+        self.apply_sync(balance.saturating_sub(amount.0), staked);
+        U128(self.mint_deposit(&sender_id, amount.0, &msg))
+    }
+}
@@ -0,0 +1,781 @@
+/*!
+Auto-compounding vault on top of the staking contract.
+NOTES:
+  - Users deposit the staked token with `ft_transfer_call` and receive vault shares, a NEP-141 token.
+  - Deposits are kept idle until the next `harvest_and_compound`, which anyone can call. It claims the rewards of
+    the vault's stake with `withdraw_stake(0, 0)` and adds them with the idle tokens to that same stake, so the vault
+    only ever has the stake at index 0 and the principal never pays the withdraw fee of the staking contract.
+    The caller earns `bounty_bps` of the harvested rewards.
+  - Share price is total assets (staked principal + idle tokens) / total shares, it goes up at every harvest.
+    The first deposit mints `DEAD_SHARES` to the vault itself, they keep the price from being inflated.
+    Rewards accrued since the last harvest are not counted until they are harvested, so a deposit into a staked
+    vault claims them first and only then mints its shares, it cannot buy into rewards it did not earn.
+  - Withdrawals burn shares and pay from idle tokens first, the rest is unstaked from the staking contract.
+  - One operation that waits for other contracts runs at a time, others fail while the vault is busy.
+  - The vault needs a storage balance on the token and on the staking contract, see scripts/init.sh.
+    Depositors pay for the storage of their shares with `storage_deposit` before their first deposit.
+*/
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
+};
+use near_contract_standards::fungible_token::FungibleToken;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LazyOption;
+use near_sdk::env::log_str;
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, ext_contract, log, near_bindgen, AccountId, Balance, Gas, PanicOnDefault, PromiseOrValue,
+};
+use staking_types::mul_div;
+
+mod compound;
+mod deposit;
+mod withdraw;
+
+pub const BPS_DENOMINATOR: u128 = 10_000;
+pub const MAX_BOUNTY_BPS: u16 = 500;
+pub const DEFAULT_BOUNTY_BPS: u16 = 50;
+// minted to the vault itself by the first deposit and never burned, so the share price cannot be inflated
+// by donating tokens to a vault with a handful of shares
+pub const DEAD_SHARES: Balance = 1_000;
+
+pub const DEPOSIT_ONE_YOCTO: Balance = 1;
+pub const NO_DEPOSIT: Balance = 0;
+pub const FT_TRANSFER_GAS: Gas = Gas(10_000_000_000_000);
+pub const FT_TRANSFER_CALL_GAS: Gas = Gas(60_000_000_000_000);
+pub const FT_BALANCE_OF_GAS: Gas = Gas(5_000_000_000_000);
+// what the staking contract attaches to the payout of one withdrawal: the registration of the vault
+// on the token, ft_transfer and on_withdraw_resolved, see SETTLEMENT_PAYOUT_GAS of staking_contract
+pub const STAKING_PAYOUT_GAS: Gas = Gas(60_000_000_000_000);
+// withdraw_stake itself, before the payout
+pub const STAKING_WITHDRAW_EXECUTION_GAS: Gas = Gas(20_000_000_000_000);
+pub const WITHDRAW_STAKE_GAS: Gas = Gas(STAKING_WITHDRAW_EXECUTION_GAS.0 + STAKING_PAYOUT_GAS.0);
+pub const HAS_STAKE_GAS: Gas = Gas(10_000_000_000_000);
+pub const PREVIEW_CLAIM_GAS: Gas = Gas(15_000_000_000_000);
+pub const ON_COMPOUND_PREVIEWED_GAS: Gas = Gas(5_000_000_000_000);
+pub const ON_SYNCED_GAS: Gas = Gas(10_000_000_000_000);
+pub const ON_COMPOUNDED_GAS: Gas = Gas(40_000_000_000_000);
+pub const ON_DEPOSIT_HARVESTED_GAS: Gas = Gas(40_000_000_000_000);
+pub const ON_DEPOSIT_SYNCED_GAS: Gas = Gas(15_000_000_000_000);
+pub const ON_HARVEST_SYNCED_GAS: Gas = Gas(140_000_000_000_000);
+pub const ON_HARVEST_WITHDRAWN_GAS: Gas = Gas(170_000_000_000_000);
+pub const ON_BOUNTY_PAID_GAS: Gas = Gas(10_000_000_000_000);
+pub const ON_WITHDRAW_PAID_GAS: Gas = Gas(10_000_000_000_000);
+pub const ON_WITHDRAW_SYNCED_GAS: Gas = Gas(40_000_000_000_000);
+pub const ON_WITHDRAW_UNSTAKED_GAS: Gas = Gas(80_000_000_000_000);
+
+#[allow(dead_code)]
+#[ext_contract(ext_ft)]
+trait FungibleTokenContract {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> U128;
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+}
+
+#[allow(dead_code)]
+#[ext_contract(ext_staking)]
+trait StakingContract {
+    fn withdraw_stake(&mut self, amount: U128, stake_index: usize);
+    fn has_stake(&self, staker: AccountId) -> StakingSummary;
+    fn preview_claim(&self, account_id: AccountId, instant: Option<bool>) -> ClaimPreview;
+}
+
+// Only the part of the staking contract's summary the vault reads
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakingSummary {
+    pub total_amount: U128,
+}
+
+// Only the part of the staking contract's preview_claim the vault reads
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimPreview {
+    #[serde(default)]
+    pub payouts: Vec<ClaimPayout>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimPayout {
+    pub token_id: AccountId,
+    pub principal_out: U128,
+    pub reward_out: U128,
+    pub shortfall: U128,
+}
+
+/**
+ * @notice
+ * CompoundPreview is what harvest_and_compound would do now. The deposit fee of the staking contract is not taken
+ * out of staked, preview_stake of the staking contract previews that deposit
+ */
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CompoundPreview {
+    // rewards of the vault's stake that would be paid now
+    pub harvested: U128,
+    // paid to the caller
+    pub bounty: U128,
+    // harvested less the bounty, with the idle tokens, transferred to the staking contract
+    pub staked: U128,
+    pub error: Option<String>,
+}
+
+impl CompoundPreview {
+    fn failed(error: String) -> Self {
+        Self {
+            harvested: U128(0),
+            bounty: U128(0),
+            staked: U128(0),
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VaultInfo {
+    pub token_id: AccountId,
+    pub staking_id: AccountId,
+    pub staked_principal: U128,
+    pub idle: U128,
+    pub total_assets: U128,
+    pub total_shares: U128,
+    // value of one whole share (10^decimals) in the token
+    pub share_price: U128,
+    pub bounty_bps: u16,
+    pub busy: bool,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Vault {
+    // vault shares
+    token: FungibleToken,
+    metadata: LazyOption<FungibleTokenMetadata>,
+    owner_id: AccountId,
+    // the token that is staked, NTK
+    token_id: AccountId,
+    staking_id: AccountId,
+    // principal of the vault's single stake in the staking contract
+    staked_principal: Balance,
+    // tokens held by the vault and not staked yet
+    idle: Balance,
+    bounty_bps: u16,
+    busy: bool,
+}
+
+#[near_bindgen]
+impl Vault {
+    #[init]
+    pub fn new(
+        owner_id: AccountId,
+        token_id: AccountId,
+        staking_id: AccountId,
+        decimals: u8,
+    ) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        let metadata = FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Nolan Vault Share".to_string(),
+            symbol: "vNTK".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals,
+        };
+        metadata.assert_valid();
+        Self {
+            token: FungibleToken::new(b"a".to_vec()),
+            metadata: LazyOption::new(b"m".to_vec(), Some(&metadata)),
+            owner_id,
+            token_id,
+            staking_id,
+            staked_principal: 0,
+            idle: 0,
+            bounty_bps: DEFAULT_BOUNTY_BPS,
+            busy: false,
+        }
+    }
+
+    pub fn set_bounty_bps(&mut self, bounty_bps: u16) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Vault: Only the owner can call this method"
+        );
+        assert!(
+            bounty_bps <= MAX_BOUNTY_BPS,
+            "Vault: Bounty cannot be more than {} bps",
+            MAX_BOUNTY_BPS
+        );
+        self.bounty_bps = bounty_bps;
+    }
+
+    // * readonly
+    pub fn get_vault_info(&self) -> VaultInfo {
+        let one_share = 10u128.pow(self.metadata.get().unwrap().decimals as u32);
+        VaultInfo {
+            token_id: self.token_id.clone(),
+            staking_id: self.staking_id.clone(),
+            staked_principal: U128(self.staked_principal),
+            idle: U128(self.idle),
+            total_assets: U128(self.total_assets()),
+            total_shares: U128(self.token.total_supply),
+            share_price: U128(self.amount_for_shares(one_share)),
+            bounty_bps: self.bounty_bps,
+            busy: self.busy,
+        }
+    }
+
+    // * readonly
+    pub fn preview_withdraw(&self, shares: U128) -> U128 {
+        U128(self.amount_for_shares(shares.0))
+    }
+
+    fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
+        log!("Closed @{} with {}", account_id, balance);
+    }
+
+    fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
+        log!("Account @{} burned {}", account_id, amount);
+    }
+}
+
+impl Vault {
+    pub(crate) fn total_assets(&self) -> Balance {
+        self.staked_principal + self.idle
+    }
+
+    pub(crate) fn shares_for_amount(&self, amount: Balance) -> Balance {
+        let total_assets = self.total_assets();
+        if self.token.total_supply == 0 || total_assets == 0 {
+            amount
+        } else {
+            mul_div(amount, self.token.total_supply, total_assets)
+                .unwrap_or_else(|| env::panic_str("Vault: Amount is too large"))
+        }
+    }
+
+    pub(crate) fn amount_for_shares(&self, shares: Balance) -> Balance {
+        if self.token.total_supply == 0 {
+            return shares;
+        }
+        mul_div(shares, self.total_assets(), self.token.total_supply)
+            .unwrap_or_else(|| env::panic_str("Vault: Amount is too large"))
+    }
+
+    fn mint_dead_shares(&mut self) {
+        let vault_id = env::current_account_id();
+        if !self.token.accounts.contains_key(&vault_id) {
+            self.token.internal_register_account(&vault_id);
+        }
+        self.token.internal_deposit(&vault_id, DEAD_SHARES);
+        near_contract_standards::fungible_token::events::FtMint {
+            owner_id: &vault_id,
+            amount: &U128(DEAD_SHARES),
+            memo: Some("dead shares"),
+        }
+        .emit();
+    }
+
+    pub(crate) fn assert_not_busy(&self) {
+        assert!(!self.busy, "Vault: Another operation is in progress");
+    }
+}
+
+near_contract_standards::impl_fungible_token_core!(Vault, token, on_tokens_burned);
+near_contract_standards::impl_fungible_token_storage!(Vault, token, on_account_closed);
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Vault {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.metadata.get().unwrap()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+
+    const ONE_NTK: Balance = 1_000_000_000_000;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn token() -> AccountId {
+        "token.testnet".parse().unwrap()
+    }
+
+    fn staking() -> AccountId {
+        "staking.testnet".parse().unwrap()
+    }
+
+    fn new_vault() -> Vault {
+        Vault::new(accounts(1), token(), staking(), 12)
+    }
+
+    fn register(vault: &mut Vault, account_id: AccountId) {
+        let mut context = get_context(account_id);
+        context.attached_deposit(vault.storage_balance_bounds().min.0);
+        testing_env!(context.build());
+        vault.storage_deposit(None, None);
+    }
+
+    fn deposit(vault: &mut Vault, sender_id: AccountId, amount: Balance) -> U128 {
+        if vault.storage_balance_of(sender_id.clone()).is_none() {
+            register(vault, sender_id.clone());
+        }
+        testing_env!(get_context(token()).build());
+        match vault.ft_on_transfer(sender_id, U128(amount), "deposit".to_string()) {
+            PromiseOrValue::Value(refund) => refund,
+            _ => panic!("expected a value"),
+        }
+    }
+
+    fn harvest_synced(
+        vault: &mut Vault,
+        balance: Balance,
+        staked: Balance,
+        assets_before: Balance,
+    ) {
+        let context = get_context(accounts(0));
+        testing_env!(
+            context.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(format!("\"{}\"", balance).into_bytes()),
+                PromiseResult::Successful(
+                    format!("{{\"total_amount\":\"{}\",\"stakes\":[]}}", staked).into_bytes()
+                ),
+            ],
+        );
+        vault.on_harvest_synced(accounts(2), U128(assets_before));
+    }
+
+    // (receiver, method, args) of the function calls made so far
+    fn function_calls() -> Vec<(AccountId, String, String)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver_id = receipt.receiver_id;
+                receipt
+                    .actions
+                    .into_iter()
+                    .filter_map(move |action| match action {
+                        VmAction::FunctionCall {
+                            function_name,
+                            args,
+                            ..
+                        } => Some((
+                            receiver_id.clone(),
+                            function_name,
+                            String::from_utf8(args).unwrap(),
+                        )),
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_new() {
+        testing_env!(get_context(accounts(1)).build());
+        let vault = new_vault();
+        let info = vault.get_vault_info();
+        assert_eq!(info.total_shares.0, 0);
+        assert_eq!(info.share_price.0, ONE_NTK);
+        assert!(!info.busy);
+    }
+
+    #[test]
+    fn test_deposit_mints_shares_at_share_price() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        assert_eq!(deposit(&mut vault, accounts(2), 100 * ONE_NTK).0, 0);
+        assert_eq!(
+            vault.ft_balance_of(accounts(2)).0,
+            100 * ONE_NTK - DEAD_SHARES
+        );
+        assert_eq!(vault.ft_total_supply().0, 100 * ONE_NTK);
+
+        // 10 NTK of rewards were compounded
+        vault.busy = true;
+        harvest_synced(&mut vault, 110 * ONE_NTK, 0, 100 * ONE_NTK);
+        vault.busy = false;
+        deposit(&mut vault, accounts(3), 55 * ONE_NTK);
+        let shares = vault.ft_balance_of(accounts(3)).0;
+        assert!(shares < 55 * ONE_NTK);
+        assert_eq!(vault.preview_withdraw(U128(shares)).0, 55 * ONE_NTK - 1);
+    }
+
+    #[test]
+    fn test_harvest_pays_bounty_and_raises_share_price() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        vault.busy = true;
+        harvest_synced(&mut vault, 110 * ONE_NTK, 0, 100 * ONE_NTK);
+
+        let bounty = 10 * ONE_NTK * DEFAULT_BOUNTY_BPS as u128 / BPS_DENOMINATOR;
+        let info = vault.get_vault_info();
+        assert_eq!(info.idle.0, 110 * ONE_NTK - bounty);
+        assert_eq!(info.share_price.0, (110 * ONE_NTK - bounty) / 100);
+        // still busy until the restake is synced
+        assert!(info.busy);
+    }
+
+    #[test]
+    fn test_deposit_near_the_token_supply() {
+        // 1M tokens of 18 decimals, the supply of NTK is 10^27
+        const MILLION_TOKENS: Balance = 1_000_000 * 1_000_000_000_000_000_000;
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), MILLION_TOKENS);
+        assert_eq!(vault.ft_total_supply().0, MILLION_TOKENS);
+
+        // the product of the amount and the supply is far above u128::MAX
+        assert_eq!(deposit(&mut vault, accounts(3), MILLION_TOKENS).0, 0);
+        assert_eq!(vault.ft_balance_of(accounts(3)).0, MILLION_TOKENS);
+        assert_eq!(
+            vault.preview_withdraw(U128(MILLION_TOKENS)).0,
+            MILLION_TOKENS
+        );
+        assert_eq!(vault.get_vault_info().total_assets.0, 2 * MILLION_TOKENS);
+    }
+
+    #[test]
+    fn test_deposit_claims_rewards_before_minting() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        vault.apply_sync(0, 100 * ONE_NTK);
+
+        // 10 NTK of rewards are pending, the deposit claims them first
+        register(&mut vault, accounts(3));
+        testing_env!(get_context(token()).build());
+        assert!(matches!(
+            vault.ft_on_transfer(accounts(3), U128(55 * ONE_NTK), "deposit".to_string()),
+            PromiseOrValue::Promise(_)
+        ));
+        assert!(vault.get_vault_info().busy);
+        let calls = function_calls();
+        assert_eq!(calls[0].1, "withdraw_stake");
+        assert_eq!(calls[0].2, r#"{"amount":"0","stake_index":0}"#);
+        assert!(function_calls()
+            .iter()
+            .all(|(_, method, _)| method != "ft_transfer"));
+
+        // the balance holds the rewards and the deposit
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(format!("\"{}\"", 65 * ONE_NTK).into_bytes()),
+                PromiseResult::Successful(
+                    format!("{{\"total_amount\":\"{}\",\"stakes\":[]}}", 100 * ONE_NTK)
+                        .into_bytes()
+                ),
+            ],
+        );
+        let refund =
+            vault.on_deposit_synced(accounts(3), U128(55 * ONE_NTK), "deposit".to_string());
+        assert_eq!(refund.0, 0);
+        let shares = vault.ft_balance_of(accounts(3)).0;
+        assert_eq!(shares, 50 * ONE_NTK);
+        assert_eq!(vault.preview_withdraw(U128(shares)).0, 55 * ONE_NTK);
+        let info = vault.get_vault_info();
+        assert_eq!(info.idle.0, 65 * ONE_NTK);
+        assert!(!info.busy);
+    }
+
+    #[test]
+    fn test_deposit_of_unregistered_sender_refunded() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        testing_env!(get_context(token()).build());
+        let refund = match vault.ft_on_transfer(accounts(3), U128(ONE_NTK), "deposit".to_string()) {
+            PromiseOrValue::Value(refund) => refund,
+            _ => panic!("expected a value"),
+        };
+        assert_eq!(refund.0, ONE_NTK);
+        assert!(vault.storage_balance_of(accounts(3)).is_none());
+        assert_eq!(vault.get_vault_info().idle.0, 100 * ONE_NTK);
+    }
+
+    #[test]
+    fn test_failed_bounty_goes_back_to_idle() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        vault.busy = true;
+        harvest_synced(&mut vault, 110 * ONE_NTK, 0, 100 * ONE_NTK);
+        let bounty = 10 * ONE_NTK * DEFAULT_BOUNTY_BPS as u128 / BPS_DENOMINATOR;
+        assert!(function_calls()
+            .iter()
+            .any(|(_, method, _)| method == "on_bounty_paid"));
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert_eq!(vault.on_bounty_paid(accounts(2), U128(bounty)).0, 0);
+        assert_eq!(vault.get_vault_info().idle.0, 110 * ONE_NTK);
+    }
+
+    #[test]
+    fn test_deposit_refunded_while_busy() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        vault.busy = true;
+        assert_eq!(deposit(&mut vault, accounts(2), ONE_NTK).0, ONE_NTK);
+        assert_eq!(vault.ft_total_supply().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Vault: Only the staked token can be deposited")]
+    fn test_deposit_other_token() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        vault.ft_on_transfer(accounts(2), U128(ONE_NTK), "deposit".to_string());
+    }
+
+    #[test]
+    fn test_withdraw_from_idle_burns_shares() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        testing_env!(get_context(accounts(2)).build());
+        vault.withdraw(U128(40 * ONE_NTK));
+        assert_eq!(
+            vault.ft_balance_of(accounts(2)).0,
+            60 * ONE_NTK - DEAD_SHARES
+        );
+        assert_eq!(vault.get_vault_info().idle.0, 60 * ONE_NTK);
+        assert!(!vault.get_vault_info().busy);
+    }
+
+    #[test]
+    fn test_failed_payout_restores_shares() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        testing_env!(get_context(accounts(2)).build());
+        vault.withdraw(U128(40 * ONE_NTK));
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        vault.on_withdraw_paid(accounts(2), U128(40 * ONE_NTK), U128(40 * ONE_NTK));
+        assert_eq!(
+            vault.ft_balance_of(accounts(2)).0,
+            100 * ONE_NTK - DEAD_SHARES
+        );
+        assert_eq!(vault.get_vault_info().idle.0, 100 * ONE_NTK);
+    }
+
+    #[test]
+    fn test_first_deposit_mints_dead_shares() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        assert_eq!(deposit(&mut vault, accounts(2), DEAD_SHARES).0, DEAD_SHARES);
+        assert_eq!(vault.ft_total_supply().0, 0);
+
+        // 1 share held by the attacker, then 1000 NTK donated to the vault
+        deposit(&mut vault, accounts(2), DEAD_SHARES + 1);
+        assert_eq!(vault.ft_balance_of(accounts(2)).0, 1);
+        vault.busy = true;
+        harvest_synced(&mut vault, 1_000 * ONE_NTK, 0, DEAD_SHARES + 1);
+        vault.busy = false;
+
+        // the victim still gets shares worth its deposit, the donation mostly went to the dead shares
+        let idle = vault.get_vault_info().idle.0;
+        deposit(&mut vault, accounts(3), 100 * ONE_NTK);
+        let shares = vault.ft_balance_of(accounts(3)).0;
+        assert!(shares > 0);
+        assert!(vault.preview_withdraw(U128(shares)).0 > 99 * ONE_NTK);
+        assert!(vault.preview_withdraw(U128(1)).0 < idle / DEAD_SHARES + 1);
+    }
+
+    #[test]
+    fn test_failed_has_stake_aborts_sync() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        vault.busy = true;
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(format!("\"{}\"", 110 * ONE_NTK).into_bytes()),
+                PromiseResult::Failed,
+            ],
+        );
+        assert!(vault.read_sync().is_none());
+        vault.on_synced();
+        let info = vault.get_vault_info();
+        assert_eq!(info.idle.0, 100 * ONE_NTK);
+        assert!(!info.busy);
+    }
+
+    #[test]
+    fn test_gas_covers_staking_payout() {
+        const MAX_TRANSACTION_GAS: u64 = 300_000_000_000_000;
+        let sync = FT_BALANCE_OF_GAS.0 + HAS_STAKE_GAS.0;
+        // (gas a call attaches to what follows it, gas it is given)
+        let budgets = [
+            (
+                staking_contract::SETTLEMENT_PAYOUT_GAS.0,
+                STAKING_PAYOUT_GAS.0,
+            ),
+            (sync + ON_HARVEST_SYNCED_GAS.0, ON_HARVEST_WITHDRAWN_GAS.0),
+            (
+                FT_TRANSFER_GAS.0
+                    + ON_BOUNTY_PAID_GAS.0
+                    + FT_TRANSFER_CALL_GAS.0
+                    + ON_COMPOUNDED_GAS.0,
+                ON_HARVEST_SYNCED_GAS.0,
+            ),
+            (sync + ON_DEPOSIT_SYNCED_GAS.0, ON_DEPOSIT_HARVESTED_GAS.0),
+            (sync + ON_WITHDRAW_SYNCED_GAS.0, ON_WITHDRAW_UNSTAKED_GAS.0),
+            (
+                WITHDRAW_STAKE_GAS.0 + ON_HARVEST_WITHDRAWN_GAS.0,
+                MAX_TRANSACTION_GAS,
+            ),
+            (
+                WITHDRAW_STAKE_GAS.0 + ON_WITHDRAW_UNSTAKED_GAS.0,
+                MAX_TRANSACTION_GAS,
+            ),
+        ];
+        for (needed, given) in budgets {
+            assert!(needed <= given, "{} > {}", needed, given);
+        }
+    }
+
+    #[test]
+    fn test_harvest_claims_rewards_and_adds_them_to_the_stake() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        vault.apply_sync(0, 100 * ONE_NTK);
+        testing_env!(get_context(accounts(2)).build());
+        vault.harvest_and_compound();
+        let calls = function_calls();
+        assert_eq!(calls[0].0, staking());
+        assert_eq!(calls[0].1, "withdraw_stake");
+        assert_eq!(calls[0].2, r#"{"amount":"0","stake_index":0}"#);
+
+        // 10 NTK of rewards were claimed
+        harvest_synced(&mut vault, 10 * ONE_NTK, 100 * ONE_NTK, 100 * ONE_NTK);
+        let bounty = 10 * ONE_NTK * DEFAULT_BOUNTY_BPS as u128 / BPS_DENOMINATOR;
+        let restake = function_calls()
+            .into_iter()
+            .find(|(_, method, _)| method == "ft_transfer_call")
+            .unwrap();
+        let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_str(&restake.2).unwrap();
+        assert_eq!(args["amount"], (10 * ONE_NTK - bounty).to_string());
+        assert_eq!(args["msg"], r#"{"action": "staking", "stake_index": 0}"#);
+        assert_eq!(vault.get_vault_info().staked_principal.0, 100 * ONE_NTK);
+    }
+
+    fn compound_previewed(vault: &Vault, result: PromiseResult) -> CompoundPreview {
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        vault.on_compound_previewed()
+    }
+
+    #[test]
+    fn test_preview_compound() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut vault = new_vault();
+        deposit(&mut vault, accounts(2), 100 * ONE_NTK);
+        // nothing is staked yet, only the idle tokens would be
+        testing_env!(get_context(accounts(3)).build());
+        match vault.preview_compound() {
+            PromiseOrValue::Value(preview) => {
+                assert_eq!(preview.harvested.0, 0);
+                assert_eq!(preview.staked.0, 100 * ONE_NTK);
+            }
+            _ => panic!("expected a value"),
+        }
+
+        vault.apply_sync(5 * ONE_NTK, 100 * ONE_NTK);
+        testing_env!(get_context(accounts(3)).build());
+        assert!(matches!(
+            vault.preview_compound(),
+            PromiseOrValue::Promise(_)
+        ));
+        let calls = function_calls();
+        assert_eq!(calls[0].0, staking());
+        assert_eq!(calls[0].1, "preview_claim");
+        assert_eq!(
+            calls[0].2,
+            format!(r#"{{"account_id":"{}","instant":false}}"#, accounts(0))
+        );
+
+        // 10 NTK of rewards, 1 NTK of them not paid because the token is underfunded
+        let claim = near_sdk::serde_json::json!({
+            "payouts": [{"token_id": token(), "principal_out": "0", "reward_out": (10 * ONE_NTK).to_string(),
+                "fees": "0", "penalty": "0", "vested": "0", "shortfall": ONE_NTK.to_string()}],
+            "stakes": [],
+            "error": null,
+        });
+        let preview = compound_previewed(
+            &vault,
+            PromiseResult::Successful(claim.to_string().into_bytes()),
+        );
+        let bounty = 9 * ONE_NTK * DEFAULT_BOUNTY_BPS as u128 / BPS_DENOMINATOR;
+        assert!(preview.error.is_none());
+        assert_eq!(preview.harvested.0, 9 * ONE_NTK);
+        assert_eq!(preview.bounty.0, bounty);
+        assert_eq!(preview.staked.0, 14 * ONE_NTK - bounty);
+
+        let failed = near_sdk::serde_json::json!({
+            "payouts": [], "stakes": [], "error": "Staking: Account has no stakes"
+        });
+        let preview = compound_previewed(
+            &vault,
+            PromiseResult::Successful(failed.to_string().into_bytes()),
+        );
+        assert_eq!(preview.error.unwrap(), "Staking: Account has no stakes");
+        let preview = compound_previewed(&vault, PromiseResult::Failed);
+        assert!(preview.error.is_some());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::*;
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
+use near_sdk::{Promise, PromiseResult};
+
+impl Vault {
+    fn burn_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        self.token.internal_withdraw(account_id, shares);
+        FtBurn {
+            owner_id: account_id,
+            amount: &U128(shares),
+            memo: Some("withdraw"),
+        }
+        .emit();
+    }
+
+    fn restore_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        self.token.internal_deposit(account_id, shares);
+        FtMint {
+            owner_id: account_id,
+            amount: &U128(shares),
+            memo: Some("withdraw failed"),
+        }
+        .emit();
+    }
+
+    fn pay(&self, account_id: AccountId, shares: Balance, amount: Balance) -> Promise {
+        ext_ft::ext(self.token_id.clone())
+            .with_static_gas(FT_TRANSFER_GAS)
+            .with_attached_deposit(DEPOSIT_ONE_YOCTO)
+            .ft_transfer(
+                account_id.clone(),
+                U128(amount),
+                Some("vault withdraw".to_string()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_WITHDRAW_PAID_GAS)
+                    .on_withdraw_paid(account_id, U128(shares), U128(amount)),
+            )
+    }
+}
+
+#[near_bindgen]
+impl Vault {
+    /**
+     * @notice
+     * withdraw burns shares and pays their value, from idle tokens when there are enough
+     * and otherwise by unstaking the rest from the vault's stake
+     */
+    pub fn withdraw(&mut self, shares: U128) -> Promise {
+        self.assert_not_busy();
+        let account_id = env::predecessor_account_id();
+        let amount = self.amount_for_shares(shares.0);
+        assert!(amount > 0, "Vault: Nothing to withdraw");
+        self.burn_shares(&account_id, shares.0);
+        if self.idle >= amount {
+            self.idle -= amount;
+            return self.pay(account_id, shares.0, amount);
+        }
+        self.busy = true;
+        let needed = (amount - self.idle).min(self.staked_principal);
+        ext_staking::ext(self.staking_id.clone())
+            .with_static_gas(WITHDRAW_STAKE_GAS)
+            .withdraw_stake(U128(needed), 0)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_WITHDRAW_UNSTAKED_GAS)
+                    .on_withdraw_unstaked(account_id, shares, U128(amount)),
+            )
+    }
+
+    #[private]
+    pub fn on_withdraw_unstaked(
+        &mut self,
+        account_id: AccountId,
+        shares: U128,
+        amount: U128,
+    ) -> PromiseOrValue<U128> {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            log_str("Vault: Unstaking failed, shares are restored");
+            self.restore_shares(&account_id, shares.0);
+            self.busy = false;
+            return PromiseOrValue::Value(U128(0));
+        }
+        PromiseOrValue::Promise(
+            self.sync().then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_WITHDRAW_SYNCED_GAS)
+                    .on_withdraw_synced(account_id, shares, amount),
+            ),
+        )
+    }
+
+    #[private]
+    pub fn on_withdraw_synced(
+        &mut self,
+        account_id: AccountId,
+        shares: U128,
+        amount: U128,
+    ) -> PromiseOrValue<U128> {
+        self.busy = false;
+        let (balance, staked) = match self.read_sync() {
+            Some(result) => result,
+            None => {
+                log_str("Vault: Cannot read the vault balance, shares are restored");
+                self.restore_shares(&account_id, shares.0);
+                return PromiseOrValue::Value(U128(0));
+            }
+        };
+        // a withdraw fee of the staking contract can leave a little less than the value of the shares
+        let payout = amount.0.min(balance);
+        self.apply_sync(balance - payout, staked);
+        PromiseOrValue::Promise(self.pay(account_id, shares.0, payout))
+    }
+
+    #[private]
+    pub fn on_withdraw_paid(&mut self, account_id: AccountId, shares: U128, amount: U128) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => amount,
+            _ => {
+                log_str("Vault: Transfer failed, shares are restored");
+                self.idle += amount.0;
+                self.restore_shares(&account_id, shares.0);
+                U128(0)
+            }
+        }
+    }
+}